@@ -23,6 +23,7 @@ use core::{
     future::Future,
     pin::Pin,
     ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicU16, Ordering},
     task::{Context, Poll},
 };
 use event::Event;
@@ -112,8 +113,16 @@ pub struct VirtIOBlock<const N: usize> {
     pub sector_size: u32,
     /// 添加一个[`Event`]成员后，完美实现块设备驱动
     pub wake_ops: Event,
+    /// 中断处理函数读到的已用环位置
+    used_cursor: AtomicU16,
+    /// 请求是否已经完成，下标为请求描述符链的头部
+    ///
+    /// 中断处理函数设置，发出请求的任务看到之后清除，再回收描述符
+    completed: [AtomicBool; VIRT_QUEUE_SIZE],
 }
 
+const NOT_COMPLETED: AtomicBool = AtomicBool::new(false);
+
 // todo: 尽量让 VirtIOBlock 天然 Send 和 Sync
 unsafe impl<const N: usize> Send for VirtIOBlock<N> {}
 unsafe impl<const N: usize> Sync for VirtIOBlock<N> {}
@@ -217,6 +226,8 @@ impl<const N: usize> VirtIOBlock<N> {
             capacity: config.capacity.read() as usize,
             sector_size: config.sector_size.read(),
             wake_ops: Event::new(),
+            used_cursor: AtomicU16::new(0),
+            completed: [NOT_COMPLETED; VIRT_QUEUE_SIZE],
         })
     }
     /// 同步方式创建异步块设备驱动
@@ -273,6 +284,8 @@ impl<const N: usize> VirtIOBlock<N> {
             capacity: config.capacity.read() as usize,
             sector_size: config.sector_size.read(),
             wake_ops: Event::new(),
+            used_cursor: AtomicU16::new(0),
+            completed: [NOT_COMPLETED; VIRT_QUEUE_SIZE],
         })
     }
     /// 通知设备 virtio 外部中断已经处理完成
@@ -441,8 +454,6 @@ impl<const N: usize> VirtIOBlock<N> {
     /// }
     /// ```
     pub async fn read_sector_event(&self, sector_id: usize, buf: &mut [u8]) -> Result<()> {
-        // 开始监听
-        let listener = self.wake_ops.listen();
         // 缓冲区大小必须等于扇区大小
         if buf.len() != self.sector_size as usize {
             panic!(
@@ -455,19 +466,18 @@ impl<const N: usize> VirtIOBlock<N> {
             reserved: 0,
             sector: sector_id as u64,
         };
-        let mut inner = self.lock_inner.lock();
         let mut resp = BlockResp::default();
+        let head = {
+            let mut inner = self.lock_inner.lock();
+            let (h, q) = inner.header_and_queue_mut();
+            let head = q
+                .add_buf(&[req.as_buf()], &[buf, resp.as_buf_mut()])
+                .expect("[virtio] virtual queue add buf error");
+            h.notify(0);
+            head
+        };
 
-        let (h, q) = inner.header_and_queue_mut();
-
-        q.add_buf(&[req.as_buf()], &[buf, resp.as_buf_mut()])
-            .expect("[virtio] virtual queue add buf error");
-
-        h.notify(0);
-
-        listener.await;
-
-        q.pop_used()?;
+        self.wait_for_completion(head).await;
 
         match resp.status {
             BlockRespStatus::Ok => Ok(()),
@@ -490,8 +500,6 @@ impl<const N: usize> VirtIOBlock<N> {
     /// }
     /// ```
     pub async fn write_serctor_event(&self, sector_id: usize, buf: &[u8]) -> Result<()> {
-        // 开始监听
-        let listener = self.wake_ops.listen();
         // 缓冲区大小必须等于扇区大小
         if buf.len() != self.sector_size as usize {
             panic!(
@@ -504,24 +512,40 @@ impl<const N: usize> VirtIOBlock<N> {
             reserved: 0,
             sector: sector_id as u64,
         };
-        let mut inner = self.lock_inner.lock();
         let mut resp = BlockResp::default();
+        let head = {
+            let mut inner = self.lock_inner.lock();
+            let (h, q) = inner.header_and_queue_mut();
+            let head = q
+                .add_buf(&[req.as_buf(), buf], &[resp.as_buf_mut()])
+                .expect("[virtio] virtual queue add buf error");
+            h.notify(0);
+            head
+        };
 
-        let (h, q) = inner.header_and_queue_mut();
-
-        q.add_buf(&[req.as_buf(), buf], &[resp.as_buf_mut()])
-            .expect("[virtio] virtual queue add buf error");
-
-        h.notify(0);
-
-        listener.await;
+        self.wait_for_completion(head).await;
 
-        q.pop_used()?;
         match resp.status {
             BlockRespStatus::Ok => Ok(()),
             _ => Err(VirtIOError::IOError),
         }
     }
+    /// 等待描述符链头部为`head`的请求完成，然后回收它的描述符
+    ///
+    /// 先监听再检查完成标志，检查之后才完成的请求一定会唤醒这里的监听器。
+    /// 等待的时候不拿锁，其它任务可以同时发出请求
+    async fn wait_for_completion(&self, head: u16) {
+        let completed = &self.completed[head as usize];
+        loop {
+            let listener = self.wake_ops.listen();
+            if completed.load(Ordering::Acquire) {
+                break;
+            }
+            listener.await;
+        }
+        completed.store(false, Ordering::Relaxed);
+        self.lock_inner.lock().queue.recycle_descriptors(head);
+    }
     /// 异步方式读取一个块
     ///
     /// 飓风内核通过这个接口实现块设备的异步读取
@@ -659,36 +683,40 @@ impl<const N: usize> VirtIOBlock<N> {
             _ => Err(VirtIOError::IOError),
         }
     }
-    /// 处理 virtio 外部中断
+    /// 处理 virtio 外部中断，返回这次完成的请求数
     ///
     /// 在飓风内核的外部中断处理函数里面被调用
     ///
-    /// todo: 仔细考虑这里的操作原子性
+    /// 一次中断可能对应多个完成的请求，也可能一个都没有。这里读出设备新放进已用环的所有元素，
+    /// 把对应的请求标记为完成，再唤醒所有等待的任务，任务醒来之后检查自己的请求有没有完成。
+    /// 中断处理函数不拿锁，也不回收描述符，描述符由发出请求的任务回收
     ///
     /// # Example:
     ///
     /// ```
     /// # static VIRTIO_BLOCK: VirtIOBlock;
     /// extern "C" fn external_interrupt() {
-    ///     let intr_ret = VIRTIO_BLOCK.handle_interrupt().unwrap();  
+    ///     let completed = VIRTIO_BLOCK.handle_interrupt();
     /// }
     /// ```
-    pub unsafe fn handle_interrupt(&self) -> Result<InterruptRet> {
-        // 这里使用获取不加锁的 inner
+    pub unsafe fn handle_interrupt(&self) -> usize {
+        // 这里使用获取不加锁的 inner，只读已用环
         let q = self.unlock_queue.as_ref();
-        if !q.can_pop() {
-            return Err(VirtIOError::IOError);
+        // 外部中断由 PLIC 分发给一个硬件线程处理，不会有两个中断处理函数同时修改游标
+        let end = q.used_index();
+        let mut cursor = self.used_cursor.load(Ordering::Relaxed);
+        let mut count = 0;
+        while cursor != end {
+            let (head, _len) = q.used_element(cursor);
+            self.completed[head as usize].store(true, Ordering::Release);
+            cursor = cursor.wrapping_add(1);
+            count += 1;
         }
-        let (idx, _len) = q.next_used()?;
-        let desc = q.descriptor(idx as usize);
-        let req_va = virtio_phys_to_virt(desc.paddr.read() as usize);
-        let req = &*(req_va as *const BlockReq);
-        let ret = match req.type_ {
-            BlockReqType::In => InterruptRet::Read(req.sector),
-            BlockReqType::Out => InterruptRet::Write(req.sector),
-            _ => InterruptRet::Other,
-        };
-        Ok(ret)
+        self.used_cursor.store(cursor, Ordering::Relaxed);
+        if count != 0 {
+            self.wake_ops.notify(usize::MAX);
+        }
+        count
     }
 }
 
@@ -813,19 +841,3 @@ impl Default for BlockResp {
 
 unsafe impl AsBuf for BlockReq {}
 unsafe impl AsBuf for BlockResp {}
-
-#[derive(Debug)]
-/// 中断响应返回值
-pub enum InterruptRet {
-    /// 读请求完成的块
-    Read(u64),
-    /// 写请求完成的块
-    Write(u64),
-    /// 其他
-    Other,
-}
-
-extern "C" {
-    /// 内核提供的物理地址到虚拟地址的转换函数
-    fn virtio_phys_to_virt(paddr: usize) -> usize;
-}
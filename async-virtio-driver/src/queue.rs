@@ -246,7 +246,7 @@ impl VirtQueue {
 
     /// 回收描述符
     /// 该方法将会把需要回收的描述符链放到空闲描述符链的头部
-    fn recycle_descriptors(&mut self, mut head: u16) {
+    pub fn recycle_descriptors(&mut self, mut head: u16) {
         let origin_desc_head = self.free_desc_head;
         self.free_desc_head = head;
         let descriptor_table = unsafe { self.descriptor_table.as_mut() };
@@ -284,6 +284,26 @@ impl VirtQueue {
         Ok((index, len))
     }
 
+    /// 已用环的索引值，即设备一共放进已用环的元素数目
+    pub fn used_index(&self) -> u16 {
+        let used_ring = unsafe { self.used_ring.as_ref() };
+        let index = used_ring.idx.read();
+        // read barrier，之后读到的已用环元素和请求的回应都是设备写好的
+        fence(Ordering::SeqCst);
+        index
+    }
+
+    /// 已用环中索引值为`index`的元素，返回请求描述符链的头部和设备写入的长度
+    ///
+    /// 不弹出元素，也不回收描述符
+    pub fn used_element(&self, index: u16) -> (u16, u32) {
+        let used_ring = unsafe { self.used_ring.as_ref() };
+        let slot = index & (self.queue_size - 1);
+        let head = used_ring.ring[slot as usize].id.read() as u16;
+        let len = used_ring.ring[slot as usize].len.read();
+        (head, len)
+    }
+
     /// 从已用环中取出下一个 token，但不弹出
     pub fn next_used(&self) -> Result<(u16, u32)> {
         if !self.can_pop() {
@@ -0,0 +1,282 @@
+//! 等待任务的结果
+//!
+//! 生成任务时用[`Joinable`]包装任务的future，任务完成时把结果放到和[`JoinHandle`]共享的状态里。
+//! 内核和用户态的运行时共用这个库，任务怎么打包、怎么从共享调度器中删除由运行时决定，见[`Abort`]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::sync::{Arc, Weak};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex;
+
+/// 等待任务结果时发生的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// 任务在完成之前被[`JoinHandle::abort`]取消
+    Aborted,
+}
+
+/// 运行时的任务表示，[`JoinHandle::abort`]设置取消标记之后通知运行时
+pub trait Abort {
+    /// 任务已经被标记为取消，还没有完成
+    ///
+    /// 运行时把任务从共享调度器中删除，或者唤醒它，让[`Joinable`]下一次被轮询时完成、由执行器删除。
+    /// 还有读写在使用future里的缓冲区时不能丢弃future
+    fn abort(self: Arc<Self>);
+}
+
+/// 任务和[`JoinHandle`]共享的状态
+struct JoinState<T> {
+    /// 任务的结果，任务完成之后、被取走之前为Some
+    output: Option<T>,
+    /// 任务是否已经完成
+    finished: bool,
+    /// 任务是否已经被取消
+    aborted: bool,
+    /// 等待结果的任务
+    waker: Option<Waker>,
+}
+
+impl<T> JoinState<T> {
+    /// 任务完成，保存结果；已经取消的任务丢弃结果
+    fn complete(&mut self, output: T) -> Option<Waker> {
+        self.finished = true;
+        if !self.aborted {
+            self.output = Some(output);
+        }
+        self.waker.take()
+    }
+}
+
+/// 包装任务的future，完成之后把结果交给[`JoinHandle`]
+///
+/// 任务被取消之后，下一次被轮询时不再轮询原来的future，直接完成
+pub struct Joinable<F: Future> {
+    future: F,
+    state: Arc<Mutex<JoinState<F::Output>>>,
+}
+
+impl<F: Future> Future for Joinable<F> {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // note(unsafe): `future`不会被移出`Joinable`
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.state.lock().aborted {
+            return Poll::Ready(());
+        }
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        match future.poll(cx) {
+            Poll::Ready(output) => {
+                let waker = this.state.lock().complete(output);
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// 用`new_task`把`future`打包成任务，返回任务和它的句柄
+pub fn joinable<F: Future, R: Abort>(
+    future: F,
+    new_task: impl FnOnce(Joinable<F>) -> Arc<R>,
+) -> (Arc<R>, JoinHandle<F::Output, R>) {
+    let state = Arc::new(Mutex::new(JoinState {
+        output: None,
+        finished: false,
+        aborted: false,
+        waker: None,
+    }));
+    let task = new_task(Joinable {
+        future,
+        state: state.clone(),
+    });
+    let handle = JoinHandle {
+        state,
+        task: Arc::downgrade(&task),
+    };
+    (task, handle)
+}
+
+/// 任务的句柄，等待它得到任务的结果
+///
+/// 丢弃句柄不影响任务运行，任务的结果随之丢弃
+pub struct JoinHandle<T, R: Abort> {
+    state: Arc<Mutex<JoinState<T>>>,
+    task: Weak<R>,
+}
+
+impl<T, R: Abort> JoinHandle<T, R> {
+    /// 任务是否已经完成，取消的任务也算完成
+    pub fn is_finished(&self) -> bool {
+        let state = self.state.lock();
+        state.finished || state.aborted
+    }
+
+    /// 取消任务，等待这个句柄会马上得到[`JoinError::Aborted`]
+    ///
+    /// 已经完成的任务不受影响。设置取消标记之后由运行时通过`delete_task`把任务从共享调度器中删除，
+    /// 见[`Abort`]；被取消的任务不会再轮询原来的future
+    pub fn abort(&self) {
+        let waker = {
+            let mut state = self.state.lock();
+            if state.finished || state.aborted {
+                return;
+            }
+            state.aborted = true;
+            state.waker.take()
+        };
+        if let Some(task) = self.task.upgrade() {
+            task.abort();
+        }
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// 取出已经完成的任务的结果，任务没有完成、被取消或者结果已经被取走时返回None
+    pub fn take_output(&self) -> Option<T> {
+        self.state.lock().output.take()
+    }
+}
+
+impl<T, R: Abort> Future for JoinHandle<T, R> {
+    type Output = Result<T, JoinError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock();
+        if let Some(output) = state.output.take() {
+            return Poll::Ready(Ok(output));
+        }
+        if state.aborted {
+            return Poll::Ready(Err(JoinError::Aborted));
+        }
+        assert!(!state.finished, "poll JoinHandle after completion");
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{joinable, Abort, JoinError, Joinable};
+    use alloc::sync::Arc;
+    use core::{
+        future::Future,
+        pin::Pin,
+        sync::atomic::{AtomicUsize, Ordering},
+        task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    };
+    use std::boxed::Box;
+
+    /// 只记录取消次数的任务
+    struct TestTask {
+        future: spin::Mutex<Pin<Box<dyn Future<Output = ()> + Send>>>,
+        aborts: AtomicUsize,
+    }
+
+    impl Abort for TestTask {
+        fn abort(self: Arc<Self>) {
+            self.aborts.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn new_task<F: Future + Send + 'static>(future: Joinable<F>) -> Arc<TestTask>
+    where
+        F::Output: Send,
+    {
+        Arc::new(TestTask {
+            future: spin::Mutex::new(Box::pin(future)),
+            aborts: AtomicUsize::new(0),
+        })
+    }
+
+    /// 唤醒时把`count`加一的唤醒器
+    fn counting_waker(count: &'static AtomicUsize) -> Waker {
+        fn clone(data: *const ()) -> RawWaker {
+            RawWaker::new(data, &VTABLE)
+        }
+        fn wake(data: *const ()) {
+            unsafe { &*(data as *const AtomicUsize) }.fetch_add(1, Ordering::Relaxed);
+        }
+        fn drop(_data: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+        unsafe { Waker::from_raw(RawWaker::new(count as *const _ as *const (), &VTABLE)) }
+    }
+
+    /// 永远不会完成，记录被轮询的次数
+    struct CountPolls(&'static AtomicUsize);
+
+    impl Future for CountPolls {
+        type Output = ();
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Poll::Pending
+        }
+    }
+
+    fn poll_task(task: &TestTask, waker: &Waker) -> Poll<()> {
+        task.future
+            .lock()
+            .as_mut()
+            .poll(&mut Context::from_waker(waker))
+    }
+
+    #[test]
+    fn output_wakes_joiner() {
+        static TASK: AtomicUsize = AtomicUsize::new(0);
+        static JOINER: AtomicUsize = AtomicUsize::new(0);
+        let (task, mut handle) = joinable(async { 42 }, new_task);
+        let joiner = counting_waker(&JOINER);
+        let mut cx = Context::from_waker(&joiner);
+        assert_eq!(Pin::new(&mut handle).poll(&mut cx), Poll::Pending);
+        assert!(!handle.is_finished());
+        assert_eq!(poll_task(&task, &counting_waker(&TASK)), Poll::Ready(()));
+        assert_eq!(JOINER.load(Ordering::Relaxed), 1);
+        assert!(handle.is_finished());
+        assert_eq!(Pin::new(&mut handle).poll(&mut cx), Poll::Ready(Ok(42)));
+    }
+
+    #[test]
+    fn abort_skips_future() {
+        static TASK: AtomicUsize = AtomicUsize::new(0);
+        static JOINER: AtomicUsize = AtomicUsize::new(0);
+        static POLLS: AtomicUsize = AtomicUsize::new(0);
+        let (task, mut handle) = joinable(CountPolls(&POLLS), new_task);
+        let waker = counting_waker(&TASK);
+        assert_eq!(poll_task(&task, &waker), Poll::Pending);
+        let joiner = counting_waker(&JOINER);
+        let mut cx = Context::from_waker(&joiner);
+        assert_eq!(Pin::new(&mut handle).poll(&mut cx), Poll::Pending);
+        handle.abort();
+        handle.abort();
+        assert_eq!(task.aborts.load(Ordering::Relaxed), 1);
+        assert_eq!(JOINER.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            Pin::new(&mut handle).poll(&mut cx),
+            Poll::Ready(Err(JoinError::Aborted))
+        );
+        // 取消之后的轮询直接完成，不再轮询原来的future
+        assert_eq!(poll_task(&task, &waker), Poll::Ready(()));
+        assert_eq!(POLLS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn abort_after_finish_keeps_output() {
+        static TASK: AtomicUsize = AtomicUsize::new(0);
+        let (task, handle) = joinable(async { 7 }, new_task);
+        assert_eq!(poll_task(&task, &counting_waker(&TASK)), Poll::Ready(()));
+        handle.abort();
+        assert_eq!(task.aborts.load(Ordering::Relaxed), 0);
+        assert_eq!(handle.take_output(), Some(7));
+        assert_eq!(handle.take_output(), None);
+    }
+}
@@ -0,0 +1,31 @@
+//! 内核、用户运行时和共享调度器共用的常量
+//!
+//! 这些常量是共享调度器接口的一部分，三方分别编译，都从这里取得同一份定义。
+//! xtask和内核的构建脚本也依赖这个库，启动的硬件线程数量和启动栈的大小都由[`MAX_HARTS`]决定
+#![no_std]
+
+/// 共享调度器虚函数表的魔数，内核和用户加载时用它确认基地址上确实是共享调度器
+pub const SHARED_PAYLOAD_MAGIC: usize = 0x5348_4152_4544_5343; // "SHAREDSC"
+
+/// 共享调度器虚函数表的版本号
+///
+/// 虚函数表的布局或者任何一个函数的签名改变时都要增加版本号
+pub const SHARED_ABI_VERSION: usize = 10;
+
+/// 新任务的默认优先级
+///
+/// 数值越小优先级越高，比默认优先级高的几级留给对延迟敏感的任务，比如块设备读写完成后的唤醒任务
+pub const DEFAULT_PRIORITY: u8 = 4;
+
+/// 不绑定硬件线程的任务使用的硬件线程编号
+///
+/// 这样的任务可以在任何硬件线程上运行，空闲的硬件线程可以把它偷过去运行
+pub const ANY_HART: usize = usize::MAX;
+
+/// 最多支持的硬件线程数量
+///
+/// 共享调度器为每个硬件线程准备一个就绪队列，内核为每个硬件线程准备一个启动栈
+pub const MAX_HARTS: usize = 4;
+
+/// 共享调度器统计信息中最多列出的地址空间数量
+pub const STATS_MAX_ASIDS: usize = 16;
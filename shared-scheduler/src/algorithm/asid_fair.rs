@@ -0,0 +1,248 @@
+//! 按地址空间分时间片的公平调度器实现
+
+use super::{RingQueue, Scheduler, WithAddressSpace};
+use crate::mm::AddressSpaceId;
+use alloc::vec::Vec;
+
+/// 默认的时间片长度，即一个地址空间连续运行的任务数
+pub const DEFAULT_QUANTUM: usize = 8;
+
+/// 按地址空间分时间片的公平调度器
+///
+/// 每个地址空间有自己的先进先出队列，地址空间之间轮转。轮到一个地址空间时，
+/// 它最多连续运行时间片长度这么多个任务，队列空了或者时间片用完才轮到下一个有任务的地址空间。
+/// 同一个地址空间的任务成批运行，执行器不需要每运行一个任务就切换一次地址空间
+///
+/// 不考虑任务的优先级
+pub struct AsidFairScheduler<T> {
+    /// 各个地址空间的队列，按轮转的顺序排列
+    queues: Vec<(AddressSpaceId, RingQueue<T>)>,
+    /// 当前轮到的地址空间在`queues`中的下标
+    active: usize,
+    /// 当前地址空间剩下的时间片，为0时下一次轮转重新分配
+    budget: usize,
+    /// 时间片长度
+    quantum: usize,
+    current: Option<T>,
+    /// 上一个取出的任务所在的地址空间
+    last_asid: Option<AddressSpaceId>,
+    /// 相邻两次取出的任务属于不同地址空间的次数
+    switches: usize,
+}
+
+impl<T> AsidFairScheduler<T> {
+    /// 创建一个空的调度器，使用默认的时间片长度
+    pub const fn new() -> Self {
+        Self {
+            queues: Vec::new(),
+            active: 0,
+            budget: 0,
+            quantum: DEFAULT_QUANTUM,
+            current: None,
+            last_asid: None,
+            switches: 0,
+        }
+    }
+
+    /// 设置时间片长度，为0时使用默认值
+    ///
+    /// 当前地址空间剩下的时间片在下一次轮转时才按新的长度计算
+    pub fn set_quantum(&mut self, quantum: usize) {
+        self.quantum = if quantum == 0 {
+            DEFAULT_QUANTUM
+        } else {
+            quantum
+        };
+    }
+
+    /// 地址空间切换的次数
+    pub fn switches(&self) -> usize {
+        self.switches
+    }
+
+    /// 任务交给执行器运行，消耗它所在地址空间的一个时间片
+    ///
+    /// 时间片和切换次数只在这里计算。弹出任务不计算，
+    /// 因为弹出的可能是已经睡眠或者删除、从来没有运行过的过期任务
+    pub fn dispatch(&mut self, task: &T)
+    where
+        T: WithAddressSpace,
+    {
+        let asid = task.address_space_id();
+        if self.last_asid.map_or(false, |last| last != asid) {
+            self.switches += 1;
+        }
+        self.last_asid = Some(asid);
+        self.budget = self.budget.saturating_sub(1);
+        self.settle();
+    }
+
+    /// 轮到下一个队列不空的地址空间，并重新分配时间片
+    ///
+    /// 当前地址空间还有任务也有时间片的时候不轮转；其它地址空间都没有任务时，
+    /// 时间片用完的地址空间继续运行
+    fn settle(&mut self) {
+        let n = self.queues.len();
+        let active_ready = self
+            .queues
+            .get(self.active)
+            .map_or(false, |(_, queue)| !queue.is_empty());
+        if active_ready && self.budget > 0 {
+            return;
+        }
+        let next = (1..=n)
+            .map(|i| (self.active + i) % n)
+            .find(|&idx| !self.queues[idx].1.is_empty());
+        if let Some(idx) = next {
+            self.active = idx;
+            self.budget = self.quantum;
+        }
+    }
+}
+
+impl<T: Clone + PartialEq + WithAddressSpace> Scheduler<T> for AsidFairScheduler<T> {
+    type Priority = ();
+    /// 添加任务到它所在地址空间的队列尾部
+    fn add_task(&mut self, task: T) -> Option<T> {
+        let asid = task.address_space_id();
+        let ans = match self.queues.iter_mut().find(|(id, _)| *id == asid) {
+            Some((_, queue)) => queue.push_back(task),
+            None => {
+                // 第一次见到这个地址空间，排在轮转顺序的最后
+                if self.queues.try_reserve(1).is_err() {
+                    return Some(task);
+                }
+                let mut queue = RingQueue::new();
+                let ans = queue.push_back(task);
+                if ans.is_none() {
+                    self.queues.push((asid, queue));
+                }
+                ans
+            }
+        };
+        self.settle();
+        ans
+    }
+    /// 取出当前地址空间的下一个任务，不消耗时间片，见[`AsidFairScheduler::dispatch`]
+    fn next_task(&mut self) -> Option<T> {
+        let ans = self
+            .queues
+            .get_mut(self.active)
+            .and_then(|(_, queue)| queue.pop_front());
+        if ans.is_some() {
+            self.settle();
+        }
+        self.current = ans.clone();
+        ans
+    }
+    /// 拿出下一个任务的不可变引用，不弹出
+    fn peek_next_task(&self) -> Option<&T> {
+        self.queues
+            .get(self.active)
+            .and_then(|(_, queue)| queue.front())
+    }
+    /// 拿出下一个任务的可变引用，不弹出
+    fn peek_next_task_mut(&mut self) -> Option<&mut T> {
+        self.queues
+            .get_mut(self.active)
+            .and_then(|(_, queue)| queue.front_mut())
+    }
+    /// 获取当前任务
+    fn current_task(&self) -> Option<T> {
+        self.current.clone()
+    }
+    /// 移除一个特定的任务
+    fn remove_task(&mut self, task: &T) {
+        let asid = task.address_space_id();
+        if let Some((_, queue)) = self.queues.iter_mut().find(|(id, _)| *id == asid) {
+            queue.take(task);
+        }
+        self.settle();
+    }
+    /// 这个调度器不考虑优先级
+    fn set_priority(&mut self, _task: T, _prio: ()) {}
+    /// 所有地址空间队列中的任务总数
+    fn queue_len(&self) -> Option<usize> {
+        Some(self.queues.iter().map(|(_, queue)| queue.len()).sum())
+    }
+    /// 在每个地址空间的队列中只保留满足条件的任务
+    fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        for (_, queue) in self.queues.iter_mut() {
+            queue.retain(&mut f);
+        }
+        self.settle();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsidFairScheduler;
+    use crate::algorithm::{Scheduler, WithAddressSpace};
+    use crate::mm::AddressSpaceId;
+    use std::vec::Vec;
+
+    /// (地址空间编号, 任务编号)
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Item(u16, usize);
+
+    impl WithAddressSpace for Item {
+        fn address_space_id(&self) -> AddressSpaceId {
+            AddressSpaceId::from_raw(self.0)
+        }
+    }
+
+    /// 像共享调度器一样运行：先看头部，交给执行器，运行结束后再移除
+    fn run(s: &mut AsidFairScheduler<Item>, count: usize) -> Vec<Item> {
+        let mut order = Vec::new();
+        for _ in 0..count {
+            let item = match s.peek_next_task() {
+                Some(item) => *item,
+                None => break,
+            };
+            s.dispatch(&item);
+            s.remove_task(&item);
+            order.push(item);
+        }
+        order
+    }
+
+    #[test]
+    fn rotate_after_quantum() {
+        let mut s = AsidFairScheduler::new();
+        s.set_quantum(2);
+        for n in 0..3 {
+            assert_eq!(s.add_task(Item(1, n)), None);
+            assert_eq!(s.add_task(Item(2, n)), None);
+        }
+        let order: Vec<_> = run(&mut s, 6).into_iter().map(|item| item.0).collect();
+        assert_eq!(order, [1, 1, 2, 2, 1, 2]);
+        assert_eq!(s.switches(), 3);
+        assert_eq!(s.queue_len(), Some(0));
+    }
+
+    #[test]
+    fn keep_running_when_alone() {
+        let mut s = AsidFairScheduler::new();
+        s.set_quantum(2);
+        for n in 0..5 {
+            assert_eq!(s.add_task(Item(1, n)), None);
+        }
+        assert_eq!(run(&mut s, 5).len(), 5);
+        assert_eq!(s.switches(), 0);
+    }
+
+    #[test]
+    fn stale_pops_are_free() {
+        let mut s = AsidFairScheduler::new();
+        s.set_quantum(2);
+        for n in 0..4 {
+            assert_eq!(s.add_task(Item(1, n)), None);
+        }
+        assert_eq!(s.add_task(Item(2, 0)), None);
+        // 弹出过期的任务不消耗时间片，也不算地址空间切换
+        assert_eq!(s.next_task(), Some(Item(1, 0)));
+        assert_eq!(s.next_task(), Some(Item(1, 1)));
+        assert_eq!(run(&mut s, 3), [Item(1, 2), Item(1, 3), Item(2, 0)]);
+        assert_eq!(s.switches(), 1);
+    }
+}
@@ -0,0 +1,105 @@
+//! 按地址空间轮转的调度器实现
+
+use super::{RingQueue, Scheduler};
+use crate::mm::AddressSpaceId;
+
+/// 能被按地址空间轮转的调度器调度的任务需要实现的 Trait
+pub trait WithAddressSpace {
+    /// 得到任务所在的地址空间编号
+    fn address_space_id(&self) -> AddressSpaceId;
+}
+
+/// 按地址空间轮转的调度器
+///
+/// 每取出一个任务，下一个任务就换成队列里其它地址空间的第一个任务，
+/// 这样一个地址空间的大量任务不会让其它地址空间一直等待。同一个地址空间的任务之间先进先出
+///
+/// 队列头部总是下一个要取出的任务，跳过的任务按原来的顺序移到队列尾部
+pub struct AsidRoundRobinScheduler<T> {
+    ring: RingQueue<T>,
+    current: Option<T>,
+    /// 上一个取出的任务所在的地址空间
+    last_asid: Option<AddressSpaceId>,
+}
+
+impl<T> AsidRoundRobinScheduler<T> {
+    /// 创建一个空的调度器
+    pub const fn new() -> Self {
+        Self {
+            ring: RingQueue::new(),
+            current: None,
+            last_asid: None,
+        }
+    }
+}
+
+impl<T: WithAddressSpace> AsidRoundRobinScheduler<T> {
+    /// 把队列里第一个不属于上一个地址空间的任务转到队列头部
+    fn rotate_to_next_asid(&mut self) {
+        let last_asid = match self.last_asid {
+            Some(asid) => asid,
+            None => return,
+        };
+        let idx = self
+            .ring
+            .iter()
+            .position(|task| task.address_space_id() != last_asid);
+        if let Some(idx) = idx {
+            self.ring.rotate(idx);
+        }
+    }
+}
+
+impl<T: Clone + PartialEq + WithAddressSpace> Scheduler<T> for AsidRoundRobinScheduler<T> {
+    type Priority = ();
+    /// 添加任务到队列尾部
+    fn add_task(&mut self, task: T) -> Option<T> {
+        let ans = self.ring.push_back(task);
+        // 队列里原来只有上一个地址空间的任务，新任务可能属于其它地址空间
+        if ans.is_none() {
+            let front_asid = self.ring.front().map(|task| task.address_space_id());
+            if front_asid.is_some() && front_asid == self.last_asid {
+                self.rotate_to_next_asid();
+            }
+        }
+        ans
+    }
+    /// 取出队列头部的任务，然后轮到下一个地址空间
+    fn next_task(&mut self) -> Option<T> {
+        let ans = self.ring.pop_front();
+        if let Some(task) = &ans {
+            self.last_asid = Some(task.address_space_id());
+            self.rotate_to_next_asid();
+        }
+        self.current = ans.clone();
+        ans
+    }
+    /// 拿出下一个任务的不可变引用，不弹出
+    fn peek_next_task(&self) -> Option<&T> {
+        self.ring.front()
+    }
+    /// 拿出下一个任务的可变引用，不弹出
+    fn peek_next_task_mut(&mut self) -> Option<&mut T> {
+        self.ring.front_mut()
+    }
+    /// 获取当前任务
+    fn current_task(&self) -> Option<T> {
+        self.current.clone()
+    }
+    /// 移除一个特定的任务
+    fn remove_task(&mut self, task: &T) {
+        self.ring.take(task);
+        self.rotate_to_next_asid();
+    }
+    /// 这个调度器不考虑优先级
+    fn set_priority(&mut self, _task: T, _prio: ()) {}
+    /// 队列中的任务数
+    fn queue_len(&self) -> Option<usize> {
+        Some(self.ring.len())
+    }
+    /// 只保留满足条件的任务
+    fn retain(&mut self, f: impl FnMut(&T) -> bool) {
+        self.ring.retain(f);
+        self.rotate_to_next_asid();
+    }
+}
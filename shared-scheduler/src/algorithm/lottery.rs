@@ -0,0 +1,116 @@
+//! 彩票调度器实现
+
+use super::{RingQueue, Scheduler, WithPriority, PRIORITY_LEVELS};
+
+/// 彩票调度器
+///
+/// 每个任务按优先级持有若干彩票，优先级0的任务持有`PRIORITY_LEVELS`张，最低优先级的任务持有1张。
+/// 每次取出任务后重新抽奖，中奖的任务转到队列头部，所以查看和取出得到的总是同一个任务
+pub struct LotteryScheduler<T> {
+    ring: RingQueue<T>,
+    current: Option<T>,
+    /// 伪随机数生成器的状态，为零时还没有播种
+    seed: u64,
+}
+
+impl<T> LotteryScheduler<T> {
+    /// 创建一个空的调度器
+    pub const fn new() -> Self {
+        Self {
+            ring: RingQueue::new(),
+            current: None,
+            seed: 0,
+        }
+    }
+
+    /// xorshift64伪随机数
+    fn next_random(&mut self) -> u64 {
+        if self.seed == 0 {
+            self.seed = 0x2545_f491_4f6c_dd1d;
+        }
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+}
+
+impl<T: WithPriority> LotteryScheduler<T> {
+    /// 任务持有的彩票数量
+    fn tickets(task: &T) -> u64 {
+        (PRIORITY_LEVELS - (task.priority() as usize).min(PRIORITY_LEVELS - 1)) as u64
+    }
+
+    /// 抽奖，把中奖的任务转到队列头部
+    fn draw(&mut self) {
+        if self.ring.len() <= 1 {
+            return;
+        }
+        let total: u64 = self.ring.iter().map(Self::tickets).sum();
+        let mut ticket = self.next_random() % total;
+        let mut winner = 0;
+        for (idx, task) in self.ring.iter().enumerate() {
+            let tickets = Self::tickets(task);
+            if ticket < tickets {
+                winner = idx;
+                break;
+            }
+            ticket -= tickets;
+        }
+        self.ring.rotate(winner);
+    }
+}
+
+impl<T: Clone + PartialEq + WithPriority> Scheduler<T> for LotteryScheduler<T> {
+    type Priority = u8;
+    /// 添加任务到队列尾部，不影响已经中奖的任务
+    fn add_task(&mut self, task: T) -> Option<T> {
+        self.ring.push_back(task)
+    }
+    /// 取出中奖的任务，然后重新抽奖
+    fn next_task(&mut self) -> Option<T> {
+        let ans = self.ring.pop_front();
+        self.draw();
+        self.current = ans.clone();
+        ans
+    }
+    /// 拿出下一个任务的不可变引用，不弹出
+    fn peek_next_task(&self) -> Option<&T> {
+        self.ring.front()
+    }
+    /// 拿出下一个任务的可变引用，不弹出
+    fn peek_next_task_mut(&mut self) -> Option<&mut T> {
+        self.ring.front_mut()
+    }
+    /// 获取当前任务
+    fn current_task(&self) -> Option<T> {
+        self.current.clone()
+    }
+    /// 移除一个特定的任务
+    fn remove_task(&mut self, task: &T) {
+        let was_front = self.ring.front() == Some(task);
+        self.ring.take(task);
+        if was_front {
+            self.draw();
+        }
+    }
+    /// 修改任务的彩票数量
+    fn set_priority(&mut self, task: T, priority: u8) {
+        let idx = self.ring.iter().position(|t| *t == task);
+        if let Some(idx) = idx {
+            let len = self.ring.len();
+            self.ring.rotate(idx);
+            self.ring.front_mut().unwrap().set_priority(priority);
+            self.ring.rotate(len - idx);
+        }
+    }
+    /// 队列中的任务数
+    fn queue_len(&self) -> Option<usize> {
+        Some(self.ring.len())
+    }
+    /// 只保留满足条件的任务，之后重新抽奖
+    fn retain(&mut self, f: impl FnMut(&T) -> bool) {
+        self.ring.retain(f);
+        self.draw();
+    }
+}
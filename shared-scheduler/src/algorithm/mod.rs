@@ -1,7 +1,18 @@
 //! 调度算法这这里实现
 
+mod asid_fair;
+mod asid_rr;
+mod lottery;
+mod policy;
+mod priority;
 mod ring_fifo;
+pub use asid_fair::AsidFairScheduler;
+pub use asid_rr::{AsidRoundRobinScheduler, WithAddressSpace};
+pub use lottery::LotteryScheduler;
+pub use policy::{PolicyScheduler, SchedPolicy};
+pub use priority::{PriorityScheduler, WithPriority, PRIORITY_LEVELS};
 pub use ring_fifo::RingFifoScheduler;
+use ring_fifo::RingQueue;
 
 /// 调度器实例需要实现的 Trait
 pub trait Scheduler<T: Clone + PartialEq> {
@@ -29,4 +40,6 @@ pub trait Scheduler<T: Clone + PartialEq> {
     fn queue_len(&self) -> Option<usize> {
         None
     }
+    /// 只保留`f`返回true的任务，保留的任务之间的顺序不变
+    fn retain(&mut self, f: impl FnMut(&T) -> bool);
 }
@@ -0,0 +1,190 @@
+//! 运行时选择调度算法
+
+use super::{
+    AsidFairScheduler, AsidRoundRobinScheduler, LotteryScheduler, PriorityScheduler,
+    RingFifoScheduler, Scheduler, WithAddressSpace, WithPriority,
+};
+
+/// 调度算法
+///
+/// 由内核初始化共享调度器时传入，数值是共享调度器接口的一部分
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum SchedPolicy {
+    /// 多级优先级队列，默认的调度算法
+    Priority = 0,
+    /// 先进先出轮转
+    Fifo = 1,
+    /// 按地址空间轮转
+    AsidRoundRobin = 2,
+    /// 彩票调度
+    Lottery = 3,
+    /// 按地址空间分时间片，同一地址空间的任务成批运行
+    AsidFair = 4,
+}
+
+impl SchedPolicy {
+    /// 从接口传入的数值得到调度算法，不认识的数值返回None
+    pub fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            0 => Some(SchedPolicy::Priority),
+            1 => Some(SchedPolicy::Fifo),
+            2 => Some(SchedPolicy::AsidRoundRobin),
+            3 => Some(SchedPolicy::Lottery),
+            4 => Some(SchedPolicy::AsidFair),
+            _ => None,
+        }
+    }
+}
+
+/// 能在运行时切换调度算法的调度器
+///
+/// 每种调度算法的调度器都放在里面，只有当前选择的那一个在使用。
+/// 这样切换算法不需要在栈上构造新的调度器，没有使用的调度器不会从堆上分配内存
+pub struct PolicyScheduler<T> {
+    policy: SchedPolicy,
+    fifo: RingFifoScheduler<T>,
+    priority: PriorityScheduler<T>,
+    asid_round_robin: AsidRoundRobinScheduler<T>,
+    lottery: LotteryScheduler<T>,
+    asid_fair: AsidFairScheduler<T>,
+}
+
+impl<T> PolicyScheduler<T> {
+    /// 创建一个空的调度器，默认使用多级优先级队列
+    pub const fn new() -> Self {
+        Self {
+            policy: SchedPolicy::Priority,
+            fifo: RingFifoScheduler::new(),
+            priority: PriorityScheduler::new(),
+            asid_round_robin: AsidRoundRobinScheduler::new(),
+            lottery: LotteryScheduler::new(),
+            asid_fair: AsidFairScheduler::new(),
+        }
+    }
+
+    /// 当前的调度算法
+    pub fn policy(&self) -> SchedPolicy {
+        self.policy
+    }
+
+    /// 设置按地址空间分时间片时的时间片长度，为0时使用默认值
+    pub fn set_quantum(&mut self, quantum: usize) {
+        self.asid_fair.set_quantum(quantum);
+    }
+
+    /// 按地址空间分时间片时，地址空间切换的次数；其它调度算法不统计，总是0
+    pub fn asid_switches(&self) -> usize {
+        self.asid_fair.switches()
+    }
+
+    /// 任务交给执行器运行，按地址空间分时间片时消耗一个时间片；其它调度算法忽略
+    pub fn dispatch(&mut self, task: &T)
+    where
+        T: WithAddressSpace,
+    {
+        if self.policy == SchedPolicy::AsidFair {
+            self.asid_fair.dispatch(task);
+        }
+    }
+}
+
+impl<T: Clone + PartialEq + WithPriority + WithAddressSpace> PolicyScheduler<T> {
+    /// 切换调度算法，只能在调度器为空的时候切换
+    pub fn set_policy(&mut self, policy: SchedPolicy) {
+        assert_eq!(self.queue_len(), Some(0));
+        self.policy = policy;
+    }
+}
+
+/// 把调用转发给当前选择的调度器
+macro_rules! dispatch {
+    ($self: expr, $s: ident => $call: expr) => {
+        match $self.policy {
+            SchedPolicy::Fifo => {
+                let $s = &mut $self.fifo;
+                $call
+            }
+            SchedPolicy::Priority => {
+                let $s = &mut $self.priority;
+                $call
+            }
+            SchedPolicy::AsidRoundRobin => {
+                let $s = &mut $self.asid_round_robin;
+                $call
+            }
+            SchedPolicy::Lottery => {
+                let $s = &mut $self.lottery;
+                $call
+            }
+            SchedPolicy::AsidFair => {
+                let $s = &mut $self.asid_fair;
+                $call
+            }
+        }
+    };
+}
+
+/// 只读地转发给当前选择的调度器
+macro_rules! dispatch_ref {
+    ($self: expr, $s: ident => $call: expr) => {
+        match $self.policy {
+            SchedPolicy::Fifo => {
+                let $s = &$self.fifo;
+                $call
+            }
+            SchedPolicy::Priority => {
+                let $s = &$self.priority;
+                $call
+            }
+            SchedPolicy::AsidRoundRobin => {
+                let $s = &$self.asid_round_robin;
+                $call
+            }
+            SchedPolicy::Lottery => {
+                let $s = &$self.lottery;
+                $call
+            }
+            SchedPolicy::AsidFair => {
+                let $s = &$self.asid_fair;
+                $call
+            }
+        }
+    };
+}
+
+impl<T: Clone + PartialEq + WithPriority + WithAddressSpace> Scheduler<T> for PolicyScheduler<T> {
+    type Priority = u8;
+    fn add_task(&mut self, task: T) -> Option<T> {
+        dispatch!(self, s => s.add_task(task))
+    }
+    fn peek_next_task(&self) -> Option<&T> {
+        dispatch_ref!(self, s => s.peek_next_task())
+    }
+    fn peek_next_task_mut(&mut self) -> Option<&mut T> {
+        dispatch!(self, s => s.peek_next_task_mut())
+    }
+    fn next_task(&mut self) -> Option<T> {
+        dispatch!(self, s => s.next_task())
+    }
+    fn current_task(&self) -> Option<T> {
+        dispatch_ref!(self, s => s.current_task())
+    }
+    fn remove_task(&mut self, task: &T) {
+        dispatch!(self, s => s.remove_task(task))
+    }
+    /// 不考虑优先级的调度算法忽略这个操作
+    fn set_priority(&mut self, task: T, priority: u8) {
+        match self.policy {
+            SchedPolicy::Priority => self.priority.set_priority(task, priority),
+            SchedPolicy::Lottery => self.lottery.set_priority(task, priority),
+            SchedPolicy::Fifo | SchedPolicy::AsidRoundRobin | SchedPolicy::AsidFair => {}
+        }
+    }
+    fn queue_len(&self) -> Option<usize> {
+        dispatch_ref!(self, s => s.queue_len())
+    }
+    fn retain(&mut self, f: impl FnMut(&T) -> bool) {
+        dispatch!(self, s => s.retain(f))
+    }
+}
@@ -0,0 +1,111 @@
+//! 多级优先级队列调度器实现
+
+use super::{RingQueue, Scheduler};
+
+/// 优先级的级数
+///
+/// 优先级的数值越小，优先级越高，0为最高优先级
+pub const PRIORITY_LEVELS: usize = 8;
+
+/// 能被优先级调度器调度的任务需要实现的 Trait
+pub trait WithPriority {
+    /// 得到任务的优先级
+    fn priority(&self) -> u8;
+    /// 修改任务的优先级
+    fn set_priority(&mut self, priority: u8);
+}
+
+/// 多级优先级队列调度器
+///
+/// 每个优先级各有一个先进先出的环形队列，总是从优先级最高的非空队列中取出任务，
+/// 相同优先级的任务之间先进先出轮转
+pub struct PriorityScheduler<T> {
+    levels: [RingQueue<T>; PRIORITY_LEVELS],
+    current: Option<T>,
+}
+
+impl<T> PriorityScheduler<T> {
+    /// 创建一个空的调度器
+    pub const fn new() -> Self {
+        Self {
+            levels: [
+                RingQueue::new(),
+                RingQueue::new(),
+                RingQueue::new(),
+                RingQueue::new(),
+                RingQueue::new(),
+                RingQueue::new(),
+                RingQueue::new(),
+                RingQueue::new(),
+            ],
+            current: None,
+        }
+    }
+}
+
+impl<T: WithPriority> PriorityScheduler<T> {
+    /// 任务所在的队列，超出范围的优先级按最低优先级处理
+    fn level_of(task: &T) -> usize {
+        (task.priority() as usize).min(PRIORITY_LEVELS - 1)
+    }
+}
+
+impl<T: Clone + PartialEq + WithPriority> Scheduler<T> for PriorityScheduler<T> {
+    type Priority = u8;
+    /// 按照任务的优先级添加到相应的队列尾部
+    fn add_task(&mut self, task: T) -> Option<T> {
+        let level = Self::level_of(&task);
+        self.levels[level].push_back(task)
+    }
+    /// 从优先级最高的非空队列头部取出任务
+    fn next_task(&mut self) -> Option<T> {
+        let ans = self
+            .levels
+            .iter_mut()
+            .find(|queue| !queue.is_empty())
+            .and_then(|queue| queue.pop_front());
+        self.current = ans.clone();
+        ans
+    }
+    /// 拿出下一个任务的不可变引用，不弹出
+    fn peek_next_task(&self) -> Option<&T> {
+        self.levels.iter().find_map(|queue| queue.front())
+    }
+    /// 拿出下一个任务的可变引用，不弹出
+    fn peek_next_task_mut(&mut self) -> Option<&mut T> {
+        self.levels.iter_mut().find_map(|queue| queue.front_mut())
+    }
+    /// 获取当前任务
+    fn current_task(&self) -> Option<T> {
+        self.current.clone()
+    }
+    /// 移除一个特定的任务
+    fn remove_task(&mut self, task: &T) {
+        let level = Self::level_of(task);
+        self.levels[level].take(task);
+    }
+    /// 设置任务优先级，任务将被移动到新优先级队列的尾部
+    fn set_priority(&mut self, task: T, priority: u8) {
+        let level = Self::level_of(&task);
+        if let Some(mut task) = self.levels[level].take(&task) {
+            let old_priority = task.priority();
+            task.set_priority(priority);
+            let new_level = Self::level_of(&task);
+            // 刚刚移除了一个任务，如果放不进新的队列，就按原来的优先级放回原来的队列
+            if let Some(mut task) = self.levels[new_level].push_back(task) {
+                task.set_priority(old_priority);
+                self.levels[level].push_back(task);
+            }
+        }
+    }
+    /// 所有优先级队列中的任务总数
+    fn queue_len(&self) -> Option<usize> {
+        Some(self.levels.iter().map(|queue| queue.len()).sum())
+    }
+    /// 在每个优先级队列中只保留满足条件的任务
+    fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        for queue in self.levels.iter_mut() {
+            queue.retain(&mut f);
+        }
+    }
+}
@@ -1,16 +1,17 @@
 //! 循环先进先出队列调度器实现
 
 use super::Scheduler;
+use alloc::vec::Vec;
 use core::mem::MaybeUninit;
 use core::ptr;
 
 /// 先进先出轮转任务调度器
-pub struct RingFifoScheduler<T, const N: usize> {
-    ring: RingQueue<T, N>,
+pub struct RingFifoScheduler<T> {
+    ring: RingQueue<T>,
     current: Option<T>,
 }
 
-impl<T, const N: usize> RingFifoScheduler<T, N> {
+impl<T> RingFifoScheduler<T> {
     /// 创建一个空的调度器
     pub const fn new() -> Self {
         Self {
@@ -20,7 +21,7 @@ impl<T, const N: usize> RingFifoScheduler<T, N> {
     }
 }
 
-impl<T: Clone + PartialEq, const N: usize> Scheduler<T> for RingFifoScheduler<T, N> {
+impl<T: Clone + PartialEq> Scheduler<T> for RingFifoScheduler<T> {
     type Priority = ();
     /// 添加任务
     fn add_task(&mut self, task: T) -> Option<T> {
@@ -54,9 +55,7 @@ impl<T: Clone + PartialEq, const N: usize> Scheduler<T> for RingFifoScheduler<T,
     }
     /// 移除一个特定的任务
     fn remove_task(&mut self, task: &T) {
-        // 移除相应的线程并且确认恰移除一个线程
-        drop(task);
-        todo!("还没做")
+        self.ring.take(task);
     }
     /// 设置任务优先级
     fn set_priority(&mut self, _task: T, _prio: ()) {}
@@ -64,43 +63,46 @@ impl<T: Clone + PartialEq, const N: usize> Scheduler<T> for RingFifoScheduler<T,
     fn queue_len(&self) -> Option<usize> {
         Some(self.ring.len())
     }
+    /// 只保留满足条件的任务
+    fn retain(&mut self, f: impl FnMut(&T) -> bool) {
+        self.ring.retain(f);
+    }
 }
 
-pub struct RingQueue<T, const N: usize> {
-    elem: [MaybeUninit<T>; N],
+/// 环形队列，存储空间放在堆上，满了的时候扩容
+///
+/// 堆内存不足、无法扩容的时候，添加元素失败
+pub struct RingQueue<T> {
+    elem: Vec<MaybeUninit<T>>,
     front: usize,
-    tail: usize,
+    len: usize,
 }
 
-impl<T, const N: usize> RingQueue<T, N> {
+/// 环形队列第一次分配的容量
+const INITIAL_CAPACITY: usize = 16;
+
+impl<T> RingQueue<T> {
     pub const fn new() -> Self {
         Self {
-            elem: MaybeUninit::uninit_array(),
+            elem: Vec::new(),
             front: 0,
-            tail: 0,
+            len: 0,
         }
     }
     pub const fn len(&self) -> usize {
-        self.tail.wrapping_sub(self.front) % N
+        self.len
     }
     pub const fn is_empty(&self) -> bool {
-        self.tail == self.front
-    }
-    #[inline]
-    fn is_full(&self) -> bool {
-        self.len() == N - 1
+        self.len == 0
     }
     // if push failed, value T is returned
     pub fn push_back(&mut self, value: T) -> Option<T> {
-        if self.is_full() {
+        if self.len == self.elem.len() && !self.grow() {
             return Some(value);
         }
-        unsafe { *self.elem[self.tail].as_mut_ptr() = value };
-        self.tail = self.tail.wrapping_add(1);
-        // '>' -> '>='
-        if self.tail >= N || self.tail == 0 {
-            self.tail = self.tail.wrapping_sub(N);
-        }
+        let idx = (self.front + self.len) % self.elem.len();
+        self.elem[idx] = MaybeUninit::new(value);
+        self.len += 1;
         None // success
     }
     pub fn pop_front(&mut self) -> Option<T> {
@@ -108,11 +110,8 @@ impl<T, const N: usize> RingQueue<T, N> {
             return None;
         }
         let value = unsafe { ptr::read(self.elem[self.front].as_ptr()) };
-        self.front = self.front.wrapping_add(1); // assured non empty
-                                                 // '>' -> '>='
-        if self.front >= N || self.front == 0 {
-            self.front = self.front.wrapping_sub(N);
-        }
+        self.front = (self.front + 1) % self.elem.len();
+        self.len -= 1;
         Some(value)
     }
     pub fn front(&self) -> Option<&T> {
@@ -129,4 +128,150 @@ impl<T, const N: usize> RingQueue<T, N> {
             Some(unsafe { &mut *self.elem[self.front].as_mut_ptr() })
         }
     }
+    /// 从头到尾遍历队列中的元素
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let cap = self.elem.len();
+        (0..self.len).map(move |i| unsafe { &*self.elem[(self.front + i) % cap].as_ptr() })
+    }
+    /// 把头部的`n`个元素依次移到尾部，其它元素的相对顺序不变
+    pub fn rotate(&mut self, n: usize) {
+        for _ in 0..n {
+            let value = self.pop_front().unwrap();
+            self.push_back(value);
+        }
+    }
+    /// 取出与`value`相等的第一个元素，其它元素的顺序保持不变
+    pub fn take(&mut self, value: &T) -> Option<T>
+    where
+        T: PartialEq,
+    {
+        let idx = self.iter().position(|elem| elem == value)?;
+        let len = self.len();
+        self.rotate(idx);
+        let ans = self.pop_front();
+        self.rotate(len - 1 - idx);
+        ans
+    }
+    /// 只保留`f`返回true的元素，顺序保持不变，不需要分配内存
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        for _ in 0..self.len() {
+            let value = self.pop_front().unwrap();
+            if f(&value) {
+                self.push_back(value);
+            }
+        }
+    }
+    /// 容量翻倍，分配不到内存时返回false
+    fn grow(&mut self) -> bool {
+        let old_cap = self.elem.len();
+        let new_cap = if old_cap == 0 {
+            INITIAL_CAPACITY
+        } else {
+            old_cap * 2
+        };
+        let mut elem = Vec::new();
+        if elem.try_reserve_exact(new_cap).is_err() {
+            return false;
+        }
+        for i in 0..self.len {
+            let value = unsafe { ptr::read(self.elem[(self.front + i) % old_cap].as_ptr()) };
+            elem.push(MaybeUninit::new(value));
+        }
+        elem.resize_with(new_cap, MaybeUninit::uninit);
+        self.elem = elem;
+        self.front = 0;
+        true
+    }
+}
+
+impl<T> Drop for RingQueue<T> {
+    fn drop(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RingFifoScheduler, RingQueue, INITIAL_CAPACITY};
+    use crate::algorithm::Scheduler;
+    use std::vec::Vec;
+
+    #[test]
+    fn fifo_order() {
+        let mut s = RingFifoScheduler::new();
+        assert_eq!(s.next_task(), None);
+        for i in 0..5 {
+            assert_eq!(s.add_task(i), None);
+        }
+        assert_eq!(s.peek_next_task(), Some(&0));
+        assert_eq!(s.next_task(), Some(0));
+        assert_eq!(s.current_task(), Some(0));
+        s.remove_task(&3);
+        s.remove_task(&7);
+        assert_eq!(s.queue_len(), Some(3));
+        let rest: Vec<_> = core::iter::from_fn(|| s.next_task()).collect();
+        assert_eq!(rest, [1, 2, 4]);
+        assert_eq!(s.peek_next_task(), None);
+    }
+
+    #[test]
+    fn wraparound_at_capacity() {
+        let mut q = RingQueue::new();
+        // 头部走到队列中间，再放满，元素在存储空间的末尾绕回开头
+        for i in 0..INITIAL_CAPACITY / 2 {
+            assert_eq!(q.push_back(i), None);
+        }
+        for i in 0..INITIAL_CAPACITY / 2 {
+            assert_eq!(q.pop_front(), Some(i));
+        }
+        for i in 0..INITIAL_CAPACITY {
+            assert_eq!(q.push_back(i), None);
+        }
+        assert_eq!(q.len(), INITIAL_CAPACITY);
+        assert!(q.iter().copied().eq(0..INITIAL_CAPACITY));
+        // 绕回的状态下扩容，顺序保持不变
+        for i in INITIAL_CAPACITY..INITIAL_CAPACITY * 3 {
+            assert_eq!(q.push_back(i), None);
+        }
+        assert!(q.iter().copied().eq(0..INITIAL_CAPACITY * 3));
+        for i in 0..INITIAL_CAPACITY * 3 {
+            assert_eq!(q.front(), Some(&i));
+            assert_eq!(q.pop_front(), Some(i));
+        }
+        assert!(q.is_empty());
+        assert_eq!(q.pop_front(), None);
+    }
+
+    #[test]
+    fn take_and_retain_keep_order() {
+        let mut q = RingQueue::new();
+        for i in 0..INITIAL_CAPACITY + 5 {
+            q.push_back(i);
+        }
+        for _ in 0..7 {
+            let i = q.pop_front().unwrap();
+            q.push_back(i);
+        }
+        let before: Vec<_> = q.iter().copied().collect();
+        assert_eq!(q.take(&10), Some(10));
+        assert_eq!(q.take(&100), None);
+        q.retain(|i| i % 3 != 0);
+        let expected: Vec<_> = before
+            .into_iter()
+            .filter(|&i| i != 10 && i % 3 != 0)
+            .collect();
+        assert!(q.iter().copied().eq(expected));
+    }
+
+    #[test]
+    fn drop_remaining() {
+        let item = std::sync::Arc::new(());
+        let mut q = RingQueue::new();
+        for _ in 0..INITIAL_CAPACITY + 1 {
+            q.push_back(item.clone());
+        }
+        drop(q.pop_front());
+        drop(q);
+        assert_eq!(std::sync::Arc::strong_count(&item), 1);
+    }
 }
@@ -0,0 +1,14 @@
+//! 共享调度器的调度算法和任务表
+//!
+//! 这部分不依赖硬件和链接脚本，既能编译进共享调度器的二进制包，也能在主机上编译和测试。
+//! 虚函数表、堆和控制台这些只在目标平台上有意义的部分放在`main.rs`中
+#![cfg_attr(not(test), no_std)]
+#![feature(try_reserve)]
+
+extern crate alloc;
+
+pub mod algorithm;
+pub mod mm;
+pub mod mpmc;
+pub mod table;
+pub mod task;
@@ -20,25 +20,30 @@ extern crate alloc;
 
 #[macro_use]
 mod console;
-mod algorithm;
-mod mm;
 mod syscall;
-mod task;
 
-use crate::{
+use buddy_system_allocator::LockedHeap;
+use core::{
+    mem::MaybeUninit,
+    ptr::NonNull,
+    sync::atomic::{compiler_fence, Ordering},
+};
+use shared_abi::{SHARED_ABI_VERSION, SHARED_PAYLOAD_MAGIC};
+use shared_scheduler::{
+    algorithm::SchedPolicy,
     mm::AddressSpaceId,
     task::{
-        shared_add_task, shared_delete_task, shared_peek_task, shared_set_task_state,
-        SharedScheduler, TaskRepr, TaskResult, TaskState, SHARED_SCHEDULER,
+        shared_add_task, shared_delete_task, shared_has_ready_task, shared_peek_task,
+        shared_remove_address_space, shared_requeue_task, shared_scheduler_stats,
+        shared_set_task_priority, shared_set_task_state, SchedulerStats, SharedScheduler, TaskRepr,
+        TaskResult, TaskState, SHARED_SCHEDULER,
     },
 };
-use buddy_system_allocator::LockedHeap;
-use core::{mem::MaybeUninit, ptr::NonNull};
 
 #[global_allocator]
 static HEAP: LockedHeap = LockedHeap::empty();
 
-const HEAP_SIZE: usize = 128 * 1024;
+const HEAP_SIZE: usize = 512 * 1024; // 就绪队列和任务表都放在堆上
 static HEAP_MEMORY: MaybeUninit<[u8; HEAP_SIZE]> = core::mem::MaybeUninit::uninit();
 
 #[cfg_attr(not(test), panic_handler)]
@@ -65,26 +70,68 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
     panic!("shared scheduler alloc error: {:?}", layout)
 }
 
+/// 共享调度器虚函数表
+///
+/// 内核和用户分别编译，只能通过这个表调用共享调度器。加载时先检查魔数、版本号和大小，
+/// 不一致就拒绝加载，而不是跳转到错误的函数上
+#[repr(C)]
+pub struct SharedRawTable {
+    /// 魔数，总是[`SHARED_PAYLOAD_MAGIC`]
+    magic: usize,
+    /// 版本号，总是[`SHARED_ABI_VERSION`]
+    version: usize,
+    /// 整个虚函数表的字节数
+    size: usize,
+    /// 共享调度器编译时的基地址，加载时用来计算偏移量
+    compiled_base: &'static u8,
+    /// 初始化函数，只能由内核运行一次，参数是调度算法的编号和时间片长度
+    init: unsafe extern "C" fn(usize, usize) -> PageList,
+    /// 共享调度器的地址
+    shared_scheduler: &'static SharedScheduler,
+    /// 添加任务
+    add_task: unsafe extern "C" fn(NonNull<()>, usize, AddressSpaceId, TaskRepr, u8) -> bool,
+    /// 弹出任务引用
+    peek_task: unsafe extern "C" fn(
+        NonNull<()>,
+        usize,
+        extern "C" fn(AddressSpaceId) -> bool,
+    ) -> TaskResult,
+    /// 删除任务
+    delete_task: unsafe extern "C" fn(NonNull<()>, TaskRepr) -> bool,
+    /// 改变任务的状态
+    set_task_state: unsafe extern "C" fn(NonNull<()>, TaskRepr, TaskState),
+    /// 改变任务的优先级
+    set_task_priority: unsafe extern "C" fn(NonNull<()>, TaskRepr, u8),
+    /// 读取统计信息
+    scheduler_stats: unsafe extern "C" fn(NonNull<()>, *mut SchedulerStats),
+    /// 删除一个地址空间的所有任务
+    remove_address_space: unsafe extern "C" fn(NonNull<()>, AddressSpaceId) -> usize,
+    /// 查看是否有其它地址空间的就绪任务，不改变调度器的状态
+    has_ready_task: unsafe extern "C" fn(NonNull<()>, usize, AddressSpaceId) -> bool,
+    /// 把就绪队列头部的任务移到队列尾部
+    requeue_task: unsafe extern "C" fn(NonNull<()>, usize) -> bool,
+}
+
 /// 共享调度器虚函数表
 #[link_section = ".meta"] // 虚函数表只读
 #[no_mangle]
-pub static SHARED_RAW_TABLE: (
-    &'static u8,                        // 共享调度器编译时的基地址
-    unsafe extern "C" fn() -> PageList, // 初始化函数，执行完之后，内核将函数指针置空
-    &'static SharedScheduler,           // 共享调度器的地址
-    unsafe extern "C" fn(NonNull<()>, usize, AddressSpaceId, TaskRepr) -> bool, // 添加任务
-    unsafe extern "C" fn(NonNull<()>, extern "C" fn(AddressSpaceId) -> bool) -> TaskResult, // 弹出任务引用
-    unsafe extern "C" fn(NonNull<()>, TaskRepr) -> bool, // 删除任务
-    unsafe extern "C" fn(NonNull<()>, TaskRepr, TaskState), // 改变任务的状态
-) = (
-    unsafe { &payload_compiled_start },
-    init_payload_environment,
-    &SHARED_SCHEDULER,
-    shared_add_task,
-    shared_peek_task,
-    shared_delete_task,
-    shared_set_task_state,
-);
+pub static SHARED_RAW_TABLE: SharedRawTable = SharedRawTable {
+    magic: SHARED_PAYLOAD_MAGIC,
+    version: SHARED_ABI_VERSION,
+    size: core::mem::size_of::<SharedRawTable>(),
+    compiled_base: unsafe { &payload_compiled_start },
+    init: init_payload_environment,
+    shared_scheduler: &SHARED_SCHEDULER,
+    add_task: shared_add_task,
+    peek_task,
+    delete_task: shared_delete_task,
+    set_task_state: shared_set_task_state,
+    set_task_priority: shared_set_task_priority,
+    scheduler_stats: shared_scheduler_stats,
+    remove_address_space: shared_remove_address_space,
+    has_ready_task: shared_has_ready_task,
+    requeue_task: shared_requeue_task,
+};
 
 #[allow(non_upper_case_globals)]
 extern "C" {
@@ -103,13 +150,35 @@ extern "C" {
     static mut ebss: u32;
 }
 
+/// 从共享调度器中找到下一个任务，见[`shared_peek_task`]
+///
+/// 调度器的内存会被内核和各个用户程序从不同的地址空间改写，编译器看不到这些写入。
+/// 这里不内联，并在进入调度器前放一个编译器屏障，避免对调度器状态的读取被合并或提前
+#[inline(never)]
+unsafe extern "C" fn peek_task(
+    shared_scheduler: NonNull<()>,
+    hart_id: usize,
+    should_switch: extern "C" fn(AddressSpaceId) -> bool,
+) -> TaskResult {
+    compiler_fence(Ordering::SeqCst);
+    shared_peek_task(shared_scheduler, hart_id, should_switch)
+}
+
 /// 初始化共享调度器环境，只能由内核运行，只能运行一次
-unsafe extern "C" fn init_payload_environment() -> PageList {
+///
+/// * policy: 调度算法的编号，见[`SchedPolicy`]，不认识的编号使用默认的调度算法
+/// * quantum: 按地址空间分时间片时的时间片长度，为0时使用默认值
+///
+/// 调度算法对所有地址空间生效，之后不能再切换，用户程序也不能改变它
+unsafe extern "C" fn init_payload_environment(policy: usize, quantum: usize) -> PageList {
     // 初始化零初始段，每次写入一个u32类型的零内存
     r0::zero_bss(&mut sbss, &mut ebss);
     // 初始化堆
     let heap_start = HEAP_MEMORY.as_ptr() as usize;
     HEAP.lock().init(heap_start, HEAP_SIZE);
+    // 选择调度算法
+    let policy = SchedPolicy::from_raw(policy).unwrap_or(SchedPolicy::Priority);
+    SHARED_SCHEDULER.lock().set_policy(policy, quantum);
     // 返回一个表，表示本共享载荷应当保护的地址范围
     PageList {
         rodata: [&srodata_page, &erodata_page], // 只读
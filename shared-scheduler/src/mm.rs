@@ -11,4 +11,10 @@ impl AddressSpaceId {
     pub(crate) fn into_inner(self) -> usize {
         self.0 as usize
     }
+
+    /// 测试时直接构造地址空间编号
+    #[cfg(test)]
+    pub(crate) fn from_raw(asid: u16) -> AddressSpaceId {
+        AddressSpaceId(asid)
+    }
 }
@@ -0,0 +1,270 @@
+//! 无锁的多生产者多消费者队列
+//!
+//! 有界的环形队列，算法来自 Dmitry Vyukov 的 bounded MPMC queue。
+//! 每个槽位有一个序号，生产者和消费者只用比较并交换争抢队尾和队头的位置，
+//! 抢到位置之后由序号判断槽位是否可以读写，不需要加锁
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// 队列中的一个槽位
+struct Slot<T> {
+    /// 槽位的序号减去槽位的下标
+    ///
+    /// 等于`pos & !mask`的时候，位置`pos`的生产者可以写入；再加一的时候，位置`pos`的消费者可以读出。
+    /// 读出之后增加容量，留给下一轮的生产者。初始值全部为零，可以直接放在零初始段里
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Slot<T> {
+    const EMPTY: Self = Slot {
+        stamp: AtomicUsize::new(0),
+        value: UnsafeCell::new(MaybeUninit::uninit()),
+    };
+}
+
+/// 无锁的有界队列，容量为`N`，必须是2的幂
+///
+/// 任何硬件线程都可以同时放入和取出，中断处理函数里也可以使用，不会因为等待锁而死锁
+pub struct MpmcQueue<T, const N: usize> {
+    slots: [Slot<T>; N],
+    /// 下一个要读出的位置
+    head: AtomicUsize,
+    /// 下一个要写入的位置
+    tail: AtomicUsize,
+}
+
+unsafe impl<T: Send, const N: usize> Send for MpmcQueue<T, N> {}
+unsafe impl<T: Send, const N: usize> Sync for MpmcQueue<T, N> {}
+
+impl<T, const N: usize> MpmcQueue<T, N> {
+    const MASK: usize = N - 1;
+
+    /// 创建一个空的队列
+    pub const fn new() -> Self {
+        Self {
+            slots: [Slot::EMPTY; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// 在队尾放入一个元素，队列已满时返回`Err`
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & Self::MASK];
+            let lap = pos & !Self::MASK;
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            let diff = stamp.wrapping_sub(lap) as isize;
+            if diff == 0 {
+                match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).as_mut_ptr().write(value) };
+                        slot.stamp.store(lap.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                // 上一轮的元素还没有被读出
+                return Err(value);
+            } else {
+                // 其它生产者已经写入了这个位置
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// 从队头取出一个元素，队列为空时返回`None`
+    pub fn pop(&self) -> Option<T> {
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & Self::MASK];
+            let lap = pos & !Self::MASK;
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            let diff = stamp.wrapping_sub(lap.wrapping_add(1)) as isize;
+            if diff == 0 {
+                match self.head.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).as_ptr().read() };
+                        slot.stamp.store(lap.wrapping_add(N), Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                // 这个位置还没有写入
+                return None;
+            } else {
+                // 其它消费者已经读出了这个位置
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for MpmcQueue<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MpmcQueue;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Barrier,
+        },
+        thread,
+        vec::Vec,
+    };
+
+    #[test]
+    fn fifo_and_wraparound() {
+        let queue: MpmcQueue<usize, 8> = MpmcQueue::new();
+        assert_eq!(queue.pop(), None);
+        for round in 0..100 {
+            for i in 0..8 {
+                assert_eq!(queue.push(round * 8 + i), Ok(()));
+            }
+            assert_eq!(queue.push(usize::MAX), Err(usize::MAX));
+            for i in 0..8 {
+                assert_eq!(queue.pop(), Some(round * 8 + i));
+            }
+            assert_eq!(queue.pop(), None);
+        }
+    }
+
+    #[test]
+    fn drop_remaining() {
+        let item = Arc::new(());
+        let queue: MpmcQueue<Arc<()>, 4> = MpmcQueue::new();
+        for _ in 0..3 {
+            queue.push(item.clone()).unwrap();
+        }
+        drop(queue.pop());
+        assert_eq!(Arc::strong_count(&item), 3);
+        drop(queue);
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+    const PRODUCERS: usize = 4;
+    const CONSUMERS: usize = 4;
+    const PER_PRODUCER: usize = 200_000;
+
+    /// 多个生产者和消费者同时争抢一个很小的队列，每个元素恰好被取出一次，
+    /// 并且同一个消费者看到的同一个生产者的元素保持放入的顺序
+    #[test]
+    fn stress_mpmc() {
+        let queue: Arc<MpmcQueue<usize, 64>> = Arc::new(MpmcQueue::new());
+        let barrier = Arc::new(Barrier::new(PRODUCERS + CONSUMERS));
+        let popped = Arc::new(AtomicUsize::new(0));
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|p| {
+                let (queue, barrier) = (queue.clone(), barrier.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    for i in 0..PER_PRODUCER {
+                        let mut value = p * PER_PRODUCER + i;
+                        while let Err(v) = queue.push(value) {
+                            value = v;
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..CONSUMERS)
+            .map(|_| {
+                let (queue, barrier, popped) = (queue.clone(), barrier.clone(), popped.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    let mut seen = Vec::new();
+                    let mut last = [None; PRODUCERS];
+                    while popped.load(Ordering::Relaxed) < PRODUCERS * PER_PRODUCER {
+                        match queue.pop() {
+                            Some(value) => {
+                                popped.fetch_add(1, Ordering::Relaxed);
+                                let p = value / PER_PRODUCER;
+                                assert!(last[p].map_or(true, |last| last < value));
+                                last[p] = Some(value);
+                                seen.push(value);
+                            }
+                            None => thread::yield_now(),
+                        }
+                    }
+                    seen
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut all: Vec<usize> = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect();
+        all.sort_unstable();
+        assert_eq!(all.len(), PRODUCERS * PER_PRODUCER);
+        assert!(all.iter().enumerate().all(|(i, &value)| i == value));
+        assert_eq!(queue.pop(), None);
+    }
+
+    /// 队列在满和空之间反复切换，放入成功的元素数减去取出的元素数，等于最后剩下的元素数
+    #[test]
+    fn stress_full_and_empty() {
+        let queue: Arc<MpmcQueue<usize, 16>> = Arc::new(MpmcQueue::new());
+        let stop = Arc::new(AtomicBool::new(false));
+        let workers: Vec<_> = (0..8)
+            .map(|w| {
+                let (queue, stop) = (queue.clone(), stop.clone());
+                thread::spawn(move || {
+                    let (mut pushed, mut popped) = (0usize, 0usize);
+                    while !stop.load(Ordering::Relaxed) {
+                        // 一半的线程放得多，一半的线程取得多
+                        for _ in 0..(1 + w % 2) {
+                            if queue.push(w).is_ok() {
+                                pushed += 1;
+                            }
+                        }
+                        for _ in 0..(2 - w % 2) {
+                            if queue.pop().is_some() {
+                                popped += 1;
+                            }
+                        }
+                    }
+                    (pushed, popped)
+                })
+            })
+            .collect();
+        thread::sleep(std::time::Duration::from_millis(300));
+        stop.store(true, Ordering::Relaxed);
+        let (pushed, popped) = workers
+            .into_iter()
+            .map(|w| w.join().unwrap())
+            .fold((0, 0), |(a, b), (c, d)| (a + c, b + d));
+        let mut left = 0;
+        while queue.pop().is_some() {
+            left += 1;
+        }
+        assert!(pushed > 0);
+        assert!(left <= 16);
+        assert_eq!(pushed - popped, left);
+    }
+}
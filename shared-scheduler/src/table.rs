@@ -0,0 +1,262 @@
+//! 任务表实现
+//!
+//! 调度队列只负责维护任务的先后顺序，任务的元数据保存在任务表中。
+//! 唤醒和删除任务的时候通过任务表直接找到任务，不需要遍历整个调度队列。
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+
+/// 开放寻址哈希表，使用线性探测
+///
+/// 槽位放在堆上，元素数量超过槽位的一半时容量翻倍。
+/// 堆内存不足、无法扩容的时候，最多保存槽位数量减一个元素，保证探测总能遇到空槽位
+pub struct TaskTable<K, V> {
+    slots: Vec<Option<(K, V)>>,
+    len: usize,
+}
+
+/// 任务表第一次分配的槽位数量
+const INITIAL_SLOTS: usize = 64;
+
+impl<K, V> TaskTable<K, V> {
+    /// 创建一个空的任务表
+    pub const fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// 表中元素的数量
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl<K: Hash + Eq, V> TaskTable<K, V> {
+    /// 插入一个元素，如果键已经存在，则替换原来的值
+    ///
+    /// 插入成功返回 None，表已满且无法扩容时返回 Some((K, V))
+    pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        // 超过一半的槽位被占用时扩容；扩容失败的时候，只要还剩下不止一个空槽位，就继续插入
+        let crowded = (self.len + 1) * 2 > self.slots.len();
+        if crowded && !self.grow() && self.len + 1 >= self.slots.len() {
+            if let Some(idx) = self.find(&key) {
+                self.slots[idx] = Some((key, value));
+                return None;
+            }
+            return Some((key, value));
+        }
+        let mut idx = self.home(&key);
+        loop {
+            match &mut self.slots[idx] {
+                Some((k, v)) if *k == key => {
+                    *v = value;
+                    return None;
+                }
+                Some(_) => idx = (idx + 1) % self.slots.len(),
+                slot @ None => {
+                    *slot = Some((key, value));
+                    self.len += 1;
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// 遍历表中所有的元素，顺序不确定
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.as_ref().map(|(k, v)| (k, v)))
+    }
+
+    /// 遍历表中所有的元素，可以修改值，顺序不确定
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        self.slots
+            .iter_mut()
+            .filter_map(|slot| slot.as_mut().map(|(k, v)| (&*k, v)))
+    }
+
+    /// 得到键对应值的不可变引用
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.find(key)
+            .and_then(|idx| self.slots[idx].as_ref())
+            .map(|(_, v)| v)
+    }
+
+    /// 得到键对应值的可变引用
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        match self.find(key) {
+            Some(idx) => self.slots[idx].as_mut().map(|(_, v)| v),
+            None => None,
+        }
+    }
+
+    /// 移除一个元素，返回它的值
+    ///
+    /// 移除后把后面同一探测链上的元素往前挪，不需要墓碑标记
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let idx = self.find(key)?;
+        Some(self.remove_at(idx))
+    }
+
+    /// 只保留`f`返回true的元素
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &V) -> bool) {
+        let mut idx = 0;
+        while idx < self.slots.len() {
+            let keep = match &self.slots[idx] {
+                Some((k, v)) => f(k, v),
+                None => true,
+            };
+            if keep {
+                idx += 1;
+            } else {
+                // 后面的元素可能挪到这个槽位上，下一轮还要检查这里
+                self.remove_at(idx);
+            }
+        }
+    }
+
+    /// 移除槽位`hole`上的元素
+    fn remove_at(&mut self, mut hole: usize) -> V {
+        let (_, value) = self.slots[hole].take().unwrap();
+        self.len -= 1;
+        let n = self.slots.len();
+        let mut idx = hole;
+        loop {
+            idx = (idx + 1) % n;
+            let home = match &self.slots[idx] {
+                Some((k, _)) => self.home(k),
+                None => break,
+            };
+            // 如果空出来的位置在这个元素的探测路径上，就把它挪过去
+            let distance_to_hole = (hole + n - home) % n;
+            let distance_to_idx = (idx + n - home) % n;
+            if distance_to_hole < distance_to_idx {
+                self.slots[hole] = self.slots[idx].take();
+                hole = idx;
+            }
+        }
+        value
+    }
+
+    /// 找到键所在的槽位
+    fn find(&self, key: &K) -> Option<usize> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let mut idx = self.home(key);
+        loop {
+            match &self.slots[idx] {
+                Some((k, _)) if k == key => return Some(idx),
+                Some(_) => idx = (idx + 1) % self.slots.len(),
+                None => return None,
+            }
+        }
+    }
+
+    /// 键的初始槽位
+    fn home(&self, key: &K) -> usize {
+        let mut hasher = FibHasher(0);
+        key.hash(&mut hasher);
+        hasher.finish() as usize % self.slots.len()
+    }
+
+    /// 槽位数量翻倍，把所有元素重新放一遍，分配不到内存时返回false
+    fn grow(&mut self) -> bool {
+        let new_len = if self.slots.is_empty() {
+            INITIAL_SLOTS
+        } else {
+            self.slots.len() * 2
+        };
+        let mut slots = Vec::new();
+        if slots.try_reserve_exact(new_len).is_err() {
+            return false;
+        }
+        slots.resize_with(new_len, || None);
+        let old = core::mem::replace(&mut self.slots, slots);
+        for (key, value) in old.into_iter().flatten() {
+            let mut idx = self.home(&key);
+            while self.slots[idx].is_some() {
+                idx = (idx + 1) % new_len;
+            }
+            self.slots[idx] = Some((key, value));
+        }
+        true
+    }
+}
+
+/// 斐波那契散列
+///
+/// 任务的表示通常是对齐过的指针，低位总是零，乘以黄金分割数后取高位，散列得比较均匀
+struct FibHasher(u64);
+
+impl Hasher for FibHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(byte as u64);
+        }
+    }
+    fn write_u64(&mut self, i: u64) {
+        self.0 = (self.0.rotate_left(5) ^ i).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64)
+    }
+    fn finish(&self) -> u64 {
+        self.0 >> 32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TaskTable, INITIAL_SLOTS};
+
+    #[test]
+    fn insert_get_remove() {
+        let mut table = TaskTable::new();
+        assert_eq!(table.get(&1usize), None);
+        assert_eq!(table.insert(1usize, "a"), None);
+        assert_eq!(table.insert(2, "b"), None);
+        assert_eq!(table.insert(1, "c"), None);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&1), Some(&"c"));
+        *table.get_mut(&2).unwrap() = "d";
+        assert_eq!(table.remove(&2), Some("d"));
+        assert_eq!(table.remove(&2), None);
+        assert_eq!(table.len(), 1);
+    }
+
+    /// 元素远多于初始槽位数，删除之后同一条探测链上的元素仍然找得到
+    #[test]
+    fn grow_and_remove_in_chains() {
+        let mut table = TaskTable::new();
+        let count = INITIAL_SLOTS * 10;
+        for i in 0..count {
+            assert_eq!(table.insert(i * 8, i), None);
+        }
+        for i in (0..count).step_by(2) {
+            assert_eq!(table.remove(&(i * 8)), Some(i));
+        }
+        assert_eq!(table.len(), count / 2);
+        for i in 0..count {
+            let expected = if i % 2 == 1 { Some(&i) } else { None };
+            assert_eq!(table.get(&(i * 8)), expected);
+        }
+        assert_eq!(table.iter().count(), count / 2);
+    }
+
+    #[test]
+    fn retain() {
+        let mut table = TaskTable::new();
+        for i in 0..1000usize {
+            table.insert(i, i % 7);
+        }
+        table.retain(|_, v| *v != 3);
+        assert_eq!(table.len(), 1000 - (0..1000).filter(|i| i % 7 == 3).count());
+        assert!(table.iter().all(|(k, v)| k % 7 == *v && *v != 3));
+        for i in 0..1000 {
+            assert_eq!(table.get(&i).is_some(), i % 7 != 3);
+        }
+    }
+}
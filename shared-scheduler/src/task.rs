@@ -24,11 +24,17 @@
 //! 许多的指令集架构存在也是名为“地址空间”的优化方法，来提高页表缓存的访问效率，我们可以用它们实现软件上的地址空间。
 //! 如果具体的处理核上没有实现这种硬件优化，我们只用软件给出“地址空间”的概念，而不在硬件上利用它们。
 use crate::{
-    algorithm::{RingFifoScheduler, Scheduler},
+    algorithm::{PolicyScheduler, SchedPolicy, Scheduler, WithAddressSpace, WithPriority},
     mm::AddressSpaceId,
+    mpmc::MpmcQueue,
+    table::TaskTable,
 };
-use core::ptr::NonNull;
-use spin::Mutex;
+use core::{
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, Ordering},
+};
+pub use shared_abi::{ANY_HART, DEFAULT_PRIORITY, MAX_HARTS, STATS_MAX_ASIDS};
+use spin::{Mutex, MutexGuard};
 
 /// 共享调度器返回的结果
 ///
@@ -48,8 +54,7 @@ pub enum TaskResult {
     ShouldYield(usize),
     /// 调度器里面没有醒着的任务，但存在睡眠任务
     ///
-    /// note: 当调度器返回该值的次数达到阈值的时候，应当执行`kernel_check`系统调用，
-    /// 该系统调用会检查内核，适当将一些任务唤醒。
+    /// 这时候执行器应当等待中断，中断处理函数唤醒任务之后再来查找
     NoWakeTask,
     /// 队列已空，所有任务已经结束
     Finished,
@@ -60,21 +65,110 @@ pub enum TaskResult {
 #[repr(transparent)]
 pub struct TaskRepr(usize);
 
-/// 共享调度器的类型
+/// 共享调度器
 ///
-/// 第二个泛型参数是常量泛型，设置调度器容量大小
-pub type SharedScheduler = Mutex<RingFifoScheduler<TaskMeta, 400>>;
+/// 任务表和就绪队列由锁保护。唤醒任务不会等待锁：唤醒的任务放进无锁的唤醒队列，
+/// 下一个拿到锁的调用者先处理唤醒队列，再做自己的操作。
+/// 这样中断处理函数里的唤醒器不会因为等待锁而自旋，打断了拿着锁的执行器时也不会死锁。
+/// 添加任务需要把结果告诉调用者，会等待锁，不能在中断处理函数里调用
+pub struct SharedScheduler {
+    queue: Mutex<TaskQueue>,
+    wakes: MpmcQueue<TaskRepr, WAKE_QUEUE_CAPACITY>,
+    /// 唤醒队列满了又没抢到锁，下一个拿到锁的调用者唤醒所有睡眠的任务
+    ///
+    /// 多出来的唤醒只会让任务多被轮询一次，不影响正确性
+    wake_all: AtomicBool,
+}
+
+/// 唤醒队列的容量
+const WAKE_QUEUE_CAPACITY: usize = 256;
+
+impl SharedScheduler {
+    /// 创建一个空的共享调度器
+    pub const fn new() -> Self {
+        Self {
+            queue: Mutex::new(TaskQueue::new()),
+            wakes: MpmcQueue::new(),
+            wake_all: AtomicBool::new(false),
+        }
+    }
+
+    /// 获取锁，并处理积攒的添加和唤醒
+    ///
+    /// 没抢到锁时自旋的次数记到统计信息里。唤醒和添加任务的路径不能调用这个函数
+    pub fn lock(&self) -> MutexGuard<TaskQueue> {
+        let mut spins = 0;
+        let mut scheduler = loop {
+            if let Some(scheduler) = self.queue.try_lock() {
+                break scheduler;
+            }
+            spins += 1;
+            core::hint::spin_loop();
+        };
+        scheduler.lock_spins += spins;
+        self.apply_pending(&mut scheduler);
+        scheduler
+    }
+
+    /// 尝试获取锁，锁被别人拿着时立即返回None
+    fn try_lock(&self) -> Option<MutexGuard<TaskQueue>> {
+        let mut scheduler = self.queue.try_lock()?;
+        self.apply_pending(&mut scheduler);
+        Some(scheduler)
+    }
+
+    /// 拿着锁的时候处理积攒的唤醒
+    ///
+    /// 内存不足、放不进就绪队列的唤醒不会丢弃，之后每次拿到锁都重试，见[`TaskQueue::lost_wakes`]
+    fn apply_pending(&self, scheduler: &mut TaskQueue) {
+        while let Some(task_repr) = self.wakes.pop() {
+            scheduler.set_task_state(task_repr, TaskState::Ready);
+        }
+        if self.wake_all.swap(false, Ordering::AcqRel) || scheduler.lost_wakes {
+            scheduler.lost_wakes = false;
+            scheduler.enqueue_all(TaskState::Sleeping);
+        }
+    }
+
+    /// 唤醒一个任务，不会等待锁
+    ///
+    /// 任务在下一次有人拿锁的时候才加到就绪队列，这之前的操作看到的任务仍然是睡眠的
+    fn wake(&self, task_repr: TaskRepr) {
+        if self.wakes.push(task_repr).is_ok() {
+            return;
+        }
+        // 唤醒队列满了，抢到锁就直接唤醒；抢不到就让拿着锁的人唤醒所有任务
+        match self.try_lock() {
+            Some(mut scheduler) => {
+                scheduler.set_task_state(task_repr, TaskState::Ready);
+            }
+            None => self.wake_all.store(true, Ordering::Release),
+        }
+    }
+
+    /// 添加任务，返回是否成功
+    ///
+    /// 等待锁，拿到锁之后才加入调度器，调用者得到的就是添加的结果
+    fn add_task(&self, meta: TaskMeta) -> bool {
+        self.lock().add_task(meta)
+    }
+}
 
 /// 全局的共享调度器
 ///
 /// 放到数据段，内核或用户从这个地址里取得共享调度器
-pub static SHARED_SCHEDULER: SharedScheduler = Mutex::new(RingFifoScheduler::new());
+pub static SHARED_SCHEDULER: SharedScheduler = SharedScheduler::new();
+
+/// 就绪队列中的过期条目超过有效任务数量加上这个数时，添加条目之前先清理一遍
+const STALE_SLACK: usize = 64;
 
 /// 共享任务的元数据
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub struct TaskMeta {
     /// 运行此任务的硬件线程编号
+    ///
+    /// 为[`ANY_HART`]时不绑定硬件线程，否则任务只会在这个硬件线程上运行
     pub(crate) hart_id: usize,
     /// 地址空间的编号
     ///
@@ -84,6 +178,8 @@ pub struct TaskMeta {
     task_repr: TaskRepr,
     /// 任务当前的状态
     pub(crate) state: TaskState,
+    /// 任务的优先级，数值越小优先级越高
+    pub(crate) priority: u8,
 }
 
 /// 任务当前的状态
@@ -99,88 +195,588 @@ pub enum TaskState {
     Sleeping = 1,
 }
 
+/// 共享调度器的统计信息
+///
+/// 内核和用户通过虚函数表中的`scheduler_stats`函数读取，布局是共享调度器接口的一部分。
+/// 计数器从共享调度器初始化开始累计，不会清零
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct SchedulerStats {
+    /// 就绪的任务数
+    pub ready: usize,
+    /// 睡眠的任务数
+    pub sleeping: usize,
+    /// `asids`中有效的项数
+    pub asid_count: usize,
+    /// 各个地址空间的任务数，按地址空间编号排列
+    ///
+    /// 地址空间超过[`STATS_MAX_ASIDS`]个时，只列出其中一部分，总数仍然计入`ready`和`sleeping`
+    pub asids: [AsidStats; STATS_MAX_ASIDS],
+    /// 查找下一个任务的次数
+    pub peeks: usize,
+    /// 返回[`TaskResult::NoWakeTask`]的次数
+    pub no_wake_task: usize,
+    /// 返回[`TaskResult::ShouldYield`]的次数
+    pub should_yield: usize,
+    /// 没有抢到共享调度器的锁、自旋等待的次数
+    pub lock_spins: usize,
+    /// 单个就绪队列中条目数量的峰值，包括还没丢弃的过期条目
+    pub peak_queue_len: usize,
+    /// 按地址空间分时间片时，地址空间切换的次数
+    pub asid_switches: usize,
+}
+
+/// 一个地址空间的任务数
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct AsidStats {
+    pub address_space_id: usize,
+    pub ready: usize,
+    pub sleeping: usize,
+}
+
+/// 就绪队列中的条目
+///
+/// 条目只记录任务在队列中的位置，任务的元数据在任务表中。
+/// 任务睡眠、被删除或者修改优先级之后，旧的条目不会马上从队列中移除，
+/// 而是和任务表中的序号对不上，轮到它的时候直接丢弃
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct QueueItem {
+    task_repr: TaskRepr,
+    seq: usize,
+    priority: u8,
+    address_space_id: AddressSpaceId,
+}
+
+impl WithPriority for QueueItem {
+    fn priority(&self) -> u8 {
+        self.priority
+    }
+    fn set_priority(&mut self, priority: u8) {
+        self.priority = priority;
+    }
+}
+
+impl WithAddressSpace for QueueItem {
+    fn address_space_id(&self) -> AddressSpaceId {
+        self.address_space_id
+    }
+}
+
+/// 任务表中保存的内容
+struct TaskEntry {
+    meta: TaskMeta,
+    /// 任务在就绪队列中有效条目的序号
+    ///
+    /// 睡眠任务的序号不对应任何条目
+    seq: usize,
+    /// 任务所在就绪队列的硬件线程编号
+    ///
+    /// 绑定的任务总在它绑定的硬件线程上，没有绑定的任务被偷走后随之改变
+    home: usize,
+}
+
+type ReadyQueue = PolicyScheduler<QueueItem>;
+
+const EMPTY_READY_QUEUE: ReadyQueue = PolicyScheduler::new();
+
+/// 就绪队列和任务表
+///
+/// 所有任务都在任务表中，只有就绪的任务才在就绪队列中，睡眠的任务只保存在任务表里。
+/// 唤醒任务时把它加到就绪队列的尾部，取下一个任务只需要看就绪队列的头部。
+/// 唤醒、睡眠和删除任务只需要查任务表，时间复杂度为O(1)
+///
+/// 每个硬件线程有自己的就绪队列。自己的队列空了的时候，从其它硬件线程的队列头部偷没有绑定的任务
+pub struct TaskQueue {
+    ready: [ReadyQueue; MAX_HARTS],
+    table: TaskTable<TaskRepr, TaskEntry>,
+    /// 下一个条目的序号
+    next_seq: usize,
+    peeks: usize,
+    no_wake_task: usize,
+    should_yield: usize,
+    lock_spins: usize,
+    peak_queue_len: usize,
+    /// 切换调度算法之前，丢弃的就绪队列累计的地址空间切换次数
+    asid_switches: usize,
+    /// 有唤醒因为内存不足没能把任务放进就绪队列
+    ///
+    /// 这样的任务保持睡眠，下一次拿到锁时重新唤醒所有睡眠的任务，直到都放进就绪队列为止
+    lost_wakes: bool,
+}
+
+impl TaskQueue {
+    /// 创建一个空的调度队列
+    pub const fn new() -> Self {
+        Self {
+            ready: [EMPTY_READY_QUEUE; MAX_HARTS],
+            table: TaskTable::new(),
+            next_seq: 0,
+            peeks: 0,
+            no_wake_task: 0,
+            should_yield: 0,
+            lock_spins: 0,
+            peak_queue_len: 0,
+            asid_switches: 0,
+            lost_wakes: false,
+        }
+    }
+
+    /// 添加任务，成功返回true
+    ///
+    /// 任务已经在调度器中，硬件线程编号不合法，或者共享调度器的堆内存不足的时候返回false
+    ///
+    /// 绑定的任务放到它绑定的硬件线程的队列中，没有绑定的任务放到任务最少的队列中
+    fn add_task(&mut self, meta: TaskMeta) -> bool {
+        let task_repr = meta.task_repr;
+        if self.table.get(&task_repr).is_some() {
+            return false;
+        }
+        let home = match meta.hart_id {
+            ANY_HART => self.least_loaded_hart(),
+            hart_id if hart_id < MAX_HARTS => hart_id,
+            _ => return false,
+        };
+        let is_ready = meta.state == TaskState::Ready;
+        let priority = meta.priority;
+        let seq = self.alloc_seq();
+        if self
+            .table
+            .insert(task_repr, TaskEntry { meta, seq, home })
+            .is_some()
+        {
+            return false;
+        }
+        if is_ready && !self.push_ready(home, task_repr, seq, priority) {
+            self.table.remove(&task_repr);
+            return false;
+        }
+        true
+    }
+
+    /// 切换所有就绪队列使用的调度算法，`quantum`是按地址空间分时间片时的时间片长度，为0时使用默认值
+    ///
+    /// 调度器里有任务的时候也可以切换。原来的就绪队列整个丢弃，释放它们占用的内存，
+    /// 就绪的任务再按任务表加入新的就绪队列，它们原来的先后顺序不保留。
+    /// 和唤醒任务时一样，内存不足放不进新队列的任务保持睡眠
+    pub fn set_policy(&mut self, policy: SchedPolicy, quantum: usize) {
+        self.asid_switches += self
+            .ready
+            .iter()
+            .map(|ready| ready.asid_switches())
+            .sum::<usize>();
+        self.ready = [EMPTY_READY_QUEUE; MAX_HARTS];
+        for ready in self.ready.iter_mut() {
+            ready.set_policy(policy);
+            ready.set_quantum(quantum);
+        }
+        self.enqueue_all(TaskState::Ready);
+    }
+
+    /// 把状态为`state`的任务全部加入就绪队列，设置为就绪
+    ///
+    /// 和唤醒任务时一样，内存不足放不进就绪队列的任务设置为睡眠，之后再重试
+    fn enqueue_all(&mut self, state: TaskState) {
+        let mut next_seq = self.next_seq;
+        for (&task_repr, entry) in self.table.iter_mut() {
+            if entry.meta.state != state {
+                continue;
+            }
+            let item = QueueItem {
+                task_repr,
+                seq: next_seq,
+                priority: entry.meta.priority,
+                address_space_id: entry.meta.address_space_id,
+            };
+            if self.ready[entry.home].add_task(item).is_some() {
+                entry.meta.state = TaskState::Sleeping;
+                self.lost_wakes = true;
+                continue;
+            }
+            entry.meta.state = TaskState::Ready;
+            entry.seq = next_seq;
+            next_seq = next_seq.wrapping_add(1);
+        }
+        self.next_seq = next_seq;
+        for ready in self.ready.iter() {
+            self.peak_queue_len = self.peak_queue_len.max(ready.queue_len().unwrap());
+        }
+    }
+
+    /// 统计任务的数量，和累计的计数器一起返回
+    fn stats(&self) -> SchedulerStats {
+        let mut stats = SchedulerStats {
+            peeks: self.peeks,
+            no_wake_task: self.no_wake_task,
+            should_yield: self.should_yield,
+            lock_spins: self.lock_spins,
+            peak_queue_len: self.peak_queue_len,
+            asid_switches: self.asid_switches
+                + self
+                    .ready
+                    .iter()
+                    .map(|ready| ready.asid_switches())
+                    .sum::<usize>(),
+            ..SchedulerStats::default()
+        };
+        for (_, entry) in self.table.iter() {
+            let asid = entry.meta.address_space_id.into_inner();
+            let count = stats.asid_count;
+            let idx = match stats.asids[..count]
+                .iter()
+                .position(|item| item.address_space_id == asid)
+            {
+                Some(idx) => Some(idx),
+                None if count < STATS_MAX_ASIDS => {
+                    stats.asids[count].address_space_id = asid;
+                    stats.asid_count += 1;
+                    Some(count)
+                }
+                None => None,
+            };
+            let is_ready = entry.meta.state == TaskState::Ready;
+            if is_ready {
+                stats.ready += 1;
+            } else {
+                stats.sleeping += 1;
+            }
+            if let Some(idx) = idx {
+                if is_ready {
+                    stats.asids[idx].ready += 1;
+                } else {
+                    stats.asids[idx].sleeping += 1;
+                }
+            }
+        }
+        stats.asids[..stats.asid_count].sort_unstable_by_key(|item| item.address_space_id);
+        stats
+    }
+
+    /// 找到硬件线程`hart_id`的下一个醒着的任务，不弹出
+    ///
+    /// 先看自己的就绪队列，空了再从其它硬件线程的队列偷任务。
+    /// 硬件线程编号不合法时没有属于它的就绪队列，返回[`TaskResult::NoWakeTask`]，
+    /// 不能让执行器以为所有任务都已经结束
+    fn peek_task(
+        &mut self,
+        hart_id: usize,
+        should_switch: extern "C" fn(AddressSpaceId) -> bool,
+    ) -> TaskResult {
+        if hart_id >= MAX_HARTS {
+            return TaskResult::NoWakeTask;
+        }
+        self.peeks += 1;
+        let item = match self.ready_head(hart_id) {
+            Some(item) => item,
+            None => match self.steal(hart_id) {
+                Some(item) => item,
+                // 没有醒着的任务，但任务表中还有睡眠任务或者绑定到其它硬件线程的任务
+                // 返回[`TaskResult::NoWakeTask`], 提示执行器调度器里面还有睡眠任务
+                // 如果等待时间过长，则下一次时间中断的时候切换地址空间
+                None if self.table.len() != 0 => {
+                    self.no_wake_task += 1;
+                    return TaskResult::NoWakeTask;
+                }
+                // 没有任务了，返回已完成
+                None => return TaskResult::Finished,
+            },
+        };
+        let asid = item.address_space_id;
+        if should_switch(asid) {
+            // 如果需要跳转到其他地址空间，则不弹出任务，返回需要跳转到的地址空间编号
+            self.should_yield += 1;
+            TaskResult::ShouldYield(asid.into_inner())
+        } else {
+            // 直接把任务交给调用者，这时才算任务运行了一次
+            self.ready[hart_id].dispatch(&item);
+            TaskResult::Task(item.task_repr)
+        }
+    }
+
+    /// 把硬件线程`hart_id`就绪队列头部的任务移到队列尾部，队列里没有有效条目时返回false
+    ///
+    /// 在队列尾部加入一个新条目，头部的条目随之过期。队列已满时任务留在原处，返回false
+    fn requeue_task(&mut self, hart_id: usize) -> bool {
+        if hart_id >= MAX_HARTS {
+            return false;
+        }
+        let item = match self.ready_head(hart_id) {
+            Some(item) => item,
+            None => return false,
+        };
+        let seq = self.alloc_seq();
+        if !self.push_ready(hart_id, item.task_repr, seq, item.priority) {
+            return false;
+        }
+        self.table.get_mut(&item.task_repr).unwrap().seq = seq;
+        true
+    }
+
+    /// 硬件线程`hart_id`能运行的就绪任务中，有没有不属于地址空间`asid`的任务
+    ///
+    /// 只读任务表，不丢弃过期的条目，不偷任务，也不计入统计信息。需要遍历任务表，时间复杂度为O(n)
+    fn has_ready_task(&self, hart_id: usize, asid: AddressSpaceId) -> bool {
+        self.table.iter().any(|(_, entry)| {
+            entry.meta.state == TaskState::Ready
+                && entry.meta.address_space_id != asid
+                && (entry.home == hart_id || entry.meta.hart_id == ANY_HART)
+        })
+    }
+
+    /// 得到硬件线程`hart_id`的就绪队列头部的有效条目
+    ///
+    /// 任务已经睡眠、被删除或者换了位置，丢弃过期的条目
+    fn ready_head(&mut self, hart_id: usize) -> Option<QueueItem> {
+        let table = &self.table;
+        let ready = &mut self.ready[hart_id];
+        while let Some(item) = ready.peek_next_task() {
+            let item = *item;
+            match table.get(&item.task_repr) {
+                Some(entry) if entry.seq == item.seq => return Some(item),
+                _ => drop(ready.next_task()),
+            }
+        }
+        None
+    }
+
+    /// 从其它硬件线程的就绪队列偷一个没有绑定的任务，放到`hart_id`的就绪队列
+    ///
+    /// 按顺序扫描各个队列，跳过绑定了硬件线程的任务，顺便丢弃过期的条目。
+    /// 偷到的任务用新的序号加入自己的队列，原来队列里的条目随之过期；
+    /// 内存不足放不进自己的队列时不偷了，原来的条目保持有效
+    fn steal(&mut self, hart_id: usize) -> Option<QueueItem> {
+        for i in 1..MAX_HARTS {
+            let victim = (hart_id + i) % MAX_HARTS;
+            let table = &self.table;
+            let mut stolen = None;
+            self.ready[victim].retain(|item| match table.get(&item.task_repr) {
+                Some(entry) if entry.seq == item.seq => {
+                    if stolen.is_none() && entry.meta.hart_id == ANY_HART {
+                        stolen = Some(*item);
+                    }
+                    true
+                }
+                _ => false,
+            });
+            let item = match stolen {
+                Some(item) => item,
+                None => continue,
+            };
+            let seq = self.alloc_seq();
+            if !self.push_ready(hart_id, item.task_repr, seq, item.priority) {
+                return None;
+            }
+            let entry = self.table.get_mut(&item.task_repr).unwrap();
+            entry.seq = seq;
+            entry.home = hart_id;
+            return Some(QueueItem { seq, ..item });
+        }
+        None
+    }
+
+    /// 就绪队列中条目最少的硬件线程，过期的条目也计算在内
+    fn least_loaded_hart(&self) -> usize {
+        (0..MAX_HARTS)
+            .min_by_key(|&hart_id| self.ready[hart_id].queue_len().unwrap())
+            .unwrap()
+    }
+
+    /// 删除任务，找不到对应的任务返回false
+    ///
+    /// 只从任务表中删除，就绪队列里的条目轮到的时候再丢弃
+    fn delete_task(&mut self, task_repr: TaskRepr) -> bool {
+        self.table.remove(&task_repr).is_some()
+    }
+
+    /// 删除一个地址空间的所有任务，返回删除的任务数
+    ///
+    /// 和删除单个任务一样，就绪队列里的条目轮到的时候再丢弃
+    fn remove_address_space(&mut self, address_space_id: AddressSpaceId) -> usize {
+        let before = self.table.len();
+        self.table
+            .retain(|_, entry| entry.meta.address_space_id != address_space_id);
+        before - self.table.len()
+    }
+
+    /// 设置任务的状态，找不到对应的任务返回false
+    ///
+    /// 睡眠的任务被唤醒时加到就绪队列尾部；就绪的任务睡眠时，它在就绪队列中的条目随之过期。
+    /// 如果就绪队列已满，任务暂时保持睡眠，返回false，之后拿到锁时重试，见[`TaskQueue::lost_wakes`]
+    fn set_task_state(&mut self, task_repr: TaskRepr, new_state: TaskState) -> bool {
+        let seq = self.alloc_seq();
+        let (old_state, priority, home) = match self.table.get(&task_repr) {
+            Some(entry) => (entry.meta.state.clone(), entry.meta.priority, entry.home),
+            None => return false,
+        };
+        match (old_state, &new_state) {
+            (TaskState::Sleeping, TaskState::Ready) => {
+                if !self.push_ready(home, task_repr, seq, priority) {
+                    self.lost_wakes = true;
+                    return false;
+                }
+            }
+            (TaskState::Ready, TaskState::Sleeping) => {}
+            // 状态没有变化
+            _ => return true,
+        }
+        let entry = self.table.get_mut(&task_repr).unwrap();
+        entry.meta.state = new_state;
+        entry.seq = seq;
+        true
+    }
+
+    /// 设置任务的优先级，找不到对应的任务或者新优先级的队列已满返回false
+    ///
+    /// 就绪的任务会在新优先级的队列尾部加入一个条目，原来的条目随之过期
+    fn set_task_priority(&mut self, task_repr: TaskRepr, priority: u8) -> bool {
+        let seq = self.alloc_seq();
+        let (is_ready, home) = match self.table.get(&task_repr) {
+            Some(entry) => (entry.meta.state == TaskState::Ready, entry.home),
+            None => return false,
+        };
+        if is_ready && !self.push_ready(home, task_repr, seq, priority) {
+            return false;
+        }
+        let entry = self.table.get_mut(&task_repr).unwrap();
+        entry.meta.priority = priority;
+        if is_ready {
+            entry.seq = seq;
+        }
+        true
+    }
+
+    /// 把条目加入硬件线程`hart_id`的就绪队列
+    ///
+    /// 队列会随着条目增多而扩容。过期的条目太多，或者内存不足无法扩容的时候，先清理过期的条目
+    fn push_ready(
+        &mut self,
+        hart_id: usize,
+        task_repr: TaskRepr,
+        seq: usize,
+        priority: u8,
+    ) -> bool {
+        let address_space_id = self.table.get(&task_repr).unwrap().meta.address_space_id;
+        let item = QueueItem {
+            task_repr,
+            seq,
+            priority,
+            address_space_id,
+        };
+        let table = &self.table;
+        let ready = &mut self.ready[hart_id];
+        let is_valid = |item: &QueueItem| matches!(table.get(&item.task_repr), Some(entry) if entry.seq == item.seq);
+        // 每次清理之后至少再添加这么多条目才会再次清理，均摊下来添加条目仍然是O(1)的
+        if ready.queue_len().unwrap() > table.len() + STALE_SLACK {
+            ready.retain(is_valid);
+        }
+        let item = match ready.add_task(item) {
+            None => None,
+            Some(item) => {
+                ready.retain(is_valid);
+                ready.add_task(item)
+            }
+        };
+        let queue_len = ready.queue_len().unwrap();
+        self.peak_queue_len = self.peak_queue_len.max(queue_len);
+        item.is_none()
+    }
+
+    fn alloc_seq(&mut self) -> usize {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        seq
+    }
+}
+
 /// 给共享调度器添加任务
 ///
 /// * shared_scheduler: 共享调度器的[`NonNull`]指针
-/// * hard_id: 硬件线程编号
+/// * hard_id: 硬件线程编号，任务将绑定到这个硬件线程上；为[`ANY_HART`]时不绑定
 /// * asid: 任务的地址空间编号
 /// * task_repr: 任务的指针
+/// * priority: 任务的优先级，数值越小优先级越高，通常为[`DEFAULT_PRIORITY`]
 ///
-/// 添加任务成功返回 true,否则返回 false
+/// 任务带着优先级加入调度器，不会先以默认优先级运行。添加任务成功返回 true,否则返回 false。
+/// 调度器的锁被别人拿着时等待锁，不能在中断处理函数里调用
 pub unsafe extern "C" fn shared_add_task(
     shared_scheduler: NonNull<()>,
     hart_id: usize,
     asid: AddressSpaceId,
     task_repr: TaskRepr,
+    priority: u8,
 ) -> bool {
     let s: NonNull<SharedScheduler> = shared_scheduler.cast();
-    let handle = prepare_handle(hart_id, asid, task_repr);
-    let mut scheduler = s.as_ref().lock();
-    scheduler.add_task(handle).is_none()
+    let handle = prepare_handle(hart_id, asid, task_repr, priority);
+    s.as_ref().add_task(handle)
 }
 
 #[inline]
 /// 用于将一些数据打包成[`TaskMeta`]
-unsafe fn prepare_handle(hart_id: usize, asid: AddressSpaceId, task_repr: TaskRepr) -> TaskMeta {
+unsafe fn prepare_handle(
+    hart_id: usize,
+    asid: AddressSpaceId,
+    task_repr: TaskRepr,
+    priority: u8,
+) -> TaskMeta {
     TaskMeta {
         hart_id,
         address_space_id: asid,
         task_repr,
         state: TaskState::Ready, // 默认为就绪状态
+        priority,
     }
 }
 
 /// 从共享调度器中找到下一个任务
 ///
-/// 如果拿出的任务处于睡眠状态则重新放入调度队列尾部
-///
 /// * shared_scheduler: 共享调度器的[`NonNull`]指针
+/// * hart_id: 调用者所在的硬件线程编号
 /// * should_switch: 判断是否需要进行地址空间切换的函数，由使用者给出
 ///
 /// 返回一个[`TaskResult`]，执行器需要根据返回值的类型采取相应的行为
 pub unsafe extern "C" fn shared_peek_task(
     shared_scheduler: NonNull<()>,
+    hart_id: usize,
     should_switch: extern "C" fn(AddressSpaceId) -> bool,
 ) -> TaskResult {
-    print!(""); // 很奇怪的 bug，需要在这里输出点东西运行才会正常
-                // 得到共享调度器的引用
-    let mut s: NonNull<SharedScheduler> = shared_scheduler.cast();
-    let mut scheduler = s.as_mut().lock();
-    let mut ret_task;
-    let mut count = 0; // 计数器，防止无限循环
-    loop {
-        ret_task = scheduler.peek_next_task();
-        match ret_task {
-            Some(task) => {
-                if task.state == TaskState::Sleeping {
-                    if count >= scheduler.queue_len().unwrap() {
-                        // 已经全部遍历过一遍，没有找到醒着的任务
-                        // 返回[`TaskResult::NoWakeTask`], 提示执行器调度器里面还有睡眠任务
-                        // 如果等待时间过长，则下一次时间中断的时候切换地址空间
-                        return TaskResult::NoWakeTask;
-                    }
-                    // 睡眠状态，将当前任务放到调度队列尾部
-                    let sleep_task = scheduler.next_task().unwrap();
-                    let add_ret = scheduler.add_task(sleep_task);
-                    assert!(add_ret.is_none());
-                    count = count.wrapping_add(1);
-                    // 进行下一个循环
-                } else {
-                    if should_switch(task.address_space_id) {
-                        // 如果需要跳转到其他地址空间，则不弹出任务，返回需要跳转到的地址空间编号
-                        return TaskResult::ShouldYield(task.address_space_id.into_inner());
-                    } else {
-                        // 直接把任务交给调用者
-                        let task_repr = task.task_repr;
-                        drop(scheduler); // 释放锁
-                        return TaskResult::Task(task_repr);
-                    }
-                }
-            }
-            // 没有任务了，返回已完成
-            None => return TaskResult::Finished,
-        }
-    }
+    // 得到共享调度器的引用
+    let s: NonNull<SharedScheduler> = shared_scheduler.cast();
+    let mut scheduler = s.as_ref().lock();
+    scheduler.peek_task(hart_id, should_switch)
+}
+
+/// 查看是否有其它地址空间的任务在等待运行
+///
+/// * shared_scheduler: 共享调度器的[`NonNull`]指针
+/// * hart_id: 调用者所在的硬件线程编号
+/// * asid: 当前的地址空间编号
+///
+/// 硬件线程`hart_id`能运行的就绪任务中有不属于`asid`的任务时返回true。
+/// 和[`shared_peek_task`]不同，不会改变调度器的状态，内核在时钟中断时用它判断是否需要抢占
+pub unsafe extern "C" fn shared_has_ready_task(
+    shared_scheduler: NonNull<()>,
+    hart_id: usize,
+    asid: AddressSpaceId,
+) -> bool {
+    let s: NonNull<SharedScheduler> = shared_scheduler.cast();
+    let scheduler = s.as_ref().lock();
+    scheduler.has_ready_task(hart_id, asid)
+}
+
+/// 把就绪队列头部的任务移到队列尾部
+///
+/// * shared_scheduler: 共享调度器的[`NonNull`]指针
+/// * hart_id: 调用者所在的硬件线程编号
+///
+/// [`shared_peek_task`]返回的地址空间暂时切换不过去时调用，让后面的任务先运行。
+/// 队列里没有就绪任务或者队列已满时返回false
+pub unsafe extern "C" fn shared_requeue_task(
+    shared_scheduler: NonNull<()>,
+    hart_id: usize,
+) -> bool {
+    let s: NonNull<SharedScheduler> = shared_scheduler.cast();
+    let mut scheduler = s.as_ref().lock();
+    scheduler.requeue_task(hart_id)
 }
 
 /// 删除一个共享调度器中的任务
@@ -193,38 +789,9 @@ pub unsafe extern "C" fn shared_delete_task(
     shared_scheduler: NonNull<()>,
     task_repr: TaskRepr,
 ) -> bool {
-    let mut s: NonNull<SharedScheduler> = shared_scheduler.cast();
-    let mut scheduler = s.as_mut().lock();
-    let len = scheduler.queue_len().unwrap();
-    let mut count = 0;
-    loop {
-        if count >= len {
-            return false;
-        }
-        let next_handle = scheduler.peek_next_task();
-        match next_handle {
-            Some(task) => {
-                if task.task_repr == task_repr {
-                    // 找到了需要删除的任务
-                    let _drop_task = scheduler.next_task().unwrap();
-                    // 之前已经把 count 个任务从头部拿出来放到尾部了，现在要恢复它们
-                    let current_len = scheduler.queue_len().unwrap();
-                    for _ in 0..(current_len - count) {
-                        let next_task = scheduler.next_task().unwrap();
-                        scheduler.add_task(next_task);
-                    }
-                    return true;
-                } else {
-                    // 把任务从头部拿出来再放队列尾部
-                    let next_task = scheduler.next_task().unwrap();
-                    scheduler.add_task(next_task);
-                    count += 1;
-                    // 进入下一次循环
-                }
-            }
-            None => return false,
-        }
-    }
+    let s: NonNull<SharedScheduler> = shared_scheduler.cast();
+    let mut scheduler = s.as_ref().lock();
+    scheduler.delete_task(task_repr)
 }
 
 /// 设置任务的状态
@@ -233,42 +800,427 @@ pub unsafe extern "C" fn shared_delete_task(
 /// * task_repr: 任务的指针
 /// * new_state: 任务的新状态
 ///
-/// 通常用来唤醒任务
+/// 通常用来唤醒任务。唤醒任务不需要拿锁，任何上下文都可以调用，见[`SharedScheduler`]
 pub unsafe extern "C" fn shared_set_task_state(
     shared_scheduler: NonNull<()>,
     task_repr: TaskRepr,
     new_state: TaskState,
 ) {
-    let mut s: NonNull<SharedScheduler> = shared_scheduler.cast();
-    let mut scheduler = s.as_mut().lock();
-    let len = scheduler.queue_len().unwrap();
-    let mut count = 0;
-    loop {
-        if count >= len {
-            break;
-        }
-        let next_handle = scheduler.peek_next_task();
-        match next_handle {
-            Some(task) => {
-                if task.task_repr == task_repr {
-                    // 找到了需要设置状态的任务
-                    let change_task = scheduler.peek_next_task_mut().unwrap();
-                    change_task.state = new_state;
-                    // 之前已经把count个任务从头部拿出来放到尾部了，现在要恢复它们
-                    for _ in 0..(len - count) {
-                        let next_task = scheduler.next_task().unwrap();
-                        scheduler.add_task(next_task);
-                    }
-                    break;
-                } else {
-                    // 把任务从头部拿出来再放队列尾部
-                    let next_task = scheduler.next_task().unwrap();
-                    scheduler.add_task(next_task);
-                    count += 1;
-                    // 进入下一次循环
-                }
+    let s: NonNull<SharedScheduler> = shared_scheduler.cast();
+    match new_state {
+        TaskState::Ready => s.as_ref().wake(task_repr),
+        TaskState::Sleeping => {
+            let mut scheduler = s.as_ref().lock();
+            scheduler.set_task_state(task_repr, new_state);
+        }
+    }
+}
+
+/// 设置任务的优先级
+///
+/// * shared_scheduler: 共享调度器的[`NonNull`]指针
+/// * task_repr: 任务的指针
+/// * priority: 任务的新优先级，数值越小优先级越高
+///
+/// 任务会被移动到新优先级队列的尾部
+pub unsafe extern "C" fn shared_set_task_priority(
+    shared_scheduler: NonNull<()>,
+    task_repr: TaskRepr,
+    priority: u8,
+) {
+    let s: NonNull<SharedScheduler> = shared_scheduler.cast();
+    let mut scheduler = s.as_ref().lock();
+    scheduler.set_task_priority(task_repr, priority);
+}
+
+/// 删除一个地址空间的所有任务
+///
+/// * shared_scheduler: 共享调度器的[`NonNull`]指针
+/// * asid: 要删除的地址空间编号
+///
+/// 通常在地址空间退出时由内核调用，返回删除的任务数。任务的内存由所在的地址空间自己回收
+pub unsafe extern "C" fn shared_remove_address_space(
+    shared_scheduler: NonNull<()>,
+    asid: AddressSpaceId,
+) -> usize {
+    let s: NonNull<SharedScheduler> = shared_scheduler.cast();
+    let mut scheduler = s.as_ref().lock();
+    scheduler.remove_address_space(asid)
+}
+
+/// 读取共享调度器的统计信息
+///
+/// * shared_scheduler: 共享调度器的[`NonNull`]指针
+/// * stats: 调用者准备好的[`SchedulerStats`]，统计信息写到这里
+pub unsafe extern "C" fn shared_scheduler_stats(
+    shared_scheduler: NonNull<()>,
+    stats: *mut SchedulerStats,
+) {
+    let s: NonNull<SharedScheduler> = shared_scheduler.cast();
+    let scheduler = s.as_ref().lock();
+    stats.write(scheduler.stats());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn asid(asid: u16) -> AddressSpaceId {
+        AddressSpaceId::from_raw(asid)
+    }
+
+    fn repr(n: usize) -> TaskRepr {
+        // 任务的表示通常是对齐过的指针
+        TaskRepr(n * 16)
+    }
+
+    extern "C" fn never_switch(_: AddressSpaceId) -> bool {
+        false
+    }
+
+    extern "C" fn switch_from_kernel(asid: AddressSpaceId) -> bool {
+        asid.into_inner() != 0
+    }
+
+    fn ptr(s: &SharedScheduler) -> NonNull<()> {
+        NonNull::from(s).cast()
+    }
+
+    fn peek(s: &SharedScheduler, hart_id: usize) -> TaskResult {
+        unsafe { shared_peek_task(ptr(s), hart_id, never_switch) }
+    }
+
+    /// 像执行器一样运行硬件线程`hart_id`上的任务，每个任务运行之后删除，返回运行的顺序
+    fn drain(s: &SharedScheduler, hart_id: usize) -> Vec<TaskRepr> {
+        let mut order = Vec::new();
+        while let TaskResult::Task(task_repr) = peek(s, hart_id) {
+            assert!(unsafe { shared_delete_task(ptr(s), task_repr) });
+            order.push(task_repr);
+        }
+        order
+    }
+
+    fn add(s: &SharedScheduler, hart_id: usize, n: usize) -> bool {
+        unsafe { shared_add_task(ptr(s), hart_id, asid(0), repr(n), DEFAULT_PRIORITY) }
+    }
+
+    fn set_state(s: &SharedScheduler, n: usize, state: TaskState) {
+        unsafe { shared_set_task_state(ptr(s), repr(n), state) }
+    }
+
+    #[test]
+    fn finished_when_empty() {
+        let s = SharedScheduler::new();
+        assert!(matches!(peek(&s, 0), TaskResult::Finished));
+        assert!(add(&s, 0, 1));
+        assert!(unsafe { shared_delete_task(ptr(&s), repr(1)) });
+        assert!(matches!(peek(&s, 0), TaskResult::Finished));
+    }
+
+    #[test]
+    fn peek_does_not_pop() {
+        let s = SharedScheduler::new();
+        for n in 1..=3 {
+            assert!(add(&s, 0, n));
+        }
+        assert!(matches!(peek(&s, 0), TaskResult::Task(t) if t == repr(1)));
+        assert!(matches!(peek(&s, 0), TaskResult::Task(t) if t == repr(1)));
+        assert_eq!(drain(&s, 0), [repr(1), repr(2), repr(3)]);
+    }
+
+    #[test]
+    fn add_rejects_duplicates_and_bad_harts() {
+        let s = SharedScheduler::new();
+        assert!(add(&s, 0, 1));
+        assert!(!add(&s, 0, 1));
+        assert!(!add(&s, MAX_HARTS, 2));
+        assert!(!unsafe { shared_delete_task(ptr(&s), repr(2)) });
+        assert!(unsafe { shared_delete_task(ptr(&s), repr(1)) });
+        assert!(!unsafe { shared_delete_task(ptr(&s), repr(1)) });
+    }
+
+    #[test]
+    fn no_wake_task_until_woken() {
+        let s = SharedScheduler::new();
+        for n in 1..=3 {
+            assert!(add(&s, 0, n));
+            set_state(&s, n, TaskState::Sleeping);
+        }
+        assert!(matches!(peek(&s, 0), TaskResult::NoWakeTask));
+        // 唤醒的顺序就是运行的顺序
+        set_state(&s, 3, TaskState::Ready);
+        set_state(&s, 1, TaskState::Ready);
+        assert_eq!(drain(&s, 0), [repr(3), repr(1)]);
+        assert!(matches!(peek(&s, 0), TaskResult::NoWakeTask));
+        set_state(&s, 2, TaskState::Ready);
+        assert_eq!(drain(&s, 0), [repr(2)]);
+        assert!(matches!(peek(&s, 0), TaskResult::Finished));
+    }
+
+    #[test]
+    fn sleep_and_wake_repeatedly() {
+        let s = SharedScheduler::new();
+        for n in 1..=4 {
+            assert!(add(&s, 0, n));
+        }
+        // 重复设置同一个状态不会产生重复的条目
+        set_state(&s, 2, TaskState::Ready);
+        set_state(&s, 1, TaskState::Sleeping);
+        set_state(&s, 1, TaskState::Sleeping);
+        set_state(&s, 1, TaskState::Ready);
+        set_state(&s, 1, TaskState::Ready);
+        assert_eq!(drain(&s, 0), [repr(2), repr(3), repr(4), repr(1)]);
+    }
+
+    #[test]
+    fn wake_after_delete_is_ignored() {
+        let s = SharedScheduler::new();
+        assert!(add(&s, 0, 1));
+        set_state(&s, 1, TaskState::Sleeping);
+        assert!(unsafe { shared_delete_task(ptr(&s), repr(1)) });
+        set_state(&s, 1, TaskState::Ready);
+        assert!(matches!(peek(&s, 0), TaskResult::Finished));
+    }
+
+    #[test]
+    fn wake_queue_overflow() {
+        let s = SharedScheduler::new();
+        let count = WAKE_QUEUE_CAPACITY * 3;
+        for n in 1..=count {
+            assert!(add(&s, 0, n));
+            set_state(&s, n, TaskState::Sleeping);
+        }
+        // 唤醒队列满了之后抢锁直接唤醒，没有任务丢失
+        for n in 1..=count {
+            set_state(&s, n, TaskState::Ready);
+        }
+        assert_eq!(drain(&s, 0).len(), count);
+    }
+
+    #[test]
+    fn wake_overflow_while_locked() {
+        let s = SharedScheduler::new();
+        let count = WAKE_QUEUE_CAPACITY + 10;
+        for n in 1..=count {
+            assert!(add(&s, 0, n));
+            set_state(&s, n, TaskState::Sleeping);
+        }
+        // 拿着锁的时候唤醒，唤醒队列满了也不能等锁，由下一个拿锁的人唤醒所有任务
+        let guard = s.lock();
+        for n in 1..=count {
+            set_state(&s, n, TaskState::Ready);
+        }
+        drop(guard);
+        assert_eq!(drain(&s, 0).len(), count);
+    }
+
+    #[test]
+    fn add_while_locked() {
+        static S: SharedScheduler = SharedScheduler::new();
+        let guard = S.lock();
+        // 锁被别人拿着时等待锁，得到的是真正添加的结果
+        let adding = std::thread::spawn(|| [add(&S, 0, 1), add(&S, 0, 1), add(&S, MAX_HARTS, 2)]);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        drop(guard);
+        assert_eq!(adding.join().unwrap(), [true, false, false]);
+        assert_eq!(drain(&S, 0), [repr(1)]);
+    }
+
+    #[test]
+    fn has_ready_task_is_read_only() {
+        let s = SharedScheduler::new();
+        assert!(unsafe { shared_add_task(ptr(&s), 1, asid(1), repr(1), DEFAULT_PRIORITY) });
+        assert!(unsafe { shared_add_task(ptr(&s), ANY_HART, asid(2), repr(2), DEFAULT_PRIORITY) });
+        let has = |hart_id, id| unsafe { shared_has_ready_task(ptr(&s), hart_id, asid(id)) };
+        // 硬件线程0看不到绑定到硬件线程1的任务
+        assert!(has(0, 1));
+        assert!(!has(0, 2));
+        assert!(has(1, 2));
+        assert_eq!(s.lock().peeks, 0);
+        unsafe { shared_set_task_state(ptr(&s), repr(2), TaskState::Sleeping) };
+        assert!(!has(0, 1));
+        assert!(!has(1, 1));
+    }
+
+    #[test]
+    fn priority_order() {
+        let s = SharedScheduler::new();
+        for n in 1..=3 {
+            assert!(add(&s, 0, n));
+        }
+        unsafe { shared_set_task_priority(ptr(&s), repr(3), 0) };
+        unsafe { shared_set_task_priority(ptr(&s), repr(1), DEFAULT_PRIORITY + 1) };
+        assert_eq!(drain(&s, 0), [repr(3), repr(2), repr(1)]);
+    }
+
+    #[test]
+    fn add_with_priority() {
+        let s = SharedScheduler::new();
+        for n in 1..=2 {
+            assert!(add(&s, 0, n));
+        }
+        // 高优先级的任务一加入就排在前面，不会先以默认优先级排队
+        assert!(unsafe { shared_add_task(ptr(&s), 0, asid(0), repr(3), 0) });
+        assert_eq!(s.lock().table.get(&repr(3)).unwrap().meta.priority, 0);
+        assert_eq!(drain(&s, 0), [repr(3), repr(1), repr(2)]);
+    }
+
+    #[test]
+    fn switch_policy_with_tasks() {
+        let s = SharedScheduler::new();
+        s.lock().set_policy(SchedPolicy::Fifo, 0);
+        for n in 1..=4 {
+            assert!(add(&s, 0, n));
+        }
+        unsafe { shared_set_task_priority(ptr(&s), repr(3), 0) };
+        set_state(&s, 2, TaskState::Sleeping);
+        s.lock().set_policy(SchedPolicy::Priority, 0);
+        // 先进先出时优先级不起作用，切换之后按优先级运行；睡眠的任务仍然睡眠
+        assert_eq!(drain(&s, 0)[0], repr(3));
+        assert!(matches!(peek(&s, 0), TaskResult::NoWakeTask));
+        set_state(&s, 2, TaskState::Ready);
+        assert_eq!(drain(&s, 0), [repr(2)]);
+    }
+
+    #[test]
+    fn should_yield_keeps_task() {
+        let s = SharedScheduler::new();
+        assert!(unsafe { shared_add_task(ptr(&s), 0, asid(2), repr(1), DEFAULT_PRIORITY) });
+        for _ in 0..2 {
+            let result = unsafe { shared_peek_task(ptr(&s), 0, switch_from_kernel) };
+            assert!(matches!(result, TaskResult::ShouldYield(2)));
+        }
+        assert_eq!(drain(&s, 0), [repr(1)]);
+    }
+
+    #[test]
+    fn requeue_skips_head() {
+        let s = SharedScheduler::new();
+        assert!(!unsafe { shared_requeue_task(ptr(&s), 0) });
+        assert!(unsafe { shared_add_task(ptr(&s), 0, asid(2), repr(1), DEFAULT_PRIORITY) });
+        assert!(add(&s, 0, 2));
+        let result = unsafe { shared_peek_task(ptr(&s), 0, switch_from_kernel) };
+        assert!(matches!(result, TaskResult::ShouldYield(2)));
+        // 切换不到地址空间2，先运行后面的内核任务
+        assert!(unsafe { shared_requeue_task(ptr(&s), 0) });
+        let result = unsafe { shared_peek_task(ptr(&s), 0, switch_from_kernel) };
+        assert!(matches!(result, TaskResult::Task(task_repr) if task_repr == repr(2)));
+        assert!(unsafe { shared_delete_task(ptr(&s), repr(2)) });
+        assert_eq!(drain(&s, 0), [repr(1)]);
+        assert!(!unsafe { shared_requeue_task(ptr(&s), MAX_HARTS) });
+    }
+
+    #[test]
+    fn bound_and_stolen_tasks() {
+        let s = SharedScheduler::new();
+        assert!(add(&s, 1, 1));
+        assert!(add(&s, ANY_HART, 2));
+        // 硬件线程0只能偷到没有绑定的任务
+        let stolen = drain(&s, 0);
+        assert!(!stolen.contains(&repr(1)));
+        assert!(matches!(peek(&s, 0), TaskResult::NoWakeTask));
+        let mut rest = drain(&s, 1);
+        rest.extend(stolen);
+        rest.sort_unstable_by_key(|t| t.0);
+        assert_eq!(rest, [repr(1), repr(2)]);
+    }
+
+    #[test]
+    fn steal_past_bound_head() {
+        let s = SharedScheduler::new();
+        // 其它硬件线程的队列里放满过期的条目，让没有绑定的任务排到硬件线程1的绑定任务后面
+        let mut next = 10;
+        for hart_id in (0..MAX_HARTS).filter(|&hart_id| hart_id != 1) {
+            for _ in 0..3 {
+                assert!(add(&s, hart_id, next));
+                set_state(&s, next, TaskState::Sleeping);
+                next += 1;
             }
-            None => break,
         }
+        assert!(add(&s, 1, 1));
+        assert!(add(&s, 1, 2));
+        assert!(add(&s, ANY_HART, 3));
+        assert_eq!(s.lock().table.get(&repr(3)).unwrap().home, 1);
+        assert_eq!(drain(&s, 0), [repr(3)]);
+        assert_eq!(drain(&s, 1), [repr(1), repr(2)]);
+    }
+
+    #[test]
+    fn bad_hart_has_no_task() {
+        let s = SharedScheduler::new();
+        assert!(add(&s, ANY_HART, 1));
+        assert!(matches!(peek(&s, MAX_HARTS), TaskResult::NoWakeTask));
+        assert!(matches!(peek(&s, usize::MAX - 1), TaskResult::NoWakeTask));
+        assert_eq!(drain(&s, 0), [repr(1)]);
+    }
+
+    /// 反复添加、睡眠、唤醒和删除远多于初始容量的任务，就绪队列和任务表都要绕回和扩容
+    #[test]
+    fn wraparound_and_growth() {
+        let s = SharedScheduler::new();
+        let mut next = 1;
+        for round in 0..20 {
+            let first = next;
+            for _ in 0..100 + round * 10 {
+                assert!(add(&s, 0, next));
+                next += 1;
+            }
+            for n in (first..next).step_by(3) {
+                set_state(&s, n, TaskState::Sleeping);
+            }
+            for n in (first..next).step_by(3) {
+                set_state(&s, n, TaskState::Ready);
+            }
+            let order = drain(&s, 0);
+            assert_eq!(order.len(), next - first);
+            let mut sorted = order.clone();
+            sorted.sort_unstable_by_key(|t| t.0);
+            assert_eq!(sorted, (first..next).map(repr).collect::<Vec<_>>());
+            assert!(matches!(peek(&s, 0), TaskResult::Finished));
+        }
+        assert!(s.lock().peak_queue_len <= 2 * 290 + STALE_SLACK);
+    }
+
+    #[test]
+    fn remove_address_space() {
+        let s = SharedScheduler::new();
+        for n in 1..=9 {
+            assert!(unsafe {
+                shared_add_task(ptr(&s), 0, asid((n % 3) as u16), repr(n), DEFAULT_PRIORITY)
+            });
+        }
+        set_state(&s, 4, TaskState::Sleeping);
+        assert_eq!(unsafe { shared_remove_address_space(ptr(&s), asid(1)) }, 3);
+        assert_eq!(unsafe { shared_remove_address_space(ptr(&s), asid(1)) }, 0);
+        let order = drain(&s, 0);
+        assert_eq!(
+            order,
+            [2, 3, 5, 6, 8, 9]
+                .iter()
+                .map(|&n| repr(n))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let s = SharedScheduler::new();
+        for n in 1..=5 {
+            assert!(unsafe {
+                shared_add_task(ptr(&s), 0, asid((n % 2) as u16), repr(n), DEFAULT_PRIORITY)
+            });
+        }
+        set_state(&s, 1, TaskState::Sleeping);
+        let _ = peek(&s, 0);
+        let mut stats = SchedulerStats::default();
+        unsafe { shared_scheduler_stats(ptr(&s), &mut stats) };
+        assert_eq!((stats.ready, stats.sleeping), (4, 1));
+        assert_eq!(stats.asid_count, 2);
+        assert_eq!(stats.asids[0].address_space_id, 0);
+        assert_eq!((stats.asids[0].ready, stats.asids[0].sleeping), (2, 0));
+        assert_eq!(stats.asids[1].address_space_id, 1);
+        assert_eq!((stats.asids[1].ready, stats.asids[1].sleeping), (2, 1));
+        assert_eq!(stats.peeks, 1);
     }
 }
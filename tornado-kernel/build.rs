@@ -20,9 +20,18 @@ fn main() {
 
     println!("cargo:rustc-link-search={}", out_dir.display());
 
+    // 启动代码中的硬件线程数量和内核、共享调度器使用同一个定义
+    let mut entry = fs::File::create(out_dir.join("entry.asm")).unwrap();
+    writeln!(entry, "    .equ MAX_HARTS, {}", shared_abi::MAX_HARTS).unwrap();
+    entry.write_all(include_bytes!("src/entry.asm")).unwrap();
+
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=src/linker.ld");
 
     println!("cargo:rerun-if-changed=src/entry.asm");
     println!("cargo:rerun-if-changed=src/interrupt/interrupt.asm");
+
+    // 共享调度器的调度算法在编译时选择
+    println!("cargo:rerun-if-env-changed=SCHED_POLICY");
+    println!("cargo:rerun-if-env-changed=SCHED_QUANTUM");
 }
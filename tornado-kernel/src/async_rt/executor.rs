@@ -4,7 +4,7 @@ use crate::{
     hart::KernelHartInfo,
     syscall::get_swap_cx,
     task::{KernelTaskRepr, TaskResult},
-    trap::switch_to_user,
+    trap::{switch_to_user, timer::Instant},
 };
 use alloc::sync::Arc;
 use core::{
@@ -13,6 +13,7 @@ use core::{
 };
 #[allow(unused)]
 use riscv::register::sie;
+use riscv::register::sstatus;
 use woke::waker_ref;
 
 /// 内核执行器实现
@@ -20,11 +21,16 @@ use woke::waker_ref;
 /// 如果是当前上下文，就解释运行，如果不是，就切换上下文。
 ///
 /// 切换上下文时，要把上下文保存好，最终还是要回到切换的地方继续运行。
+///
+/// 下一个任务的地址空间正在其它硬件线程上运行时，用`requeue_task`把它移到就绪队列尾部，先运行后面的任务
 pub fn run_until_idle(
     peek_task: impl Fn() -> TaskResult,
     delete_task: impl Fn(usize) -> bool,
     set_task_state: impl Fn(usize, TaskState),
+    requeue_task: impl Fn() -> bool,
 ) {
+    // 因为切换不过去而移到队列尾部的第一个地址空间，再次遇到它说明队列里没有能运行的任务了
+    let mut skipped = None;
     loop {
         // unsafe {
         //     sstatus::set_sie();
@@ -35,6 +41,7 @@ pub fn run_until_idle(
         // println!(">>> kernel executor: next task = {:x?}", task);
         match task {
             TaskResult::Task(task_repr) => {
+                skipped = None;
                 // 在相同的（内核）地址空间里面
                 ext_intr_off();
                 set_task_state(task_repr, TaskState::Sleeping);
@@ -51,9 +58,31 @@ pub fn run_until_idle(
                     ext_intr_off();
                     delete_task(task_repr);
                     ext_intr_on();
+                    // 刚加载完的进程在共享调度器里还没有任务，不会被切换过去，这里主动进入它的地址空间
+                    if let Some(asid) = crate::user::take_loaded_process() {
+                        drop(task);
+                        crate::user::enter_user(asid)
+                    }
                 } // 隐含一个drop(task)
             }
             TaskResult::ShouldYield(next_asid) => {
+                if !KernelHartInfo::try_enter_user(next_asid) {
+                    // 这个地址空间正在其它硬件线程上运行，先运行队列后面的任务
+                    let requeued = skipped != Some(next_asid) && {
+                        ext_intr_off();
+                        let requeued = requeue_task();
+                        ext_intr_on();
+                        requeued
+                    };
+                    if requeued {
+                        skipped = skipped.or(Some(next_asid));
+                    } else {
+                        // 队列里的任务都切换不过去，等到有任务醒来，最迟下一次时钟中断再看
+                        skipped = None;
+                        wait_for_interrupt(|| false);
+                    }
+                    continue;
+                }
                 // 不释放这个任务的内存，执行切换地址空间的系统调用
                 mem::forget(task);
                 let next_satp = KernelHartInfo::user_satp(next_asid).expect("get satp with asid");
@@ -61,7 +90,13 @@ pub fn run_until_idle(
                 switch_to_user(swap_cx, next_satp.inner(), next_asid)
             }
             TaskResult::NoWakeTask => {
-                // 没有醒着的任务，直接跳过
+                // 没有醒着的任务，等待中断把任务唤醒
+                wait_for_interrupt(|| {
+                    ext_intr_off();
+                    let task = peek_task();
+                    ext_intr_on();
+                    !matches!(task, TaskResult::NoWakeTask)
+                })
             }
             TaskResult::Finished => break,
         }
@@ -125,6 +160,26 @@ impl woke::Woke for KernelTaskRepr {
     }
 }
 
+/// 没有可以运行的任务时，让当前硬件线程等待中断
+///
+/// `has_task`在关闭中断、标记为空闲之后再检查一次有没有任务。检查之前设置就绪的任务已经能被看到，
+/// 之后设置就绪的任务会发送处理器间中断；本地的中断挂起着，`wfi`会马上返回，
+/// 重新打开中断之后再处理。等待的时间计入[`KernelHartInfo::idle_time`]
+pub fn wait_for_interrupt(has_task: impl FnOnce() -> bool) {
+    let enabled = sstatus::read().sie();
+    unsafe { sstatus::clear_sie() };
+    KernelHartInfo::set_idle(true);
+    if !has_task() {
+        let start = Instant::now();
+        unsafe { riscv::asm::wfi() };
+        KernelHartInfo::add_idle_time(Instant::now().duration_since(start));
+    }
+    KernelHartInfo::set_idle(false);
+    if enabled {
+        unsafe { sstatus::set_sie() };
+    }
+}
+
 /// 打开外部中断
 pub fn ext_intr_on() {
     #[cfg(feature = "qemu")]
@@ -1,6 +1,6 @@
 //! 内核异步运行时实现
 //!
-//! 目前包含共享调度器实例化和内核执行器两个模块。
+//! 目前包含共享调度器实例化、内核执行器和定时器三个模块。
 //!
 //! Rust异步运行时是不包含在标准库里面的，交给社区贡献者实现，通常包含以下几个方面：
 //!
@@ -13,6 +13,13 @@
 //! 在中断处理函数或者系统调用处理函数里面存在任务唤醒机制。
 mod executor;
 mod shared;
+mod timer;
 
-pub use executor::{ext_intr_off, ext_intr_on, run_one, run_until_idle};
-pub use shared::{kernel_should_switch, SharedPayload, TaskState};
+pub use executor::{ext_intr_off, ext_intr_on, run_one, run_until_idle, wait_for_interrupt};
+pub use shared::{
+    kernel_should_switch, PayloadError, SchedPolicy, SchedulerStats, SharedPayload, TaskState,
+};
+pub use timer::{
+    add_timer, cancel_timers, sleep, sleep_until, timeout, wake_expired_timers, Elapsed, Sleep,
+    Timeout,
+};
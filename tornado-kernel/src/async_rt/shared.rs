@@ -1,5 +1,12 @@
-use crate::{hart::KernelHartInfo, memory::AddressSpaceId, task::TaskResult};
+use crate::{
+    hart::KernelHartInfo,
+    memory::AddressSpaceId,
+    task::{KernelTaskRepr, TaskResult},
+};
+use alloc::sync::Arc;
 use core::{mem, ptr::NonNull};
+pub use shared_abi::{DEFAULT_PRIORITY, STATS_MAX_ASIDS};
+use shared_abi::{SHARED_ABI_VERSION, SHARED_PAYLOAD_MAGIC};
 
 /// 任务当前的状态
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -14,83 +21,195 @@ pub extern "C" fn kernel_should_switch(address_space_id: AddressSpaceId) -> bool
     KernelHartInfo::current_address_space_id() != address_space_id
 }
 
+/// 共享调度器的统计信息，布局和共享调度器中的定义一致
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct SchedulerStats {
+    /// 就绪的任务数
+    pub ready: usize,
+    /// 睡眠的任务数
+    pub sleeping: usize,
+    /// `asids`中有效的项数
+    pub asid_count: usize,
+    /// 各个地址空间的任务数，按地址空间编号排列
+    pub asids: [AsidStats; STATS_MAX_ASIDS],
+    /// 查找下一个任务的次数
+    pub peeks: usize,
+    /// 返回`NoWakeTask`的次数
+    pub no_wake_task: usize,
+    /// 返回`ShouldYield`的次数
+    pub should_yield: usize,
+    /// 没有抢到共享调度器的锁、自旋等待的次数
+    pub lock_spins: usize,
+    /// 单个就绪队列中条目数量的峰值
+    pub peak_queue_len: usize,
+    /// 按地址空间分时间片时，地址空间切换的次数
+    pub asid_switches: usize,
+}
+
+/// 一个地址空间的任务数
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct AsidStats {
+    pub address_space_id: usize,
+    pub ready: usize,
+    pub sleeping: usize,
+}
+
+/// 共享调度器的调度算法，数值和共享调度器中的定义一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum SchedPolicy {
+    /// 多级优先级队列
+    Priority = 0,
+    /// 先进先出轮转
+    Fifo = 1,
+    /// 按地址空间轮转
+    AsidRoundRobin = 2,
+    /// 彩票调度
+    Lottery = 3,
+    /// 按地址空间分时间片
+    AsidFair = 4,
+}
+
+impl SchedPolicy {
+    /// 从名字得到调度算法，可以是`priority`、`fifo`、`asid-rr`、`lottery`或`asid-fair`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "priority" => Some(SchedPolicy::Priority),
+            "fifo" => Some(SchedPolicy::Fifo),
+            "asid-rr" => Some(SchedPolicy::AsidRoundRobin),
+            "lottery" => Some(SchedPolicy::Lottery),
+            "asid-fair" => Some(SchedPolicy::AsidFair),
+            _ => None,
+        }
+    }
+}
+
 /// 共享调度器
 #[repr(C)]
 pub struct SharedPayload {
     pub(crate) shared_scheduler: NonNull<()>,
-    shared_add_task: unsafe extern "C" fn(NonNull<()>, usize, AddressSpaceId, usize) -> bool,
-    shared_peek_task:
-        unsafe extern "C" fn(NonNull<()>, extern "C" fn(AddressSpaceId) -> bool) -> TaskResult,
+    payload_init: InitFunction,
+    shared_add_task: unsafe extern "C" fn(NonNull<()>, usize, AddressSpaceId, usize, u8) -> bool,
+    shared_peek_task: unsafe extern "C" fn(
+        NonNull<()>,
+        usize,
+        extern "C" fn(AddressSpaceId) -> bool,
+    ) -> TaskResult,
     shared_delete_task: unsafe extern "C" fn(NonNull<()>, usize) -> bool,
     pub(crate) shared_set_task_state: unsafe extern "C" fn(NonNull<()>, usize, TaskState),
+    shared_scheduler_stats: unsafe extern "C" fn(NonNull<()>, *mut SchedulerStats),
+    shared_remove_address_space: unsafe extern "C" fn(NonNull<()>, AddressSpaceId) -> usize,
+    shared_has_ready_task: unsafe extern "C" fn(NonNull<()>, usize, AddressSpaceId) -> bool,
+    shared_requeue_task: unsafe extern "C" fn(NonNull<()>, usize) -> bool,
 }
 
 unsafe impl Send for SharedPayload {}
 unsafe impl Sync for SharedPayload {}
 
-type SharedPayloadAsUsize = [usize; 7]; // 编译时基地址，初始化函数，共享调度器地址，添加函数，弹出函数
-type InitFunction = unsafe extern "C" fn() -> PageList;
-type SharedPayloadRaw = (
-    usize, // 编译时基地址，转换后类型占位，不使用
-    usize, // 初始化函数，执行完之后，内核将函数指针置空
-    NonNull<()>,
-    unsafe extern "C" fn(NonNull<()>, usize, AddressSpaceId, usize) -> bool, // 添加任务
-    unsafe extern "C" fn(NonNull<()>, extern "C" fn(AddressSpaceId) -> bool) -> TaskResult, // 弹出任务
-    unsafe extern "C" fn(NonNull<()>, usize) -> bool, // 删除任务
-    unsafe extern "C" fn(NonNull<()>, usize, TaskState), // 改变任务的状态
-);
+type InitFunction = unsafe extern "C" fn(usize, usize) -> PageList;
+
+/// 共享调度器中的虚函数表，布局和共享调度器中的`SharedRawTable`一致
+///
+/// 地址都是共享调度器编译时的地址，加载时需要加上偏移量
+#[repr(C)]
+struct SharedPayloadRaw {
+    magic: usize,
+    version: usize,
+    size: usize,
+    compiled_base: usize,
+    init: usize,
+    shared_scheduler: usize,
+    add_task: usize,
+    peek_task: usize,
+    delete_task: usize,
+    set_task_state: usize,
+    set_task_priority: usize, // 内核添加任务时直接指定优先级，不需要单独设置
+    scheduler_stats: usize,
+    remove_address_space: usize,
+    has_ready_task: usize,
+    requeue_task: usize,
+}
+
+/// 加载共享调度器时发生的错误
+#[derive(Debug)]
+pub enum PayloadError {
+    /// 基地址上的魔数不对，可能没有烧录共享调度器
+    BadMagic(usize),
+    /// 共享调度器的版本号和内核不一致
+    VersionMismatch { expected: usize, found: usize },
+    /// 虚函数表的大小和内核不一致
+    SizeMismatch { expected: usize, found: usize },
+}
 
 impl SharedPayload {
     /// 根据基地址加载共享调度器
     ///
+    /// 先检查虚函数表的魔数、版本号和大小，和内核不一致时返回错误
+    ///
     /// # Example:
     ///
     /// ```
     /// # const BASE: usize = 0x8600_000;
-    /// let shared_load = unsafe { SharedPayload::load(BASE); }
+    /// let shared_load = unsafe { SharedPayload::load(BASE) }.expect("load shared payload");
     /// ```
-    pub unsafe fn load(base: usize) -> Self {
-        let mut payload_usize = *(base as *const SharedPayloadAsUsize);
-        // println!(
-        //     "[kernel:shared] Raw table base: {:p}",
-        //     base as *const SharedPayloadAsUsize
-        // );
-        // println!("[kernel:shared] Content: {:x?}", payload_usize);
-        let compiled_offset = payload_usize[0];
-        for (i, idx) in payload_usize.iter_mut().enumerate() {
-            if i == 0 {
-                continue;
-            }
-            *idx = idx.wrapping_sub(compiled_offset).wrapping_add(base);
-            if *idx == 0 {
-                panic!("shared scheduler used effective address of zero")
-            }
+    pub unsafe fn load(base: usize) -> Result<Self, PayloadError> {
+        let raw = &*(base as *const SharedPayloadRaw);
+        if raw.magic != SHARED_PAYLOAD_MAGIC {
+            return Err(PayloadError::BadMagic(raw.magic));
         }
-        // println!("[kernel:shared] After patched: {:x?}", payload_usize);
-        let payload_init: InitFunction = mem::transmute(payload_usize[1]);
-        let _page_list = payload_init(); // 初始化载荷，包括零初始化段的清零等等
-        payload_usize[1] = 0; // 置空初始化函数
-                              // println!("[kernel:shared] Init, page list: {:x?}", page_list); // 应当在分页系统中使用上，本次比赛设计暂时不深入
-        let raw_table: SharedPayloadRaw = mem::transmute(payload_usize);
-        Self {
-            shared_scheduler: raw_table.2,
-            shared_add_task: raw_table.3,
-            shared_peek_task: raw_table.4,
-            shared_delete_task: raw_table.5,
-            shared_set_task_state: raw_table.6,
+        if raw.version != SHARED_ABI_VERSION {
+            return Err(PayloadError::VersionMismatch {
+                expected: SHARED_ABI_VERSION,
+                found: raw.version,
+            });
         }
+        if raw.size != mem::size_of::<SharedPayloadRaw>() {
+            return Err(PayloadError::SizeMismatch {
+                expected: mem::size_of::<SharedPayloadRaw>(),
+                found: raw.size,
+            });
+        }
+        let relocate = |addr: usize| {
+            let addr = addr.wrapping_sub(raw.compiled_base).wrapping_add(base);
+            if addr == 0 {
+                panic!("shared scheduler used effective address of zero")
+            }
+            addr
+        };
+        Ok(Self {
+            shared_scheduler: NonNull::new_unchecked(relocate(raw.shared_scheduler) as *mut ()),
+            payload_init: mem::transmute(relocate(raw.init)),
+            shared_add_task: mem::transmute(relocate(raw.add_task)),
+            shared_peek_task: mem::transmute(relocate(raw.peek_task)),
+            shared_delete_task: mem::transmute(relocate(raw.delete_task)),
+            shared_set_task_state: mem::transmute(relocate(raw.set_task_state)),
+            shared_scheduler_stats: mem::transmute(relocate(raw.scheduler_stats)),
+            shared_remove_address_space: mem::transmute(relocate(raw.remove_address_space)),
+            shared_has_ready_task: mem::transmute(relocate(raw.has_ready_task)),
+            shared_requeue_task: mem::transmute(relocate(raw.requeue_task)),
+        })
+    }
+
+    /// 初始化共享调度器的运行环境，包括零初始化段的清零、堆的初始化、选择调度算法和时间片长度
+    ///
+    /// 只能在内核启动时运行一次，再次运行会清空共享调度器中的所有任务
+    pub unsafe fn init_environment(&self, policy: SchedPolicy, quantum: usize) {
+        let f = self.payload_init;
+        let _page_list = f(policy as usize, quantum); // 应当在分页系统中使用上，本次比赛设计暂时不深入
     }
 
-    /// 往共享调度器中添加任务
+    /// 往共享调度器中添加任务，任务一加入就使用优先级`priority`
     ///
     /// # Example:
     ///
     /// ```
     /// # const BASE: usize = 0x8600_000;
     /// unsafe {
-    ///     let shared_load = SharedPayload::new(BASE);
+    ///     let shared_load = SharedPayload::load(BASE).unwrap();
     ///     let asid = AddressSpaceId::from_raw(0);
-    ///     shared_load.add_task(0, asid, task.task_repr());
+    ///     shared_load.add_task(0, asid, task.task_repr(), DEFAULT_PRIORITY);
     /// }
     /// ```
     pub unsafe fn add_task(
@@ -98,13 +217,52 @@ impl SharedPayload {
         hart_id: usize,
         address_space_id: AddressSpaceId,
         task_repr: usize,
+        priority: u8,
     ) -> bool {
         let f = self.shared_add_task;
-        // hart_id, address_space_id, task_repr);
-        f(self.shared_scheduler, hart_id, address_space_id, task_repr)
+        f(
+            self.shared_scheduler,
+            hart_id,
+            address_space_id,
+            task_repr,
+            priority,
+        )
     }
 
-    /// 从共享调度器中得到下一个任务
+    /// 把一个内核任务交给共享调度器，使用默认优先级，成功时返回任务的表示
+    ///
+    /// 共享调度器的队列放在共享载荷的堆上，堆内存耗尽时会拒绝新任务。
+    /// 这时任务的引用计数被收回，原样返回给调用者，由调用者决定是报错、稍后重试还是丢弃
+    pub unsafe fn add_kernel_task(
+        &self,
+        hart_id: usize,
+        address_space_id: AddressSpaceId,
+        task: Arc<KernelTaskRepr>,
+    ) -> Result<usize, Arc<KernelTaskRepr>> {
+        self.add_kernel_task_with_priority(hart_id, address_space_id, task, DEFAULT_PRIORITY)
+    }
+
+    /// 把一个指定优先级的内核任务交给共享调度器，见[`SharedPayload::add_kernel_task`]
+    ///
+    /// 优先级和任务一起交给共享调度器，任务不会先以默认优先级排队
+    pub unsafe fn add_kernel_task_with_priority(
+        &self,
+        hart_id: usize,
+        address_space_id: AddressSpaceId,
+        task: Arc<KernelTaskRepr>,
+        priority: u8,
+    ) -> Result<usize, Arc<KernelTaskRepr>> {
+        let task_repr = task.task_repr();
+        if self.add_task(hart_id, address_space_id, task_repr, priority) {
+            Ok(task_repr)
+        } else {
+            Err(Arc::from_raw(task_repr as *const KernelTaskRepr))
+        }
+    }
+
+    /// 从共享调度器中得到硬件线程`hart_id`的下一个任务
+    ///
+    /// 自己的队列中没有任务时，共享调度器会从其它硬件线程偷没有绑定的任务
     ///
     /// # Example:
     ///
@@ -113,10 +271,11 @@ impl SharedPayload {
     /// ```
     pub unsafe fn peek_task(
         &self,
+        hart_id: usize,
         should_yield: extern "C" fn(AddressSpaceId) -> bool,
     ) -> TaskResult {
         let f = self.shared_peek_task;
-        f(self.shared_scheduler, should_yield)
+        f(self.shared_scheduler, hart_id, should_yield)
     }
 
     /// 从共享调度器中删除任务
@@ -133,6 +292,8 @@ impl SharedPayload {
 
     /// 设置一个任务的状态
     ///
+    /// 任务变为就绪时叫醒正在等待中断的其它硬件线程
+    ///
     /// # Example:
     ///
     /// ```
@@ -140,7 +301,50 @@ impl SharedPayload {
     /// ```
     pub unsafe fn set_task_state(&self, task_repr: usize, new_state: TaskState) {
         let f = self.shared_set_task_state;
-        f(self.shared_scheduler, task_repr, new_state)
+        let ready = new_state == TaskState::Ready;
+        f(self.shared_scheduler, task_repr, new_state);
+        if ready {
+            KernelHartInfo::notify_idle_harts();
+        }
+    }
+
+    /// 读取共享调度器的统计信息
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// let stats = unsafe { shared_load.stats() };
+    /// println!("ready: {}, sleeping: {}", stats.ready, stats.sleeping);
+    /// ```
+    pub unsafe fn stats(&self) -> SchedulerStats {
+        let f = self.shared_scheduler_stats;
+        let mut stats = SchedulerStats::default();
+        f(self.shared_scheduler, &mut stats);
+        stats
+    }
+
+    /// 删除一个地址空间在共享调度器中的所有任务，返回删除的任务数
+    ///
+    /// 用户程序退出时调用，之后共享调度器不会再让执行器切换到这个地址空间
+    pub unsafe fn remove_address_space(&self, address_space_id: AddressSpaceId) -> usize {
+        let f = self.shared_remove_address_space;
+        f(self.shared_scheduler, address_space_id)
+    }
+
+    /// 硬件线程`hart_id`能运行的就绪任务中，有没有不属于地址空间`address_space_id`的任务
+    ///
+    /// 不改变共享调度器的状态，时钟中断判断是否抢占用户程序时使用
+    pub unsafe fn has_ready_task(&self, hart_id: usize, address_space_id: AddressSpaceId) -> bool {
+        let f = self.shared_has_ready_task;
+        f(self.shared_scheduler, hart_id, address_space_id)
+    }
+
+    /// 把硬件线程`hart_id`就绪队列头部的任务移到队列尾部，队列里没有就绪任务时返回false
+    ///
+    /// 执行器切换不到头部任务的地址空间时使用，先运行后面的任务
+    pub unsafe fn requeue_task(&self, hart_id: usize) -> bool {
+        let f = self.shared_requeue_task;
+        f(self.shared_scheduler, hart_id)
     }
 }
 
@@ -0,0 +1,446 @@
+//! 内核定时器
+//!
+//! 等待时间的任务把唤醒器登记到定时器队列，时钟中断到来时唤醒所有到期的任务。
+//! 唤醒通过共享调度器的`set_task_state`完成，任务醒来之后由执行器重新运行。
+//!
+//! 定时器在时钟中断里检查，精度是时钟中断的间隔
+use crate::{memory::AddressSpaceId, trap::timer::Instant};
+use alloc::{collections::BinaryHeap, vec::Vec};
+use core::{
+    cmp::Reverse,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use lazy_static::lazy_static;
+use riscv::register::sie;
+use spin::Mutex;
+
+/// 定时器队列
+///
+/// 中断处理函数里不能分配或释放堆内存，被打断的代码可能正拿着堆分配器的锁。
+/// 所以到期时只从堆中弹出条目、取出唤醒器，添加定时器时预留好空闲列表的容量。
+/// 定时器取消的时候只清空槽位，堆中的条目到期时发现代数对不上就丢弃。
+/// 这样的条目比正在等待的定时器还多时，登记新的定时器之前把它们从堆中清理掉
+struct TimerQueue {
+    /// 按到期时间排列的小根堆，条目为到期时间、槽位下标和槽位的代数
+    heap: BinaryHeap<Reverse<(Instant, usize, usize)>>,
+    /// 定时器槽位
+    slots: Vec<Slot>,
+    /// 空闲槽位的下标，容量总是不小于槽位的数量
+    free: Vec<usize>,
+}
+
+struct Slot {
+    /// 槽位每次到期或者取消之后加一，区分先后使用这个槽位的定时器
+    generation: usize,
+    waker: Option<Waker>,
+    /// 登记定时器的用户地址空间编号，内核自己的定时器为None
+    owner: Option<usize>,
+}
+
+/// 登记在定时器队列中的定时器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TimerHandle {
+    index: usize,
+    generation: usize,
+}
+
+impl TimerQueue {
+    fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// 登记一个内核的定时器，到期时唤醒`waker`
+    fn register(&mut self, deadline: Instant, waker: Waker) -> TimerHandle {
+        self.register_owned(deadline, None, waker)
+    }
+
+    /// 登记一个属于`owner`地址空间的定时器，到期时唤醒`waker`
+    fn register_owned(
+        &mut self,
+        deadline: Instant,
+        owner: Option<usize>,
+        waker: Waker,
+    ) -> TimerHandle {
+        if self.heap.len() > 2 * self.live() {
+            self.compact();
+        }
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    waker: None,
+                    owner: None,
+                });
+                // 保证中断处理函数里放回空闲槽位时不需要分配内存
+                self.free.reserve(self.slots.len());
+                self.slots.len() - 1
+            }
+        };
+        let slot = &mut self.slots[index];
+        slot.waker = Some(waker);
+        slot.owner = owner;
+        let generation = slot.generation;
+        self.heap.push(Reverse((deadline, index, generation)));
+        TimerHandle { index, generation }
+    }
+
+    /// 正在等待的定时器个数
+    fn live(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    /// 丢弃堆中已经取消的定时器的条目
+    fn compact(&mut self) {
+        let slots = &self.slots;
+        let mut entries = core::mem::take(&mut self.heap).into_vec();
+        entries.retain(|Reverse((_, index, generation))| slots[*index].generation == *generation);
+        self.heap = BinaryHeap::from(entries);
+    }
+
+    /// 定时器登记的唤醒器，定时器已经到期或者取消时返回None
+    fn waker(&self, handle: TimerHandle) -> Option<&Waker> {
+        let slot = &self.slots[handle.index];
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.waker.as_ref()
+    }
+
+    /// 取消定时器，返回定时器的唤醒器
+    fn cancel(&mut self, handle: TimerHandle) -> Option<Waker> {
+        self.release(handle.index, handle.generation)
+    }
+
+    /// 取消`owner`地址空间登记的所有定时器，返回它们的唤醒器
+    fn cancel_owned(&mut self, owner: usize) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        for index in 0..self.slots.len() {
+            let slot = &self.slots[index];
+            if slot.owner == Some(owner) && slot.waker.is_some() {
+                let generation = slot.generation;
+                wakers.extend(self.release(index, generation));
+            }
+        }
+        wakers
+    }
+
+    /// 清空一个槽位并放回空闲列表，代数不对时说明槽位已经被释放过
+    fn release(&mut self, index: usize, generation: usize) -> Option<Waker> {
+        let slot = &mut self.slots[index];
+        if slot.generation != generation {
+            return None;
+        }
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(index);
+        slot.waker.take()
+    }
+
+    /// 弹出一个在`now`之前到期的定时器，返回它的唤醒器
+    fn pop_expired(&mut self, now: Instant) -> Option<Waker> {
+        while let Some(Reverse((deadline, index, generation))) = self.heap.peek().copied() {
+            if deadline > now {
+                return None;
+            }
+            self.heap.pop();
+            if let Some(waker) = self.release(index, generation) {
+                return Some(waker);
+            }
+        }
+        None
+    }
+}
+
+lazy_static! {
+    static ref TIMERS: Mutex<TimerQueue> = Mutex::new(TimerQueue::new());
+}
+
+/// 关闭时钟中断，操作定时器队列
+///
+/// 时钟中断处理函数也要操作定时器队列，拿着锁的时候不能被它打断。
+/// 释放唤醒器可能连带释放任务和任务里的其它定时器，所以唤醒器都拿到锁外面再释放
+fn with_timers<T>(f: impl FnOnce(&mut TimerQueue) -> T) -> T {
+    let stimer = sie::read().stimer();
+    unsafe { sie::clear_stimer() };
+    let ans = f(&mut TIMERS.lock());
+    if stimer {
+        unsafe { sie::set_stimer() };
+    }
+    ans
+}
+
+/// 唤醒所有到期的任务，在时钟中断处理函数里调用
+pub fn wake_expired_timers() {
+    let now = Instant::now();
+    while let Some(waker) = with_timers(|timers| timers.pop_expired(now)) {
+        // 到期的定时器还被它的`Sleep`拥有，这里不会释放任务的内存
+        waker.wake();
+    }
+}
+
+/// 为`owner`地址空间登记一个定时器，到`deadline`时刻唤醒`waker`
+///
+/// 给不在内核里等待的任务使用，比如睡眠中的用户任务。`waker`在时钟中断里唤醒和释放，
+/// 不能在释放时回收堆内存。地址空间卸载时用[`cancel_timers`]取消它还没有到期的定时器
+pub fn add_timer(deadline: Instant, owner: AddressSpaceId, waker: Waker) {
+    with_timers(|timers| timers.register_owned(deadline, Some(owner.into_inner()), waker));
+}
+
+/// 取消`owner`地址空间登记的所有定时器，返回取消的个数
+///
+/// 卸载用户程序时调用，之后这个地址空间编号可能分配给别的程序，旧的定时器不能再唤醒任务
+pub fn cancel_timers(owner: AddressSpaceId) -> usize {
+    let wakers = with_timers(|timers| timers.cancel_owned(owner.into_inner()));
+    wakers.len()
+}
+
+/// 睡眠`duration`这么长的时间
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// 睡眠到`deadline`时刻
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        handle: None,
+    }
+}
+
+/// [`sleep`]和[`sleep_until`]返回的future
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Instant,
+    handle: Option<TimerHandle>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let (deadline, handle) = (self.deadline, self.handle.take());
+        let (handle, old_waker) = with_timers(|timers| {
+            if Instant::now() >= deadline {
+                // 时间到了，定时器还没在中断里到期的话顺便取消
+                return (None, handle.and_then(|handle| timers.cancel(handle)));
+            }
+            match handle {
+                Some(handle)
+                    if timers
+                        .waker(handle)
+                        .map_or(false, |waker| waker.will_wake(cx.waker())) =>
+                {
+                    (Some(handle), None)
+                }
+                // 第一次轮询，或者任务换了唤醒器，重新登记
+                _ => {
+                    let old_waker = handle.and_then(|handle| timers.cancel(handle));
+                    let handle = timers.register(deadline, cx.waker().clone());
+                    (Some(handle), old_waker)
+                }
+            }
+        });
+        drop(old_waker);
+        self.handle = handle;
+        match handle {
+            Some(_) => Poll::Pending,
+            None => Poll::Ready(()),
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            let waker = with_timers(|timers| timers.cancel(handle));
+            drop(waker);
+        }
+    }
+}
+
+/// 给`future`加上时间限制，超过`duration`还没有完成就返回[`Elapsed`]
+pub fn timeout<F: Future>(future: F, duration: Duration) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
+
+/// [`timeout`]超时返回的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// [`timeout`]返回的future
+#[derive(Debug)]
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // note(unsafe): `future`不会被移出`Timeout`，`sleep`不需要固定
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimerQueue;
+    use crate::trap::timer::Instant;
+    use core::{
+        sync::atomic::{AtomicUsize, Ordering},
+        task::{RawWaker, RawWakerVTable, Waker},
+    };
+
+    /// 唤醒时把`count`加一的唤醒器
+    fn counting_waker(count: &'static AtomicUsize) -> Waker {
+        fn clone(data: *const ()) -> RawWaker {
+            RawWaker::new(data, &VTABLE)
+        }
+        fn wake(data: *const ()) {
+            unsafe { &*(data as *const AtomicUsize) }.fetch_add(1, Ordering::Relaxed);
+        }
+        fn drop(_data: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+        unsafe { Waker::from_raw(RawWaker::new(count as *const _ as *const (), &VTABLE)) }
+    }
+
+    fn at(ms: usize) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    /// 弹出`now`之前到期的所有定时器并唤醒，返回唤醒的个数
+    fn wake_expired(timers: &mut TimerQueue, now: Instant) -> usize {
+        let mut n = 0;
+        while let Some(waker) = timers.pop_expired(now) {
+            waker.wake();
+            n += 1;
+        }
+        n
+    }
+
+    #[test]
+    fn expire_in_deadline_order() {
+        static A: AtomicUsize = AtomicUsize::new(0);
+        static B: AtomicUsize = AtomicUsize::new(0);
+        static C: AtomicUsize = AtomicUsize::new(0);
+        let mut timers = TimerQueue::new();
+        timers.register(at(30), counting_waker(&A));
+        timers.register(at(10), counting_waker(&B));
+        timers.register(at(20), counting_waker(&C));
+        assert!(timers.free.capacity() >= timers.slots.len());
+        assert_eq!(wake_expired(&mut timers, at(5)), 0);
+        assert_eq!(wake_expired(&mut timers, at(20)), 2);
+        assert_eq!(
+            (
+                A.load(Ordering::Relaxed),
+                B.load(Ordering::Relaxed),
+                C.load(Ordering::Relaxed)
+            ),
+            (0, 1, 1)
+        );
+        assert_eq!(wake_expired(&mut timers, at(30)), 1);
+        assert_eq!(A.load(Ordering::Relaxed), 1);
+        assert_eq!(wake_expired(&mut timers, at(100)), 0);
+    }
+
+    #[test]
+    fn cancel_before_expire() {
+        static A: AtomicUsize = AtomicUsize::new(0);
+        let mut timers = TimerQueue::new();
+        let handle = timers.register(at(10), counting_waker(&A));
+        assert!(timers.waker(handle).is_some());
+        assert!(timers.cancel(handle).is_some());
+        assert!(timers.waker(handle).is_none());
+        // 取消过的定时器不会再到期，也不能再取消一次
+        assert_eq!(wake_expired(&mut timers, at(100)), 0);
+        assert!(timers.cancel(handle).is_none());
+        assert_eq!(A.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn cancel_by_owner() {
+        static A: AtomicUsize = AtomicUsize::new(0);
+        static B: AtomicUsize = AtomicUsize::new(0);
+        static C: AtomicUsize = AtomicUsize::new(0);
+        let mut timers = TimerQueue::new();
+        timers.register_owned(at(10), Some(1), counting_waker(&A));
+        timers.register_owned(at(20), Some(2), counting_waker(&B));
+        let kernel = timers.register(at(30), counting_waker(&C));
+        timers.register_owned(at(40), Some(1), counting_waker(&A));
+        assert_eq!(timers.cancel_owned(1).len(), 2);
+        assert!(timers.cancel_owned(1).is_empty());
+        assert!(timers.waker(kernel).is_some());
+        // 取消的槽位可以重新使用，新的定时器不属于原来的地址空间
+        timers.register(at(50), counting_waker(&C));
+        assert!(timers.cancel_owned(1).is_empty());
+        assert_eq!(wake_expired(&mut timers, at(100)), 3);
+        assert_eq!(
+            (
+                A.load(Ordering::Relaxed),
+                B.load(Ordering::Relaxed),
+                C.load(Ordering::Relaxed)
+            ),
+            (0, 1, 2)
+        );
+    }
+
+    #[test]
+    fn stale_generation_is_ignored() {
+        static A: AtomicUsize = AtomicUsize::new(0);
+        static B: AtomicUsize = AtomicUsize::new(0);
+        let mut timers = TimerQueue::new();
+        let old = timers.register(at(10), counting_waker(&A));
+        drop(timers.cancel(old));
+        // 新的定时器用了同一个槽位，旧的堆条目和旧的句柄都已经过期
+        let new = timers.register(at(50), counting_waker(&B));
+        assert_eq!(new.index, old.index);
+        assert_ne!(new.generation, old.generation);
+        assert_eq!(wake_expired(&mut timers, at(20)), 0);
+        assert!(timers.cancel(old).is_none());
+        assert!(timers.waker(new).is_some());
+        assert_eq!(wake_expired(&mut timers, at(50)), 1);
+        assert_eq!(
+            (A.load(Ordering::Relaxed), B.load(Ordering::Relaxed)),
+            (0, 1)
+        );
+        assert!(timers.cancel(new).is_none());
+    }
+
+    #[test]
+    fn cancelled_entries_are_compacted() {
+        static A: AtomicUsize = AtomicUsize::new(0);
+        static B: AtomicUsize = AtomicUsize::new(0);
+        let mut timers = TimerQueue::new();
+        let kept = timers.register(at(10_000), counting_waker(&B));
+        // 反复登记又取消很久以后才到期的定时器，堆的大小不随次数增长
+        for i in 0..1000 {
+            let handle = timers.register(at(20_000 + i), counting_waker(&A));
+            drop(timers.cancel(handle));
+            assert!(timers.heap.len() <= 2 * timers.live() + 2);
+        }
+        assert_eq!(timers.slots.len(), 2);
+        assert!(timers.waker(kept).is_some());
+        assert_eq!(wake_expired(&mut timers, at(100_000)), 1);
+        assert_eq!(
+            (A.load(Ordering::Relaxed), B.load(Ordering::Relaxed)),
+            (0, 1)
+        );
+    }
+}
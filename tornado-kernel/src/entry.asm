@@ -1,6 +1,23 @@
     .section .text.entry
     .globl _start
 _start:
+    # 启动硬件线程，a0 为硬件线程编号
+    lui t2, %hi(rust_main)
+    addi t2, t2, %lo(rust_main)
+    j _boot
+
+    .globl _start_secondary
+_start_secondary:
+    # 其它硬件线程由启动硬件线程通过 SBI 唤醒，从这里开始运行
+    lui t2, %hi(rust_main_secondary)
+    addi t2, t2, %lo(rust_main_secondary)
+
+_boot:
+    # 启动栈只够 MAX_HARTS 个硬件线程使用，编号更大的硬件线程在碰到栈之前停下
+    # MAX_HARTS 由构建脚本从 shared-abi 中生成，见 build.rs
+    li t0, MAX_HARTS
+    bgeu a0, t0, _park
+
     # 初始化启动页
     la t0, boot_page_table # 此时pc是物理地址，得到的是物理地址的偏移
     srli t0, t0, 12
@@ -9,19 +26,24 @@ _start:
     csrw satp, t0 # 写入 satp 
     sfence.vma # 更新页表缓存
 
-    # 加载栈地址
+    # 加载栈地址，每个硬件线程使用自己的启动栈
     lui sp, %hi(boot_stack_top)
     addi sp, sp, %lo(boot_stack_top)
+    li t0, 4096 * 16
+    mul t0, t0, a0
+    sub sp, sp, t0
+
+    # 跳转到rust_main或rust_main_secondary
+    jr t2
 
-    # 跳转到rust_main
-    lui t0, %hi(rust_main)
-    addi t0, t0, %lo(rust_main)
-    jr t0
+_park:
+    wfi
+    j _park
 
     .section .bss.stack
     .global boot_stack
 boot_stack:
-    .space 4096 * 16
+    .space 4096 * 16 * MAX_HARTS
     .global boot_stack_top
 boot_stack_top:
 
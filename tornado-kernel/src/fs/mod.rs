@@ -31,9 +31,9 @@ impl Fs {
     pub fn list<S: Into<String>>(&self, dir: S) -> Vec<String> {
         self.0.list(dir)
     }
-    /// 加载文件的数据到内存
-    pub async fn load_binary<S: Into<String>>(&self, file: S) -> Vec<u8> {
-        self.0.load_binary(file).await.expect("load binary")
+    /// 加载文件的数据到内存，文件不存在时返回None
+    pub async fn load_binary<S: Into<String>>(&self, file: S) -> Option<Vec<u8>> {
+        self.0.load_binary(file).await.ok()
     }
     /// 写入文件
     #[allow(unused)]
@@ -4,6 +4,13 @@ use crate::{
     task::Process,
 };
 use alloc::{boxed::Box, collections::LinkedList, sync::Arc};
+use core::{
+    sync::atomic::{fence, AtomicUsize, Ordering},
+    time::Duration,
+};
+use lazy_static::lazy_static;
+pub use shared_abi::MAX_HARTS;
+use spin::Mutex;
 
 /// 写一个指针到上下文指针
 #[inline]
@@ -24,15 +31,44 @@ pub fn read_tp() -> usize {
 /// 用户层将定义自己的tp寄存器意义
 ///
 /// 在内核层中，tp指向一个结构体，说明当前的硬件线程编号，
-/// 以及已经分配的地址空间和对应的用户上下文
+/// 以及当前硬件线程进入的用户地址空间
 #[repr(C)]
 pub struct KernelHartInfo {
     hart_id: usize,
     current_address_space_id: AddressSpaceId, // currently unused
     current_process: Option<Arc<Process>>,    // currently unused
-    hart_max_asid: AddressSpaceId,            // note: different between qemu and k210 platform
-    asid_alloc: (LinkedList<usize>, usize),   // (空余的编号回收池，目前已分配最大的编号)
-    user_mm_sets: (LinkedList<MemorySet>, usize), // (注册的用户地址空间映射，上一次进入的用户地址空间编号)
+    prev_asid: usize,                         // 上一次进入的用户地址空间编号
+    user_ticks: usize,                        // 上一次进入的用户地址空间连续运行的时钟中断次数
+    trap_stack_top: usize,                    // 用户态陷入内核时使用的内核栈
+}
+
+/// 所有硬件线程共用的地址空间信息
+///
+/// 任何硬件线程都可以运行任何地址空间，所以地址空间编号的分配和注册的用户地址空间映射都是全局的
+struct AddressSpaces {
+    max_asid: AddressSpaceId, // note: different between qemu and k210 platform
+    asid_alloc: (LinkedList<usize>, usize), // (空余的编号回收池，目前已分配最大的编号)
+    user_mm_sets: LinkedList<MemorySet>, // 注册的用户地址空间映射
+    running: [Option<usize>; MAX_HARTS], // 每个硬件线程正在运行的用户地址空间编号
+}
+
+/// 启动硬件线程的编号，也就是第一个加载[`KernelHartInfo`]的硬件线程
+static BOOT_HART_ID: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// 正在等待中断的硬件线程，第`i`位对应编号为`i`的硬件线程
+static IDLE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// 各个硬件线程等待中断的总时间，单位为微秒
+static IDLE_MICROS: [AtomicUsize; MAX_HARTS] = [IDLE_ZERO; MAX_HARTS];
+const IDLE_ZERO: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref ADDRESS_SPACES: Mutex<AddressSpaces> = Mutex::new(AddressSpaces {
+        max_asid: crate::memory::max_asid(),
+        asid_alloc: (LinkedList::new(), 0), // 0留给内核，其它留给应用
+        user_mm_sets: LinkedList::new(),
+        running: [None; MAX_HARTS],
+    });
 }
 
 impl KernelHartInfo {
@@ -41,13 +77,16 @@ impl KernelHartInfo {
     /// 在堆上申请一片内存存放[`KernelHartInfo`]数据结构
     /// 这片内存不会马上释放，只有在调用`unload_hart`函数的时候才会释放
     pub unsafe fn load_hart(hart_id: usize) {
+        assert!(hart_id < MAX_HARTS, "hart id {} out of range", hart_id);
+        let _ =
+            BOOT_HART_ID.compare_exchange(usize::MAX, hart_id, Ordering::AcqRel, Ordering::Acquire);
         let hart_info = Box::new(KernelHartInfo {
             hart_id,
             current_address_space_id: AddressSpaceId::from_raw(0),
             current_process: None,
-            hart_max_asid: crate::memory::max_asid(),
-            asid_alloc: (LinkedList::new(), 0), // 0留给内核，其它留给应用,
-            user_mm_sets: (LinkedList::new(), 0),
+            prev_asid: 0,
+            user_ticks: 0,
+            trap_stack_top: 0,
         });
         let tp = Box::into_raw(hart_info) as usize; // todo: 这里有内存泄漏，要在drop里处理
         write_tp(tp)
@@ -65,6 +104,54 @@ impl KernelHartInfo {
         use_tp_box(|b| b.hart_id)
     }
 
+    /// 当前硬件线程是否为启动硬件线程，只有启动硬件线程负责关机
+    pub fn is_boot_hart() -> bool {
+        Self::hart_id() == BOOT_HART_ID.load(Ordering::Acquire)
+    }
+
+    /// 标记当前硬件线程是否在等待中断
+    ///
+    /// 标记之后其它硬件线程设置任务就绪时会发送处理器间中断，把它从`wfi`中叫醒。
+    /// 等待的一方先写空闲标记再读共享调度器，唤醒的一方先写共享调度器再读空闲标记，
+    /// 两边都要用SeqCst隔开写和读，否则两边可能都看不到对方的写入，唤醒就丢了
+    pub fn set_idle(idle: bool) {
+        let bit = 1 << Self::hart_id();
+        if idle {
+            IDLE_HARTS.fetch_or(bit, Ordering::SeqCst);
+            fence(Ordering::SeqCst);
+        } else {
+            IDLE_HARTS.fetch_and(!bit, Ordering::AcqRel);
+        }
+    }
+
+    /// 当前硬件线程又等待了`duration`这么长的中断
+    pub fn add_idle_time(duration: Duration) {
+        IDLE_MICROS[Self::hart_id()].fetch_add(duration.as_micros() as usize, Ordering::Relaxed);
+    }
+
+    /// 硬件线程`hart_id`等待中断的总时间
+    pub fn idle_time(hart_id: usize) -> Duration {
+        Duration::from_micros(IDLE_MICROS[hart_id].load(Ordering::Relaxed) as u64)
+    }
+
+    /// 有任务变为就绪，叫醒其它正在等待中断的硬件线程
+    ///
+    /// 共享调度器设置任务状态时不拿锁，不知道任务会放进哪个硬件线程的队列，
+    /// 所以叫醒所有等待中的硬件线程，由它们自己到共享调度器里找任务
+    pub fn notify_idle_harts() {
+        // 和`set_idle`配对，任务设置为就绪的写入必须在读空闲标记之前完成
+        fence(Ordering::SeqCst);
+        let mask = IDLE_HARTS.load(Ordering::SeqCst) & !(1 << Self::hart_id());
+        if mask != 0 {
+            if let Err(error) = crate::sbi::send_ipi(mask, 0) {
+                println!(
+                    "[kernel] send ipi to {:#b} failed, sbi error {}",
+                    mask, error
+                );
+            }
+        }
+    }
+
     pub unsafe fn load_address_space_id(asid: AddressSpaceId) {
         use_tp_box(|b| b.current_address_space_id = asid);
     }
@@ -84,147 +171,178 @@ impl KernelHartInfo {
         use_tp_box(|b| b.current_process.clone())
     }
 
+    /// 设置当前硬件线程从用户态陷入内核时使用的内核栈
+    pub fn load_trap_stack(stack_top: usize) {
+        use_tp_box(|b| b.trap_stack_top = stack_top)
+    }
+
+    /// 当前硬件线程从用户态陷入内核时使用的内核栈，还没有设置时返回None
+    pub fn trap_stack() -> Option<usize> {
+        use_tp_box(|b| Some(b.trap_stack_top).filter(|&top| top != 0))
+    }
+
     /// 分配一个地址空间编号
     #[cfg(feature = "qemu")]
     pub fn alloc_address_space_id() -> Option<AddressSpaceId> {
-        use_tp_box(|b| {
-            let (free, max) = &mut b.asid_alloc;
-            if let Some(_) = free.front() {
-                // 如果链表有内容，返回内容
-                return free
-                    .pop_front()
-                    .map(|idx| unsafe { AddressSpaceId::from_raw(idx) });
-            }
-            // 如果链表是空的
-            if *max < b.hart_max_asid.into_inner() {
-                let ans = *max;
-                *max += 1;
-                Some(unsafe { AddressSpaceId::from_raw(ans) })
-            } else {
-                None
-            }
-        })
+        let mut spaces = ADDRESS_SPACES.lock();
+        let max_asid = spaces.max_asid.into_inner();
+        let (free, max) = &mut spaces.asid_alloc;
+        if let Some(_) = free.front() {
+            // 如果链表有内容，返回内容
+            return free
+                .pop_front()
+                .map(|idx| unsafe { AddressSpaceId::from_raw(idx) });
+        }
+        // 如果链表是空的
+        if *max < max_asid {
+            let ans = *max;
+            *max += 1;
+            Some(unsafe { AddressSpaceId::from_raw(ans) })
+        } else {
+            None
+        }
     }
 
     #[cfg(feature = "k210")]
     pub fn alloc_address_space_id() -> Option<AddressSpaceId> {
         // k210 平台上最大地址空间编号为 `0`，这里假设可以存在大于 0 的地址空间编号
-        use_tp_box(|b| {
-            let (free, max) = &mut b.asid_alloc;
-            if let Some(_) = free.front() {
-                // 如果链表有内容，返回内容
-                return free
-                    .pop_front()
-                    .map(|idx| unsafe { AddressSpaceId::from_raw(idx) });
-            }
-            // 如果链表是空的
-            let ans = *max;
-            *max += 1;
-            Some(unsafe { AddressSpaceId::from_raw(ans) })
-        })
+        let mut spaces = ADDRESS_SPACES.lock();
+        let (free, max) = &mut spaces.asid_alloc;
+        if let Some(_) = free.front() {
+            // 如果链表有内容，返回内容
+            return free
+                .pop_front()
+                .map(|idx| unsafe { AddressSpaceId::from_raw(idx) });
+        }
+        // 如果链表是空的
+        let ans = *max;
+        *max += 1;
+        Some(unsafe { AddressSpaceId::from_raw(ans) })
     }
 
     /// 释放地址空间编号
-    #[allow(unused)]
     pub fn free_address_space_id(asid: AddressSpaceId) {
-        use_tp_box(|b| {
-            let (free, max) = &mut b.asid_alloc;
-            if asid.into_inner() == *max && *max > 0 {
-                *max -= 1;
-                return;
-            } else {
-                free.push_back(asid.into_inner())
-            }
-        });
+        let mut spaces = ADDRESS_SPACES.lock();
+        let (free, max) = &mut spaces.asid_alloc;
+        // `max`是下一个要分配的编号，释放的是最后分配的编号时直接退回去
+        if asid.into_inner() + 1 == *max {
+            *max -= 1;
+        } else {
+            free.push_back(asid.into_inner())
+        }
     }
 
     /// 添加用户地址空间映射
     ///
     /// 添加成功返回true，否则返回false
     pub fn load_user_mm_set(mm_set: MemorySet) -> bool {
-        use_tp_box_move(|b| {
-            // 检查链表当前是否有相同地址空间的[`MemorySet`]
-            let (link, _prev) = &mut b.user_mm_sets;
-            for set in link.iter() {
-                if set.address_space_id == mm_set.address_space_id {
-                    return false;
-                }
+        let mut spaces = ADDRESS_SPACES.lock();
+        // 检查链表当前是否有相同地址空间的[`MemorySet`]
+        let link = &mut spaces.user_mm_sets;
+        for set in link.iter() {
+            if set.address_space_id == mm_set.address_space_id {
+                return false;
             }
-            link.push_back(mm_set);
-            true
-        })
+        }
+        link.push_back(mm_set);
+        true
     }
 
     /// 删除某个用户地址空间映射
     ///
     /// note: feature `linked_list_remove` is not stable
-    #[allow(unused)]
     pub unsafe fn unload_user_mm_set(asid: usize) -> Option<MemorySet> {
-        use_tp_box(|b| {
-            let (link, _prev) = &mut b.user_mm_sets;
-            let mut index = 0;
-            for set in link.iter() {
-                if set.address_space_id.into_inner() == asid {
-                    break;
-                }
-                index += 1;
-            }
-            if index < link.len() {
-                let mm_set = link.remove(index);
-                Some(mm_set)
-            } else {
-                None
+        let mut spaces = ADDRESS_SPACES.lock();
+        let link = &mut spaces.user_mm_sets;
+        let mut index = 0;
+        for set in link.iter() {
+            if set.address_space_id.into_inner() == asid {
+                break;
             }
-        })
+            index += 1;
+        }
+        if index < link.len() {
+            let mm_set = link.remove(index);
+            Some(mm_set)
+        } else {
+            None
+        }
     }
 
     /// 根据地址空间编号找到相应的[`Satp`]结构
     ///
     /// 没有对应的地址空间编号返回[`None`]
     pub fn user_satp(asid: usize) -> Option<Satp> {
-        use_tp_box(|b| {
-            let (link, _prev) = &b.user_mm_sets;
-            for set in link.iter() {
-                if set.address_space_id.into_inner() == asid {
-                    return Some(set.satp());
-                }
+        let spaces = ADDRESS_SPACES.lock();
+        for set in spaces.user_mm_sets.iter() {
+            if set.address_space_id.into_inner() == asid {
+                return Some(set.satp());
             }
-            None
-        })
+        }
+        None
+    }
+
+    /// 让当前硬件线程占用地址空间`asid`，地址空间正在其它硬件线程上运行时返回false
+    ///
+    /// 每个地址空间只有一份用户上下文和用户栈，同一时刻只能在一个硬件线程上运行。
+    /// 当前硬件线程原来占用的地址空间随之释放
+    pub fn try_enter_user(asid: usize) -> bool {
+        let hart_id = Self::hart_id();
+        let mut spaces = ADDRESS_SPACES.lock();
+        let running = &mut spaces.running;
+        if (0..MAX_HARTS).any(|id| id != hart_id && running[id] == Some(asid)) {
+            return false;
+        }
+        running[hart_id] = Some(asid);
+        true
+    }
+
+    /// 当前硬件线程回到内核执行器，释放它占用的地址空间
+    pub fn leave_user() {
+        let hart_id = Self::hart_id();
+        ADDRESS_SPACES.lock().running[hart_id] = None;
     }
 
     /// 获取上一个进入的用户的[`Satp`]结构
     pub fn prev_satp() -> Option<Satp> {
-        let asid = use_tp_box(|b| b.user_mm_sets.1);
-        Self::user_satp(asid)
+        Self::user_satp(Self::get_prev_asid())
     }
 
     /// 设置上一次进入的用户地址空间编号
     ///
-    /// 用于即将进入用户态
+    /// 用于即将进入用户态，换了地址空间时重新计算连续运行的时钟中断次数
     pub fn set_prev_asid(asid: usize) {
-        use_tp_box(|b| b.user_mm_sets.1 = asid)
+        use_tp_box(|b| {
+            if b.prev_asid != asid {
+                b.prev_asid = asid;
+                b.user_ticks = 0;
+            }
+        })
     }
 
     /// 获取上一次进入的用户态地址空间编号
     ///
     /// 用于用户陷入内核的时候
     pub fn get_prev_asid() -> usize {
-        use_tp_box(|b| b.user_mm_sets.1)
+        use_tp_box(|b| b.prev_asid)
     }
-}
 
-#[inline]
-fn use_tp_box<F: Fn(&mut Box<KernelHartInfo>) -> T, T>(f: F) -> T {
-    let addr = read_tp();
-    let mut bx: Box<KernelHartInfo> = unsafe { Box::from_raw(addr as *mut _) };
-    let ans = f(&mut bx);
-    drop(Box::into_raw(bx)); // 防止Box指向的空间被释放
-    ans
+    /// 上一次进入的用户地址空间又运行了一次时钟中断，返回它连续运行的时钟中断次数
+    pub fn tick_user() -> usize {
+        use_tp_box(|b| {
+            b.user_ticks += 1;
+            b.user_ticks
+        })
+    }
+
+    /// 重新计算上一次进入的用户地址空间连续运行的时钟中断次数
+    pub fn reset_user_ticks() {
+        use_tp_box(|b| b.user_ticks = 0)
+    }
 }
 
 #[inline]
-fn use_tp_box_move<F: FnOnce(&mut Box<KernelHartInfo>) -> T, T>(f: F) -> T {
+fn use_tp_box<F: Fn(&mut Box<KernelHartInfo>) -> T, T>(f: F) -> T {
     let addr = read_tp();
     let mut bx: Box<KernelHartInfo> = unsafe { Box::from_raw(addr as *mut _) };
     let ans = f(&mut bx);
@@ -42,7 +42,7 @@ mod user;
 mod virtio;
 
 #[cfg(not(test))]
-global_asm!(include_str!("entry.asm"));
+global_asm!(include_str!(concat!(env!("OUT_DIR"), "/entry.asm")));
 
 /// qemu平台下共享调度器的基地址
 #[cfg(feature = "qemu")]
@@ -52,6 +52,9 @@ const SHAREDPAYLOAD_BASE: usize = 0x8600_0000;
 #[cfg(feature = "k210")]
 const SHAREDPAYLOAD_BASE: usize = 0x8040_0000;
 
+/// 启动硬件线程创建的内核进程，其它硬件线程启动之后共用它
+static KERNEL_PROCESS: spin::Once<Arc<task::Process>> = spin::Once::new();
+
 #[no_mangle]
 pub extern "C" fn rust_main(hart_id: usize) -> ! {
     extern "C" {
@@ -140,7 +143,20 @@ pub extern "C" fn rust_main(hart_id: usize) -> ! {
         plic::xv6_plic_init();
     }
 
-    let shared_payload = unsafe { async_rt::SharedPayload::load(SHAREDPAYLOAD_BASE) };
+    let shared_payload =
+        unsafe { async_rt::SharedPayload::load(SHAREDPAYLOAD_BASE) }.expect("load shared payload");
+    // 调度算法和时间片长度在编译内核时由环境变量`SCHED_POLICY`和`SCHED_QUANTUM`选择，
+    // 见xtask的`--policy`和`--quantum`参数；没有指定时使用多级优先级队列和默认的时间片
+    let policy = option_env!("SCHED_POLICY").map_or(async_rt::SchedPolicy::Priority, |name| {
+        async_rt::SchedPolicy::from_name(name).expect("unknown SCHED_POLICY")
+    });
+    let quantum =
+        option_env!("SCHED_QUANTUM").map_or(0, |n| n.parse().expect("invalid SCHED_QUANTUM"));
+    unsafe { shared_payload.init_environment(policy, quantum) };
+    println!(
+        "[kernel] shared scheduler policy: {:?}, quantum: {}",
+        policy, quantum
+    );
 
     // 创建一个内核进程
     let process = task::Process::new(kernel_memory).expect("create process 1");
@@ -148,6 +164,11 @@ pub extern "C" fn rust_main(hart_id: usize) -> ! {
     let address_space_id = process.address_space_id();
     // 分配一个内核栈
     let stack_handle = process.alloc_stack().expect("alloc initial stack");
+    hart::KernelHartInfo::load_trap_stack(stack_handle.end.0);
+
+    // 启动其它硬件线程，它们从共享调度器里领取任务运行
+    KERNEL_PROCESS.call_once(|| process.clone());
+    start_secondary_harts(hart_id);
 
     // 创建一些测试任务
     #[allow(unused)]
@@ -188,6 +209,22 @@ pub extern "C" fn rust_main(hart_id: usize) -> ! {
         shared_payload.shared_set_task_state,
     );
 
+    #[cfg(feature = "demo")]
+    let task_timer = task::new_kernel(
+        timer_test(),
+        process.clone(),
+        shared_payload.shared_scheduler,
+        shared_payload.shared_set_task_state,
+    );
+
+    #[cfg(feature = "demo")]
+    let task_join = task::new_kernel(
+        join_test(),
+        process.clone(),
+        shared_payload.shared_scheduler,
+        shared_payload.shared_set_task_state,
+    );
+
     // 创建一个初始化文件系统的任务
     let task_5 = task::new_kernel(
         fs::fs_init(),
@@ -200,84 +237,116 @@ pub extern "C" fn rust_main(hart_id: usize) -> ! {
         // shared_payload.add_task(hart_id, address_space_id, task_1.task_repr());
         // shared_payload.add_task(hart_id, address_space_id, task_2.task_repr());
         // shared_payload.add_task(hart_id, address_space_id, task_3.task_repr());
-        shared_payload.add_task(hart_id, address_space_id, task_5.task_repr());
+        // 定时器演示
+        #[cfg(feature = "demo")]
+        shared_payload
+            .add_kernel_task(hart_id, address_space_id, task_timer)
+            .expect("add timer test");
+        // 任务句柄演示
+        #[cfg(feature = "demo")]
+        shared_payload
+            .add_kernel_task(hart_id, address_space_id, task_join)
+            .expect("add join test");
+        shared_payload
+            .add_kernel_task(hart_id, address_space_id, task_5)
+            .expect("add fs init task");
     }
 
     // 运行任务
     async_rt::run_until_idle(
-        || unsafe { shared_payload.peek_task(async_rt::kernel_should_switch) },
+        || unsafe { shared_payload.peek_task(hart_id, async_rt::kernel_should_switch) },
         |task_repr| unsafe { shared_payload.delete_task(task_repr) },
         |task_repr, new_state| unsafe { shared_payload.set_task_state(task_repr, new_state) },
+        || unsafe { shared_payload.requeue_task(hart_id) },
     );
 
     // 通过一些任务从文件系统中加载用户的二进制文件和准备用户的上下文
     #[allow(unused)]
     let task_6 = task::new_kernel(
-        user::prepare_user("yield-task0.bin", stack_handle.end.0),
+        user::prepare_user("yield-task0", stack_handle.end.0),
         process.clone(),
         shared_payload.shared_scheduler,
         shared_payload.shared_set_task_state,
     );
     #[allow(unused)]
     let task_7 = task::new_kernel(
-        user::prepare_user("yield-task1.bin", stack_handle.end.0),
+        user::prepare_user("yield-task1", stack_handle.end.0),
         process.clone(),
         shared_payload.shared_scheduler,
         shared_payload.shared_set_task_state,
     );
     #[allow(unused)]
     let task_8 = task::new_kernel(
-        user::prepare_user("async-read.bin", stack_handle.end.0),
+        user::prepare_user("async-read", stack_handle.end.0),
         process.clone(),
         shared_payload.shared_scheduler,
         shared_payload.shared_set_task_state,
     );
     #[allow(unused)]
     let task_9 = task::new_kernel(
-        user::prepare_user("channel.bin", stack_handle.end.0),
+        user::prepare_user("channel", stack_handle.end.0),
         process.clone(),
         shared_payload.shared_scheduler,
         shared_payload.shared_set_task_state,
     );
     #[allow(unused)]
     let task_10 = task::new_kernel(
-        user::prepare_user("analysis0.bin", stack_handle.end.0),
+        user::prepare_user("analysis0", stack_handle.end.0),
         process.clone(),
         shared_payload.shared_scheduler,
         shared_payload.shared_set_task_state,
     );
     #[allow(unused)]
     let task_11 = task::new_kernel(
-        user::prepare_user("analysis1.bin", stack_handle.end.0),
+        user::prepare_user("analysis1", stack_handle.end.0),
         process.clone(),
         shared_payload.shared_scheduler,
         shared_payload.shared_set_task_state,
     );
     #[allow(unused)]
     let task_12 = task::new_kernel(
-        user::prepare_user("analysis2.bin", stack_handle.end.0),
+        user::prepare_user("analysis2", stack_handle.end.0),
         process.clone(),
         shared_payload.shared_scheduler,
         shared_payload.shared_set_task_state,
     );
     #[allow(unused)]
     let task_13 = task::new_kernel(
-        user::prepare_user("analysis3.bin", stack_handle.end.0),
+        user::prepare_user("analysis3", stack_handle.end.0),
         process.clone(),
         shared_payload.shared_scheduler,
         shared_payload.shared_set_task_state,
     );
     #[allow(unused)]
     let task_14 = task::new_kernel(
-        user::prepare_user("database.bin", stack_handle.end.0),
+        user::prepare_user("database", stack_handle.end.0),
+        process.clone(),
+        shared_payload.shared_scheduler,
+        shared_payload.shared_set_task_state,
+    );
+    #[cfg(feature = "demo")]
+    let task_17 = task::new_kernel(
+        user::prepare_user("spawn", stack_handle.end.0),
         process.clone(),
         shared_payload.shared_scheduler,
         shared_payload.shared_set_task_state,
     );
     unsafe {
         // 任务切换演示
-        shared_payload.add_task(hart_id, address_space_id, task_6.task_repr());
-        shared_payload.add_task(hart_id, address_space_id, task_7.task_repr());
+        #[cfg(not(feature = "demo"))]
+        shared_payload
+            .add_kernel_task(hart_id, address_space_id, task_6)
+            .expect("add yield-task0");
+        #[cfg(not(feature = "demo"))]
+        shared_payload
+            .add_kernel_task(hart_id, address_space_id, task_7)
+            .expect("add yield-task1");
+
+        // 用户程序启动其它程序演示，spawn程序再启动user_task和sleep程序，演示用户态睡眠
+        #[cfg(feature = "demo")]
+        shared_payload
+            .add_kernel_task(hart_id, address_space_id, task_17)
+            .expect("add spawn");
 
         // 异步IO系统调用演示
         // shared_payload.add_task(hart_id, address_space_id, task_8.task_repr());
@@ -301,19 +370,81 @@ pub extern "C" fn rust_main(hart_id: usize) -> ! {
 
     // 运行执行器
     async_rt::run_until_idle(
-        || unsafe { shared_payload.peek_task(async_rt::kernel_should_switch) },
+        || unsafe { shared_payload.peek_task(hart_id, async_rt::kernel_should_switch) },
         |task_repr| unsafe { shared_payload.delete_task(task_repr) },
         |task_repr, new_state| unsafe { shared_payload.set_task_state(task_repr, new_state) },
+        || unsafe { shared_payload.requeue_task(hart_id) },
     );
 
-    
     // 进入地址空间编号为 1 的用户态空间
     user::enter_user(1)
     // end()
 }
 
+/// 其它硬件线程的入口，由[`start_secondary_harts`]通过SBI唤醒
+#[no_mangle]
+pub extern "C" fn rust_main_secondary(hart_id: usize) -> ! {
+    unsafe { hart::KernelHartInfo::load_hart(hart_id) };
+    let process = KERNEL_PROCESS.wait().clone();
+    process.load_on_hart();
+    let stack_handle = process.alloc_stack().expect("alloc trap stack");
+    hart::KernelHartInfo::load_trap_stack(stack_handle.end.0);
+    trap::init();
+    println!("[kernel] hart {} started", hart_id);
+    secondary_idle()
+}
+
+/// 启动除了`boot_hart_id`以外的硬件线程，不存在的硬件线程跳过
+fn start_secondary_harts(boot_hart_id: usize) {
+    extern "C" {
+        fn _start_secondary();
+    }
+    let start_addr = memory::VirtualAddress(_start_secondary as usize)
+        .physical_address_linear()
+        .0;
+    for hart_id in (0..hart::MAX_HARTS).filter(|&id| id != boot_hart_id) {
+        if let Err(error) = sbi::hart_start(hart_id, start_addr, 0) {
+            println!(
+                "[kernel] cannot start hart {}, sbi error {}",
+                hart_id, error
+            );
+        }
+    }
+}
+
+/// 其它硬件线程在共享调度器里没有任务的时候等待中断，有新任务之后继续运行
+///
+/// 只有启动硬件线程负责关机，其它硬件线程上的执行器结束时可能还有地址空间在别处运行
+fn secondary_idle() -> ! {
+    let hart_id = hart::KernelHartInfo::hart_id();
+    let shared_payload =
+        unsafe { async_rt::SharedPayload::load(SHAREDPAYLOAD_BASE) }.expect("load shared payload");
+    loop {
+        async_rt::run_until_idle(
+            || unsafe { shared_payload.peek_task(hart_id, async_rt::kernel_should_switch) },
+            |task_repr| unsafe { shared_payload.delete_task(task_repr) },
+            |task_repr, new_state| unsafe { shared_payload.set_task_state(task_repr, new_state) },
+            || unsafe { shared_payload.requeue_task(hart_id) },
+        );
+        async_rt::wait_for_interrupt(|| {
+            let task = unsafe { shared_payload.peek_task(hart_id, async_rt::kernel_should_switch) };
+            !matches!(task, task::TaskResult::Finished)
+        });
+    }
+}
+
 #[allow(unused)]
 fn end() -> ! {
+    if !hart::KernelHartInfo::is_boot_hart() {
+        secondary_idle()
+    }
+    for hart_id in 0..hart::MAX_HARTS {
+        println!(
+            "[kernel] hart {} idle for {:?}",
+            hart_id,
+            hart::KernelHartInfo::idle_time(hart_id)
+        );
+    }
     // 关机之前，卸载当前的核。虽然关机后内存已经清空，不是必要，预留未来热加载热卸载处理核的情况
     unsafe { hart::KernelHartInfo::unload_hart() };
     // 没有任务了，关机
@@ -328,6 +459,39 @@ async fn task_2() {
     println!("hello world from 2!");
 }
 
+/// 定时器演示，先睡眠，再给不会完成的任务加上时间限制
+#[cfg(feature = "demo")]
+async fn timer_test() {
+    let start = trap::timer::Instant::now();
+    async_rt::sleep(Duration::from_millis(100)).await;
+    println!(
+        "[kernel] timer: slept 100ms, now {}ms",
+        trap::timer::get_time_ms()
+    );
+    async_rt::sleep_until(start + Duration::from_millis(300)).await;
+    println!(
+        "[kernel] timer: 300ms after start, now {}ms",
+        trap::timer::get_time_ms()
+    );
+    let ans = async_rt::timeout(core::future::pending::<()>(), Duration::from_millis(200)).await;
+    assert_eq!(ans, Err(async_rt::Elapsed));
+    let ans = async_rt::timeout(async { 42 }, Duration::from_secs(1)).await;
+    assert_eq!(ans, Ok(42));
+    println!("[kernel] timer: timeout test passed");
+}
+
+/// 任务句柄演示，等待任务的结果，再取消一个不会完成的任务
+#[cfg(feature = "demo")]
+async fn join_test() {
+    let handle = task::spawn(async { 6 * 7 });
+    assert_eq!(handle.await, Ok(42));
+    let handle = task::spawn(core::future::pending::<()>());
+    assert!(!handle.is_finished());
+    handle.abort();
+    assert_eq!(handle.await, Err(task::JoinError::Aborted));
+    println!("[kernel] join: test passed");
+}
+
 struct FibonacciFuture {
     a: usize,
     b: usize,
@@ -346,9 +510,12 @@ impl FibonacciFuture {
     }
 }
 
+use alloc::sync::Arc;
 use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll};
+#[cfg(feature = "demo")]
+use core::time::Duration;
 
 impl Future for FibonacciFuture {
     type Output = ();
@@ -11,7 +11,7 @@ use crate::SHAREDPAYLOAD_BASE;
 use alloc::vec::Vec;
 use core::ops::Range;
 
-use super::Satp;
+use super::{page_table_entry::PageTableEntry, Satp};
 
 /// 一个地址空间中，所有与内存空间有关的信息
 #[derive(Debug)]
@@ -152,22 +152,14 @@ impl MemorySet {
             address_space_id,
         })
     }
-    /// 通过一个二进制文件创建用户态映射
-    pub fn new_bin(base: usize, pages: usize, asid: AddressSpaceId) -> Option<MemorySet> {
+    /// 创建用户态映射，程序本身的段之后用[`map_program_segment`](Self::map_program_segment)映射
+    pub fn new_user(asid: AddressSpaceId) -> Option<MemorySet> {
         extern "C" {
             fn _swap_frame();
         }
         let mut mapping = Mapping::new_alloc()?;
         let allocated_pairs = Vec::new();
 
-        let va_range = VirtualAddress(0)..VirtualAddress(PAGE_SIZE * pages);
-        let pa_range = PhysicalAddress(base)..PhysicalAddress(base + PAGE_SIZE * pages);
-        mapping.map_defined(
-            &va_range,
-            &pa_range,
-            Flags::EXECUTABLE | Flags::READABLE | Flags::WRITABLE | Flags::USER,
-        );
-
         // 映射 _swap_frame
         let swap_frame_va = VirtualAddress(SWAP_FRAME_VA);
         let swap_frame_vpn = VirtualPageNumber::floor(swap_frame_va);
@@ -208,6 +200,41 @@ impl MemorySet {
             address_space_id: asid,
         })
     }
+    /// 映射用户程序的一个段，段的起始地址所在的页对应物理地址`base`所在的页
+    ///
+    /// 相邻的两个段可能共用一页，这一页的权限取两个段的并集。
+    /// 只有程序之前映射的段可以共用，和其它映射重叠时返回None。
+    /// 映射好的段记录到`segments`中，之后分配的虚拟空间会避开它
+    pub fn map_program_segment(
+        &mut self,
+        range: Range<VirtualAddress>,
+        base: PhysicalAddress,
+        flags: Flags,
+    ) -> Option<()> {
+        let vpn_range = VirtualPageNumber::floor(range.start)..VirtualPageNumber::ceil(range.end);
+        let base_ppn = PhysicalPageNumber::floor(base);
+        for i in 0..vpn_range.end - vpn_range.start {
+            let vpn = vpn_range.start + i;
+            let is_program_page = self
+                .segments
+                .iter()
+                .any(|seg| seg.page_range().contains(&vpn));
+            match self.mapping.find_pte(vpn) {
+                Some(entry) if entry.is_valid() && is_program_page => {
+                    *entry = PageTableEntry::new(Some(entry.page_number()), entry.flags() | flags)
+                }
+                Some(entry) if entry.is_valid() => return None,
+                _ => self.mapping.map_one(vpn, Some(base_ppn + i), flags)?,
+            }
+        }
+        // 程序占用的物理内存由内核另外管理，这里的映射类型只用来检查重叠
+        self.segments.push(Segment {
+            map_type: MapType::Framed,
+            range,
+            flags,
+        });
+        Some(())
+    }
     /// 检测一段内存区域和已有的是否存在重叠区域
     pub fn overlap_with(&self, range: Range<VirtualPageNumber>) -> bool {
         fn range_overlap<T: core::cmp::Ord>(a: &Range<T>, b: &Range<T>) -> bool {
@@ -220,10 +247,12 @@ impl MemorySet {
         }
         false
     }
-    /// 添加一个[`Segment`]的内存映射
+    /// 添加一个[`Segment`]的内存映射，和已有的映射重叠时返回None
     pub fn add_segment(&mut self, segment: Segment, init_data: Option<&[u8]>) -> Option<()> {
         // 检测 segment 没有重合
-        assert!(!self.overlap_with(segment.page_range()));
+        if self.overlap_with(segment.page_range()) {
+            return None;
+        }
         // 映射并将新分配的页面保存下来
         self.allocated_pairs
             .extend(self.mapping.map_segment(&segment, init_data)?);
@@ -39,3 +39,65 @@ pub fn shutdown() -> ! {
 pub fn set_timer(time: usize) {
     sbi_call(SBI_SET_TIMER, time, 0, 0);
 }
+
+/// SBI v0.2 的硬件线程状态管理扩展
+const EXTENSION_HSM: usize = 0x48534D;
+const FUNCTION_HSM_HART_START: usize = 0;
+
+/// SBI v0.2 的处理器间中断扩展
+const EXTENSION_IPI: usize = 0x735049;
+const FUNCTION_IPI_SEND_IPI: usize = 0;
+
+/// SBI v0.2 的调用，返回错误码和返回值
+#[inline(always)]
+fn sbi_call_ext(
+    extension: usize,
+    function: usize,
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
+) -> (usize, usize) {
+    let (error, value);
+    unsafe {
+        llvm_asm!("ecall"
+            : "={x10}" (error), "={x11}" (value)
+            : "{x10}" (arg0), "{x11}" (arg1), "{x12}" (arg2), "{x16}" (function), "{x17}" (extension)
+            : "memory"
+            : "volatile");
+    }
+    (error, value)
+}
+
+/// 启动硬件线程`hart_id`，从物理地址`start_addr`开始运行，`a0`为硬件线程编号，`a1`为`opaque`
+///
+/// 失败时返回SBI的错误码，比如硬件线程不存在或者已经启动
+pub fn hart_start(hart_id: usize, start_addr: usize, opaque: usize) -> Result<(), isize> {
+    let (error, _) = sbi_call_ext(
+        EXTENSION_HSM,
+        FUNCTION_HSM_HART_START,
+        hart_id,
+        start_addr,
+        opaque,
+    );
+    match error as isize {
+        0 => Ok(()),
+        error => Err(error),
+    }
+}
+
+/// 给`hart_mask`中的硬件线程发送处理器间中断，第`i`位对应编号为`hart_mask_base + i`的硬件线程
+///
+/// 对方收到的是S态软件中断。和旧版的接口不同，`hart_mask`直接是掩码而不是掩码的地址
+pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> Result<(), isize> {
+    let (error, _) = sbi_call_ext(
+        EXTENSION_IPI,
+        FUNCTION_IPI_SEND_IPI,
+        hart_mask,
+        hart_mask_base,
+        0,
+    );
+    match error as isize {
+        0 => Ok(()),
+        error => Err(error),
+    }
+}
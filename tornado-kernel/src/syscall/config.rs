@@ -4,6 +4,8 @@ pub const MODULE_TASK: usize = 0x7777777;
 
 pub const FUNC_PROCESS_EXIT: usize = 0x1919810;
 pub const FUNC_PROCESS_PANIC: usize = 0x11451419;
+pub const FUNC_PROCESS_SPAWN: usize = 0x1919811;
+pub const FUNC_PROCESS_WAIT: usize = 0x1919812;
 
 pub const FUNC_TEST_WRITE: usize = 0x666233;
 pub const FUNC_TEST_WRITE_ONE: usize = 0x444555;
@@ -14,5 +16,11 @@ pub const FUNC_TEST_READ_TIMER: usize = 0x22;
 
 pub const FUNC_SWITCH_TASK: usize = 0x666666;
 pub const FUNC_IO_TASK: usize = 0x55555;
+pub const FUNC_TIMER_TASK: usize = 0x56666;
+pub const FUNC_GET_TIME: usize = 0x56667;
+pub const FUNC_ENABLE_PREEMPT: usize = 0x57777;
 
-pub const FUNC_CHECK: usize = 0x4444;
+pub const FUNC_WAIT_INTERRUPT: usize = 0x4455;
+pub const FUNC_HART_ID: usize = 0x8888;
+pub const FUNC_NOTIFY_IDLE: usize = 0x8889;
+pub const FUNC_SCHEDULER_STATS: usize = 0x9999;
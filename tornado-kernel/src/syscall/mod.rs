@@ -4,13 +4,18 @@ mod config;
 mod user_syscall;
 
 use crate::{
+    async_rt::{SchedulerStats, SharedPayload},
     hart::KernelHartInfo,
-    memory::{Satp, VirtualAddress, VirtualPageNumber},
+    memory::{Flags, PhysicalPageNumber, Satp, VirtualAddress, VirtualPageNumber, PAGE_SIZE},
     trap::timer,
+    user::{self, WaitStatus, ARGS_MAX},
+    SHAREDPAYLOAD_BASE,
 };
+use alloc::{string::String, vec::Vec};
 use bit_field::BitField;
 use config::*;
-pub use user_syscall::{get_swap_cx, user_trap_handler, WAKE_NUM};
+use core::{convert::TryInto, mem, slice};
+pub use user_syscall::{get_swap_cx, user_trap_handler};
 
 /// 系统调用结果
 pub enum SyscallResult {
@@ -29,13 +34,22 @@ pub enum SyscallResult {
         block_id: usize,
         buf_ptr: usize,
         write: bool,
+        wake_task_repr: usize,
+    },
+    TimerTask {
+        deadline_ms: usize,
+        wake_task_repr: usize,
+    },
+    WaitInterrupt,
+    SpawnTask {
+        path: String,
+        args: Vec<String>,
+        envs: Vec<String>,
     },
-    Check,
     Terminate(i32),
 }
 
 impl SyscallResult {
-    #[allow(unused)]
     fn ok(extra: usize) -> Self {
         SyscallResult::Procceed { code: 0, extra }
     }
@@ -46,17 +60,27 @@ pub fn syscall(param: [usize; 6], user_satp: usize, func: usize, module: usize)
     match module {
         MODULE_PROCESS => do_process(param, user_satp, func),
         MODULE_TEST_INTERFACE => do_test_interface(param, user_satp, func),
-        MODULE_TASK => do_task(param, func),
+        MODULE_TASK => do_task(param, user_satp, func),
         _ => panic!("Unknown module {:x}", module),
     }
 }
 
 /// 任务相关系统调用
-fn do_task(param: [usize; 6], func: usize) -> SyscallResult {
+fn do_task(param: [usize; 6], user_satp: usize, func: usize) -> SyscallResult {
     match func {
         FUNC_SWITCH_TASK => switch_next_task(param[0]),
-        FUNC_IO_TASK => do_io_task(param[0], param[1], param[2]),
-        FUNC_CHECK => do_check(),
+        FUNC_IO_TASK => do_io_task(param[0], param[1], param[2], param[3]),
+        FUNC_TIMER_TASK => do_timer_task(param[0], param[1]),
+        FUNC_GET_TIME => SyscallResult::ok(timer::get_time_ms()),
+        FUNC_ENABLE_PREEMPT => do_enable_preempt(user_satp, param[0], param[1]),
+        FUNC_WAIT_INTERRUPT => SyscallResult::WaitInterrupt,
+        FUNC_HART_ID => SyscallResult::ok(KernelHartInfo::hart_id()),
+        FUNC_NOTIFY_IDLE => {
+            // 用户在共享调度器里设置了任务就绪，用户态不能发送处理器间中断，由内核叫醒等待中的硬件线程
+            KernelHartInfo::notify_idle_harts();
+            SyscallResult::ok(0)
+        }
+        FUNC_SCHEDULER_STATS => do_scheduler_stats(user_satp, param[0], param[1]),
         _ => unimplemented!(),
     }
 }
@@ -85,30 +109,85 @@ fn switch_next_task(next_asid: usize) -> SyscallResult {
 ///
 /// 这时候内核会创建一个块设备读写任务并添加到共享调度器中。
 ///
-/// 任务的结尾会将对应的用户态任务唤醒。
-fn do_io_task(io_type: usize, block_id: usize, buf_ptr: usize) -> SyscallResult {
+/// 任务的结尾会将`wake_task_repr`对应的用户态任务唤醒。
+fn do_io_task(
+    io_type: usize,
+    block_id: usize,
+    buf_ptr: usize,
+    wake_task_repr: usize,
+) -> SyscallResult {
     match io_type {
         0 => SyscallResult::IOTask {
             block_id,
             buf_ptr,
             write: false,
+            wake_task_repr,
         },
         1 => SyscallResult::IOTask {
             block_id,
             buf_ptr,
             write: true,
+            wake_task_repr,
         },
         _ => panic!("unknown io type"),
     }
 }
 
-/// `kernel_check`系统调用
+/// 定时器系统调用
+///
+/// 内核在定时器队列里登记一个到`deadline_ms`时刻的定时器，到期时唤醒`wake_task_repr`对应的用户态任务。
+///
+/// 时间以毫秒为单位，和读取时间的系统调用使用同一个时钟
+fn do_timer_task(deadline_ms: usize, wake_task_repr: usize) -> SyscallResult {
+    SyscallResult::TimerTask {
+        deadline_ms,
+        wake_task_repr,
+    }
+}
+
+/// 用户允许抢占时没有指定时钟中断次数，连续运行这么多次时钟中断之后尝试抢占
+const DEFAULT_PREEMPT_TICKS: usize = 5;
+
+/// 允许内核抢占当前地址空间的系统调用
+///
+/// `current_task_ptr`是用户执行器记录当前任务的变量地址。时钟中断抢占用户程序的时候，
+/// 内核从这里读出被打断的任务，在共享调度器里把它设置为就绪，见[`user_trap_handler`]。
+/// `ticks`是连续运行多少次时钟中断之后尝试抢占，为0时使用默认值。可以再次调用来修改
+fn do_enable_preempt(user_satp: usize, current_task_ptr: usize, ticks: usize) -> SyscallResult {
+    let asid = KernelHartInfo::get_prev_asid();
+    let swap_cx = unsafe { get_swap_cx(&Satp(user_satp), asid) };
+    swap_cx.current_task_ptr = current_task_ptr;
+    swap_cx.preempt_ticks = if ticks == 0 {
+        DEFAULT_PREEMPT_TICKS
+    } else {
+        ticks
+    };
+    SyscallResult::ok(0)
+}
+
+/// 读取共享调度器统计信息的系统调用
 ///
-/// 进行内核检查
-fn do_check() -> SyscallResult {
-    SyscallResult::Check
+/// 把[`SchedulerStats`]复制到用户的缓冲区，返回统计信息的字节数。
+/// 缓冲区比统计信息短的时候只复制前面的部分，用户可以根据返回值判断两边的定义是否一致。
+/// 缓冲区不在用户可写的内存中时什么也不复制，返回错误码[`STATS_BAD_ADDRESS`]
+fn do_scheduler_stats(user_satp: usize, buf_ptr: usize, buf_len: usize) -> SyscallResult {
+    let shared_payload =
+        unsafe { SharedPayload::load(SHAREDPAYLOAD_BASE) }.expect("load shared payload");
+    let stats = unsafe { shared_payload.stats() };
+    let size = mem::size_of::<SchedulerStats>();
+    let bytes = unsafe { slice::from_raw_parts(&stats as *const _ as *const u8, size) };
+    match unsafe { copy_to_user(user_satp, buf_ptr, &bytes[..size.min(buf_len)]) } {
+        Some(()) => SyscallResult::ok(size),
+        None => SyscallResult::Procceed {
+            code: STATS_BAD_ADDRESS,
+            extra: 0,
+        },
+    }
 }
 
+/// 读取统计信息时，缓冲区不在用户可写的内存中，返回给用户的错误码
+const STATS_BAD_ADDRESS: usize = 1;
+
 #[allow(missing_docs)]
 fn do_process(param: [usize; 6], user_satp: usize, func: usize) -> SyscallResult {
     match func {
@@ -136,6 +215,16 @@ fn do_process(param: [usize; 6], user_satp: usize, func: usize) -> SyscallResult
             );
             SyscallResult::Terminate(-1)
         }
+        FUNC_PROCESS_SPAWN => {
+            let [path_ptr, path_len, argv_ptr, argc, envp_ptr, envc] = param;
+            do_spawn(
+                user_satp,
+                (path_ptr, path_len),
+                (argv_ptr, argc),
+                (envp_ptr, envc),
+            )
+        }
+        FUNC_PROCESS_WAIT => do_wait(param[0], param[1]),
         _ => panic!(
             "Unknown syscall process, func: {}, param: {:?}",
             func, param
@@ -143,6 +232,53 @@ fn do_process(param: [usize; 6], user_satp: usize, func: usize) -> SyscallResult
     }
 }
 
+/// 启动进程时，路径、参数或者环境变量不在用户能访问的内存中，返回给用户的错误码
+const SPAWN_BAD_ADDRESS: usize = 3;
+/// 启动进程时，路径、参数和环境变量放在参数块里超过了[`ARGS_MAX`]，返回给用户的错误码
+const SPAWN_ARGS_TOO_LONG: usize = 4;
+
+/// 启动新进程的系统调用
+///
+/// 用户传入程序路径、参数和环境变量，参数和环境变量都是(指针, 长度)对组成的数组，
+/// 每个环境变量的格式是`KEY=VALUE`。加载程序的工作由内核任务完成，见[`user::spawn_process`]
+///
+/// 读取的同时累计参数块的长度，超过[`ARGS_MAX`]就不再读取，复制失败时也马上返回错误码
+fn do_spawn(
+    user_satp: usize,
+    (path_ptr, path_len): (usize, usize),
+    (argv_ptr, argc): (usize, usize),
+    (envp_ptr, envc): (usize, usize),
+) -> SyscallResult {
+    let read = || -> Result<_, usize> {
+        let mut reader = ArgsReader::new(user_satp);
+        // 路径是新进程的第一个参数，在参数块里也占一个(指针, 长度)对
+        reader.reserve(2 * mem::size_of::<usize>())?;
+        let path = unsafe { reader.read_str(path_ptr, path_len)? };
+        let args = unsafe { reader.read_strs(argv_ptr, argc)? };
+        let envs = unsafe { reader.read_strs(envp_ptr, envc)? };
+        Ok((path, args, envs))
+    };
+    match read() {
+        Ok((path, args, envs)) => SyscallResult::SpawnTask { path, args, envs },
+        Err(code) => SyscallResult::Procceed { code, extra: 0 },
+    }
+}
+
+/// 等待进程退出的系统调用
+///
+/// 进程已经退出时`code`为0，退出码在`extra`中；
+/// 还在运行时`code`为1，进程退出后内核唤醒`wake_task_repr`表示的任务；没有这个子进程时`code`为2；
+/// 已经有别的任务在等待时`code`为3
+fn do_wait(pid: usize, wake_task_repr: usize) -> SyscallResult {
+    let asid = KernelHartInfo::get_prev_asid();
+    match user::wait_process(pid, asid, wake_task_repr) {
+        WaitStatus::Exited(exit_code) => SyscallResult::ok(exit_code as usize),
+        WaitStatus::Running => SyscallResult::Procceed { code: 1, extra: 0 },
+        WaitStatus::NotFound => SyscallResult::Procceed { code: 2, extra: 0 },
+        WaitStatus::Busy => SyscallResult::Procceed { code: 3, extra: 0 },
+    }
+}
+
 fn do_test_interface(param: [usize; 6], user_satp: usize, func: usize) -> SyscallResult {
     match func {
         FUNC_TEST_WRITE => {
@@ -227,3 +363,115 @@ unsafe fn get_user_buf_mut<'a>(user_satp: usize, buf_ptr: usize, buf_len: usize)
     let ptr = (va as *const u8).as_ref().expect("non-null pointer");
     core::slice::from_raw_parts_mut(ptr as *const _ as *mut _, buf_len)
 }
+
+/// 找到用户虚拟地址`va`所在的物理页，页没有映射或者用户不能访问时返回None
+///
+/// `writable`为true时还要求这一页用户可写
+unsafe fn user_page(user_satp: usize, va: usize, writable: bool) -> Option<PhysicalPageNumber> {
+    let entry = Satp(user_satp).find_pte(VirtualPageNumber::floor(VirtualAddress(va)))?;
+    let mut flags = Flags::USER;
+    flags.set(Flags::WRITABLE, writable);
+    if entry.is_valid() && entry.flags().contains(flags) {
+        Some(entry.page_number())
+    } else {
+        None
+    }
+}
+
+/// 从用户的缓冲区复制数据，缓冲区可以跨越页的边界
+///
+/// 缓冲区有一部分不在用户能访问的内存中时返回None。长度由用户决定，调用者应当先检查它
+unsafe fn copy_from_user(user_satp: usize, buf_ptr: usize, len: usize) -> Option<Vec<u8>> {
+    buf_ptr.checked_add(len)?;
+    let mut dst = Vec::with_capacity(len);
+    while dst.len() < len {
+        let va = buf_ptr + dst.len();
+        let offset = va.get_bits(0..12);
+        let n = (PAGE_SIZE - offset).min(len - dst.len());
+        let src = user_page(user_satp, va, false)?
+            .start_address()
+            .virtual_address_linear()
+            .0
+            .wrapping_add(offset) as *const u8;
+        dst.extend_from_slice(slice::from_raw_parts(src, n));
+    }
+    Some(dst)
+}
+
+/// 从用户内存读取启动进程用的字符串，同时累计它们在参数块中占用的长度
+///
+/// 参数块的布局和`user::push_args`相同，每次读取之前先从剩余的长度中扣除，
+/// 扣不够的时候不再复制。失败时得到返回给用户的错误码
+struct ArgsReader {
+    user_satp: usize,
+    /// 参数块还剩下的长度
+    remaining: usize,
+}
+
+impl ArgsReader {
+    fn new(user_satp: usize) -> Self {
+        Self {
+            user_satp,
+            // 参数个数和环境变量个数各占一项
+            remaining: ARGS_MAX - 2 * mem::size_of::<usize>(),
+        }
+    }
+
+    /// 在参数块中预留`len`字节
+    fn reserve(&mut self, len: usize) -> Result<(), usize> {
+        self.remaining = self.remaining.checked_sub(len).ok_or(SPAWN_ARGS_TOO_LONG)?;
+        Ok(())
+    }
+
+    /// 读取一个字符串，不是UTF-8的部分被替换
+    unsafe fn read_str(&mut self, ptr: usize, len: usize) -> Result<String, usize> {
+        self.reserve(len)?;
+        let bytes = copy_from_user(self.user_satp, ptr, len).ok_or(SPAWN_BAD_ADDRESS)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// 读取`count`个(指针, 长度)对指向的字符串
+    unsafe fn read_strs(&mut self, ptr: usize, count: usize) -> Result<Vec<String>, usize> {
+        const WORD: usize = mem::size_of::<usize>();
+        let len = count.checked_mul(2 * WORD).ok_or(SPAWN_ARGS_TOO_LONG)?;
+        self.reserve(len)?;
+        let pairs = copy_from_user(self.user_satp, ptr, len).ok_or(SPAWN_BAD_ADDRESS)?;
+        pairs
+            .chunks_exact(2 * WORD)
+            .map(|pair| {
+                let ptr = usize::from_le_bytes(pair[..WORD].try_into().unwrap());
+                let len = usize::from_le_bytes(pair[WORD..].try_into().unwrap());
+                self.read_str(ptr, len)
+            })
+            .collect()
+    }
+}
+
+/// 把数据复制到用户的缓冲区，缓冲区可以跨越页的边界
+///
+/// 缓冲区有一部分不在用户可写的内存中时返回None，这时什么也不复制
+unsafe fn copy_to_user(user_satp: usize, buf_ptr: usize, src: &[u8]) -> Option<()> {
+    buf_ptr.checked_add(src.len())?;
+    // 先检查所有的页，再开始复制
+    let mut pages = Vec::new();
+    let mut checked = 0;
+    while checked < src.len() {
+        let va = buf_ptr + checked;
+        let offset = va.get_bits(0..12);
+        let len = (PAGE_SIZE - offset).min(src.len() - checked);
+        let ppn = user_page(user_satp, va, true)?;
+        pages.push((ppn, offset, len));
+        checked += len;
+    }
+    let mut copied = 0;
+    for (ppn, offset, len) in pages {
+        let dst = ppn
+            .start_address()
+            .virtual_address_linear()
+            .0
+            .wrapping_add(offset) as *mut u8;
+        slice::from_raw_parts_mut(dst, len).copy_from_slice(&src[copied..copied + len]);
+        copied += len;
+    }
+    Some(())
+}
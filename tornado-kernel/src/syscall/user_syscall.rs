@@ -5,20 +5,33 @@ use crate::{
     hart::KernelHartInfo,
     memory::{self, Satp},
     memory::{AddressSpaceId, VirtualAddress, VirtualPageNumber, KERNEL_MAP_OFFSET},
-    plic, task,
+    plic,
+    task::{self, TaskResult},
     trap::timer,
     trap::{self, SwapContext},
-    SHAREDPAYLOAD_BASE,
+    user, SHAREDPAYLOAD_BASE,
 };
 #[allow(unused)]
 use crate::{sdcard::SD_CARD, virtio::VIRTIO_BLOCK};
+use core::{
+    future::Future,
+    mem, ptr,
+    task::{RawWaker, RawWakerVTable, Waker},
+};
 use riscv::register::{
     scause::{self, Interrupt, Trap},
     sepc, stval,
 };
 
 const BLOCK_SIZE: usize = 512;
-pub static mut WAKE_NUM: usize = 1;
+/// 块设备读写任务的优先级
+///
+/// 用户任务在等待这些任务完成，它们应当排在计算任务的前面
+const WAKE_TASK_PRIORITY: u8 = 0;
+/// 共享调度器已满，块设备读写任务没有被接收时返回给用户的错误码
+const WAKE_TASK_REJECTED: usize = 1;
+/// 没有空闲的地址空间编号，不能启动新进程时返回给用户的错误码
+const SPAWN_NO_ADDRESS_SPACE: usize = 2;
 
 /// 中断/异常/系统调用处理函数，用户态发生中断/异常/系统调用会陷入到这里
 #[no_mangle]
@@ -37,16 +50,23 @@ pub extern "C" fn user_trap_handler() {
     let a7 = swap_cx.x[16];
     match scause::read().cause() {
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            // todo: 切换地址空间
-            //
-            // 不跳过指令，继续运行
             timer::tick();
+            // 用户程序运行太久的时候切换到下一个地址空间，上下文已经保存在`swap_cx`中。
+            // 不跳过指令，切换回来的时候从被打断的地方继续运行
+            if preempt_user(swap_cx, &user_satp) {
+                run_kernel_tasks()
+            }
+            trap::switch_to_user(swap_cx, user_satp.inner(), asid)
+        }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            // 其它硬件线程设置了任务就绪，用户执行器自己会去共享调度器里找任务，直接回到用户态
+            trap::clear_software_pending();
             trap::switch_to_user(swap_cx, user_satp.inner(), asid)
         }
         Trap::Exception(scause::Exception::Breakpoint) => {
-            // 用户目前通过断点异常通知内核发生了错误，这时候直接退出
+            // 用户目前通过断点异常通知内核发生了错误，这时候卸载这个用户程序
             println!("user mode panic!");
-            crate::sbi::shutdown();
+            exit_user(asid, -1)
         }
         Trap::Exception(scause::Exception::UserEnvCall) => {
             // 用户系统调用
@@ -61,124 +81,124 @@ pub extern "C" fn user_trap_handler() {
                     // 不跳过指令，继续运行
                     trap::switch_to_user(swap_cx, user_satp.inner(), asid)
                 }
-                SyscallResult::NextASID { asid, satp } => {
+                SyscallResult::NextASID {
+                    asid: next_asid,
+                    satp,
+                } => {
                     // 需要切换地址空间
                     // 跳过`do_yield`指令
                     swap_cx.epc = swap_cx.epc.wrapping_add(4);
+                    if !KernelHartInfo::try_enter_user(next_asid) {
+                        // 目标地址空间正在其它硬件线程上运行，先回到原来的地址空间，它的执行器之后会再次让出
+                        trap::switch_to_user(swap_cx, user_satp.inner(), asid)
+                    }
                     // 需要转到目标地址空间去运行
-                    // println!("[syscall] yield: {}", asid);
-                    let next_swap_contex = unsafe { get_swap_cx(&satp, asid) };
-                    trap::switch_to_user(next_swap_contex, satp.inner(), asid)
+                    // println!("[syscall] yield: {}", next_asid);
+                    let next_swap_contex = unsafe { get_swap_cx(&satp, next_asid) };
+                    trap::switch_to_user(next_swap_contex, satp.inner(), next_asid)
                 }
                 SyscallResult::KernelTask => {
                     // 需要运行内核任务
                     // 跳过 `do_yield` 指令
                     swap_cx.epc = swap_cx.epc.wrapping_add(4);
                     // println!("[syscall] yield kernel");
-                    let shared_payload =
-                        unsafe { async_rt::SharedPayload::load(crate::SHAREDPAYLOAD_BASE) };
-                    trap::init();
-                    async_rt::run_until_idle(
-                        || unsafe { shared_payload.peek_task(async_rt::kernel_should_switch) },
-                        |task_repr| unsafe { shared_payload.delete_task(task_repr) },
-                        |task_repr, new_state| unsafe {
-                            shared_payload.set_task_state(task_repr, new_state)
-                        },
-                    );
-                    crate::end()
+                    run_kernel_tasks()
                 }
                 SyscallResult::IOTask {
                     // 需要注册IO任务
                     block_id,
                     buf_ptr,
                     write,
+                    wake_task_repr,
                 } => {
-                    let wake_task_repr = unsafe { next_task_repr() };
-                    let process = KernelHartInfo::current_process().expect("get kernel process");
-                    unsafe {
-                        let shared_payload = async_rt::SharedPayload::load(SHAREDPAYLOAD_BASE);
-                        let task = if write {
-                            task::new_kernel(
-                                write_block_task(
-                                    block_id,
-                                    buf_ptr,
-                                    user_satp.inner(),
-                                    wake_task_repr,
-                                ),
-                                process,
-                                shared_payload.shared_scheduler,
-                                shared_payload.shared_set_task_state,
-                            )
-                        } else {
-                            task::new_kernel(
-                                read_block_task(
-                                    block_id,
-                                    buf_ptr,
-                                    user_satp.inner(),
-                                    wake_task_repr,
-                                ),
-                                process,
-                                shared_payload.shared_scheduler,
-                                shared_payload.shared_set_task_state,
-                            )
+                    // 读写结束之前这个地址空间不会被释放，任务没能加入共享调度器时随之结束
+                    let io = user::UserIo::begin(unsafe { AddressSpaceId::from_raw(asid) });
+                    swap_cx.x[9] = if write {
+                        add_wake_task(write_block_task(
+                            block_id,
+                            buf_ptr,
+                            user_satp.inner(),
+                            wake_task_repr,
+                            io,
+                        ))
+                    } else {
+                        add_wake_task(read_block_task(
+                            block_id,
+                            buf_ptr,
+                            user_satp.inner(),
+                            wake_task_repr,
+                            io,
+                        ))
+                    };
+                    // 运行下一条指令
+                    swap_cx.epc = swap_cx.epc.wrapping_add(4);
+                    trap::switch_to_user(swap_cx, user_satp.inner(), asid)
+                }
+                SyscallResult::TimerTask {
+                    // 需要登记定时器
+                    deadline_ms,
+                    wake_task_repr,
+                } => {
+                    let deadline = timer::Instant::from_millis(deadline_ms);
+                    let owner = unsafe { AddressSpaceId::from_raw(asid) };
+                    async_rt::add_timer(deadline, owner, user_task_waker(wake_task_repr));
+                    swap_cx.x[9] = 0;
+                    // 运行下一条指令
+                    swap_cx.epc = swap_cx.epc.wrapping_add(4);
+                    trap::switch_to_user(swap_cx, user_satp.inner(), asid)
+                }
+                SyscallResult::WaitInterrupt => {
+                    // 用户执行器没有可以运行的任务，在内核里等待中断。
+                    // 回到用户态之后挂起的中断马上陷入，由这个函数的中断分支处理
+                    let shared_payload =
+                        unsafe { async_rt::SharedPayload::load(SHAREDPAYLOAD_BASE) }
+                            .expect("load shared payload");
+                    async_rt::wait_for_interrupt(|| {
+                        let task = unsafe {
+                            shared_payload.peek_task(KernelHartInfo::hart_id(), user_should_switch)
                         };
-                        let task_repr = task.task_repr();
-                        // println!("[syscall] new kernel task: {:x}", task_repr);
-                        ext_intr_off();
-                        shared_payload.add_task(0, AddressSpaceId::from_raw(0), task_repr);
-                        ext_intr_on();
-                    }
+                        !matches!(task, TaskResult::NoWakeTask)
+                    });
                     // 运行下一条指令
                     swap_cx.epc = swap_cx.epc.wrapping_add(4);
                     trap::switch_to_user(swap_cx, user_satp.inner(), asid)
                 }
-                SyscallResult::Check => {
-                    // 内核检查
-                    // 如果有未唤醒的块设备读写任务，将其唤醒
-                    unsafe {
-                        if WAKE_NUM > 1 {
-                            VIRTIO_BLOCK.0.wake_ops.notify(WAKE_NUM);
-                            WAKE_NUM = 1;
-                        }
-                    }
+                SyscallResult::SpawnTask { path, args, envs } => {
+                    // 需要启动新进程，由内核任务加载程序，加载完成之后内核执行器进入它的地址空间
+                    let stack_top = KernelHartInfo::trap_stack().expect("get trap stack");
+                    let (code, pid) = match user::spawn_process(asid, path, args, envs, stack_top) {
+                        Some((pid, load_task)) => match add_wake_task(load_task) {
+                            0 => (0, pid),
+                            code => {
+                                user::cancel_process(pid);
+                                (code, 0)
+                            }
+                        },
+                        None => (SPAWN_NO_ADDRESS_SPACE, 0),
+                    };
+                    swap_cx.x[9] = code;
+                    swap_cx.x[10] = pid;
                     // 运行下一条指令
                     swap_cx.epc = swap_cx.epc.wrapping_add(4);
                     trap::switch_to_user(swap_cx, user_satp.inner(), asid)
                 }
-                SyscallResult::Terminate(_exit_code) => {
-                    // 用户执行器认为可以退出系统了
-                    println!("User exit!");
-                    crate::sbi::shutdown();
+                SyscallResult::Terminate(exit_code) => {
+                    // 用户程序退出，卸载它之后继续运行其它任务
+                    println!("[kernel] asid {} exit with code {}", asid, exit_code);
+                    exit_user(asid, exit_code)
                 }
             }
         }
         Trap::Interrupt(Interrupt::SupervisorExternal) => {
-            // 用户态被外部中断打断
             // 用户态被外部中断打断
             //
-            // todo: 这里有个问题，就是最后一次外部中断的时候程序运行在共享调度器里面的时候
-            // 后面的任务不会被唤醒，执行器一直轮询找不到`醒着的`任务
-            //
-            // 目前的想法是用户执行器需要一个自检机制，当一直轮询找不到任务的次数达到某个阈值的时候
-            // 通过一个系统调用陷入内核检查是否有没有唤醒的块设备读写任务，将其唤醒
+            // 即使被打断的是共享调度器，而且它正拿着锁，这里也可以直接唤醒：
+            // 唤醒只把任务放进共享调度器的无锁唤醒队列，共享调度器下一次拿锁时就能看到
             unsafe {
                 let irq = plic::plic_claim();
                 if irq == 1 {
-                    // virtio 外部中断
-                    let _intr_ret = VIRTIO_BLOCK.handle_interrupt().unwrap();
-                    let sepc = sepc::read();
-                    if sepc < SHAREDPAYLOAD_BASE {
-                        println!("extr intr in user");
-                        // 运行在用户程序中
-                        // 唤醒一定数量的任务
-                        VIRTIO_BLOCK.0.wake_ops.notify(WAKE_NUM);
-                        WAKE_NUM = 1;
-                    } else {
-                        // 运行在共享调度器中
-                        // 这里不唤醒，增加计数，下次外部中断来的时候一起唤醒
-                        println!("extr intr in shared");
-                        WAKE_NUM += 1;
-                    }
+                    // virtio 外部中断，唤醒所有请求已经完成的块设备读写任务
+                    VIRTIO_BLOCK.handle_interrupt();
                     plic::plic_complete(irq);
                     // 不跳过指令回到用户态运行
                     trap::switch_to_user(swap_cx, user_satp.inner(), asid)
@@ -205,6 +225,75 @@ pub extern "C" fn user_trap_handler() {
     }
 }
 
+/// 时钟中断时检查是否要抢占当前的用户程序，需要抢占时返回true
+///
+/// 用户程序连续运行的时钟中断次数达到它允许抢占时指定的次数，才检查共享调度器。
+/// 只在用户执行器正在运行任务、并且不在共享调度器里面时抢占，否则用户程序可能拿着共享调度器的锁。
+/// 被打断的任务重新设置为就绪，其它执行器轮到它的时候会切换回来，让它从打断的地方继续运行。
+/// 抢占之后回到内核执行器，由它从共享调度器取出下一个任务，切换到任务所在的地址空间
+fn preempt_user(swap_cx: &SwapContext, user_satp: &Satp) -> bool {
+    if KernelHartInfo::tick_user() < swap_cx.preempt_ticks
+        || swap_cx.current_task_ptr == 0
+        || swap_cx.epc >= SHAREDPAYLOAD_BASE
+    {
+        return false;
+    }
+    let buf = unsafe {
+        super::get_user_buf(
+            user_satp.inner(),
+            swap_cx.current_task_ptr,
+            mem::size_of::<usize>(),
+        )
+    };
+    let task_repr = unsafe { ptr::read_volatile(buf.as_ptr() as *const usize) };
+    if task_repr == 0 {
+        // 执行器在两个任务之间，马上就会回到共享调度器
+        return false;
+    }
+    let shared_payload =
+        unsafe { async_rt::SharedPayload::load(SHAREDPAYLOAD_BASE) }.expect("load shared payload");
+    let asid = unsafe { AddressSpaceId::from_raw(KernelHartInfo::get_prev_asid()) };
+    // 只查看，不从共享调度器里取任务，没有其它地址空间的任务在等待时不改变调度器的状态
+    if !unsafe { shared_payload.has_ready_task(KernelHartInfo::hart_id(), asid) } {
+        // 继续运行，下一次时钟中断再检查
+        return false;
+    }
+    unsafe { shared_payload.set_task_state(task_repr, TaskState::Ready) };
+    KernelHartInfo::reset_user_ticks();
+    true
+}
+
+/// 下一个任务不在被打断的用户地址空间时，应当切换过去
+extern "C" fn user_should_switch(address_space_id: AddressSpaceId) -> bool {
+    address_space_id.into_inner() != KernelHartInfo::get_prev_asid()
+}
+
+/// 在当前硬件线程上运行内核执行器
+///
+/// 其它用户程序还有任务时，内核执行器会切换过去；所有任务都结束了才关机
+fn run_kernel_tasks() -> ! {
+    KernelHartInfo::leave_user();
+    let shared_payload =
+        unsafe { async_rt::SharedPayload::load(SHAREDPAYLOAD_BASE) }.expect("load shared payload");
+    trap::init();
+    async_rt::run_until_idle(
+        || unsafe {
+            shared_payload.peek_task(KernelHartInfo::hart_id(), async_rt::kernel_should_switch)
+        },
+        |task_repr| unsafe { shared_payload.delete_task(task_repr) },
+        |task_repr, new_state| unsafe { shared_payload.set_task_state(task_repr, new_state) },
+        || unsafe { shared_payload.requeue_task(KernelHartInfo::hart_id()) },
+    );
+    crate::end()
+}
+
+/// 卸载地址空间为`asid`的用户程序，唤醒等待它退出的进程，然后运行内核执行器
+fn exit_user(asid: usize, exit_code: i32) -> ! {
+    user::unload_user(unsafe { AddressSpaceId::from_raw(asid) });
+    user::exit_process(asid, exit_code);
+    run_kernel_tasks()
+}
+
 /// 获取[`SwapContext`]的可变引用
 ///
 /// 给定satp寄存器，获取[`SwapContext`]的裸指针
@@ -223,56 +312,90 @@ pub unsafe fn get_swap_cx<'cx>(satp: &'cx Satp, asid: usize) -> &'cx mut SwapCon
         .unwrap()
 }
 
-#[allow(missing_docs)]
-async fn read_block_task(block_id: usize, buf_ptr: usize, user_satp: usize, wake_task_repr: usize) {
+/// 创建一个完成后唤醒用户任务的内核任务，添加到共享调度器中，也用于加载新进程
+///
+/// 返回给用户的错误码，共享调度器放不下新任务时丢掉这个任务，让用户稍后重试
+fn add_wake_task(future: impl Future<Output = ()> + 'static + Send + Sync) -> usize {
+    let process = KernelHartInfo::current_process().expect("get kernel process");
+    unsafe {
+        let shared_payload =
+            async_rt::SharedPayload::load(SHAREDPAYLOAD_BASE).expect("load shared payload");
+        let task = task::new_kernel(
+            future,
+            process,
+            shared_payload.shared_scheduler,
+            shared_payload.shared_set_task_state,
+        );
+        ext_intr_off();
+        let asid = AddressSpaceId::from_raw(0);
+        let code =
+            match shared_payload.add_kernel_task_with_priority(0, asid, task, WAKE_TASK_PRIORITY) {
+                Ok(_task_repr) => 0,
+                Err(_task) => WAKE_TASK_REJECTED,
+            };
+        ext_intr_on();
+        code
+    }
+}
+
+/// 唤醒`wake_task_repr`对应的用户态任务
+fn wake_user_task(wake_task_repr: usize) {
+    unsafe {
+        let shared_payload =
+            async_rt::SharedPayload::load(SHAREDPAYLOAD_BASE).expect("load shared payload");
+        ext_intr_off();
+        shared_payload.set_task_state(wake_task_repr, TaskState::Ready);
+        ext_intr_on();
+    }
+}
+
+/// 读块设备到用户的缓冲区，完成之后唤醒用户任务；程序已经卸载时不再唤醒
+async fn read_block_task(
+    block_id: usize,
+    buf_ptr: usize,
+    user_satp: usize,
+    wake_task_repr: usize,
+    io: user::UserIo,
+) {
     let buf = unsafe { super::get_user_buf_mut(user_satp, buf_ptr, BLOCK_SIZE) };
     #[cfg(feature = "qemu")]
     VIRTIO_BLOCK.read_block(block_id, buf).await;
     #[cfg(feature = "k210")]
     SD_CARD.read_block(block_id, buf).await;
-    unsafe {
-        let shared_payload = async_rt::SharedPayload::load(SHAREDPAYLOAD_BASE);
-        ext_intr_off();
-        shared_payload.set_task_state(wake_task_repr, TaskState::Ready);
-        ext_intr_on();
+    if io.is_live() {
+        wake_user_task(wake_task_repr);
     }
 }
 
-#[allow(missing_docs)]
+/// 把用户的缓冲区写到块设备，完成之后唤醒用户任务；程序已经卸载时不再唤醒
 async fn write_block_task(
     block_id: usize,
     buf_ptr: usize,
     user_satp: usize,
     wake_task_repr: usize,
+    io: user::UserIo,
 ) {
     let buf = unsafe { super::get_user_buf_mut(user_satp, buf_ptr, BLOCK_SIZE) };
     VIRTIO_BLOCK.write_block(block_id, buf).await;
-    unsafe {
-        let shared_payload = async_rt::SharedPayload::load(SHAREDPAYLOAD_BASE);
-        ext_intr_off();
-        shared_payload.set_task_state(wake_task_repr, TaskState::Ready);
-        ext_intr_on();
+    if io.is_live() {
+        wake_user_task(wake_task_repr);
     }
 }
 
-/// 从共享调度器中拿出下一个任务的指针，不弹出
+/// 唤醒`wake_task_repr`对应的用户态任务的唤醒器，登记到定时器队列里
 ///
-/// note: 这个函数需要保证调用时共享调度器`peek_task`的返回值是[`TaskResult::Task`]
-///
-/// 一般只用于`enroll_read`或`enroll_write`系统调用
-unsafe fn next_task_repr() -> usize {
-    let shared_payload = async_rt::SharedPayload::load(SHAREDPAYLOAD_BASE);
-    ext_intr_off();
-    let next_task = shared_payload.peek_task(should_switch);
-    ext_intr_on();
-    match next_task {
-        task::TaskResult::Task(task_repr) => task_repr,
-        _ => unreachable!(),
+/// 唤醒器只保存任务指针，复制和释放都不需要堆内存，可以在时钟中断里释放。
+/// 用户任务在这之前结束的话，唤醒的是已经删除的任务，共享调度器会忽略它
+fn user_task_waker(wake_task_repr: usize) -> Waker {
+    fn clone(data: *const ()) -> RawWaker {
+        RawWaker::new(data, &VTABLE)
     }
-}
-
-extern "C" fn should_switch(asid: AddressSpaceId) -> bool {
-    asid.into_inner() != KernelHartInfo::get_prev_asid()
+    fn wake(data: *const ()) {
+        wake_user_task(data as usize);
+    }
+    fn drop(_data: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+    unsafe { Waker::from_raw(RawWaker::new(wake_task_repr as *const (), &VTABLE)) }
 }
 
 /// 读取当前 PC 值
@@ -3,14 +3,23 @@
 mod kernel_task;
 mod process;
 
+pub use join_handle::JoinError;
+use join_handle::{joinable, Abort};
 pub use kernel_task::{KernelTask, TaskId};
 pub use process::{Process, ProcessId};
 pub use rv_lock::{Lock, LockGuard};
 
-use crate::async_rt::TaskState;
+use crate::{
+    async_rt::{self, SharedPayload, TaskState},
+    hart::KernelHartInfo,
+    SHAREDPAYLOAD_BASE,
+};
 use alloc::sync::Arc;
 use core::{future::Future, ptr::NonNull};
 
+/// 内核任务的句柄，见[`spawn`]
+pub type JoinHandle<T> = join_handle::JoinHandle<T, KernelTaskRepr>;
+
 /// 共享调度器返回的结果
 #[derive(Debug)]
 #[repr(C)]
@@ -40,6 +49,38 @@ pub fn new_kernel(
     ))
 }
 
+/// 在当前进程里生成一个内核任务，放到当前硬件线程的队列中，返回任务的句柄
+///
+/// 共享调度器放不下新任务时会panic
+#[allow(unused)] // 目前只有演示任务使用
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + Sync + 'static,
+    F::Output: Send + 'static,
+{
+    let process = KernelHartInfo::current_process().expect("get kernel process");
+    let address_space_id = process.address_space_id();
+    let shared_payload =
+        unsafe { SharedPayload::load(SHAREDPAYLOAD_BASE) }.expect("load shared payload");
+    let (task, handle) = joinable(future, |future| {
+        new_kernel(
+            future,
+            process,
+            shared_payload.shared_scheduler,
+            shared_payload.shared_set_task_state,
+        )
+    });
+    async_rt::ext_intr_off();
+    let ans = unsafe {
+        shared_payload.add_kernel_task(KernelHartInfo::hart_id(), address_space_id, task)
+    };
+    async_rt::ext_intr_on();
+    if ans.is_err() {
+        panic!("cannot spawn kernel task: shared scheduler is full")
+    }
+    handle
+}
+
 /// 内核任务的表示
 #[derive(Debug)]
 pub struct KernelTaskRepr(
@@ -62,9 +103,18 @@ impl KernelTaskRepr {
         let shared_scheduler = NonNull::new(self.1 as *mut ()).unwrap();
         let task_repr = Arc::as_ptr(self) as usize;
         (self.2)(shared_scheduler, task_repr, TaskState::Ready);
+        KernelHartInfo::notify_idle_harts();
     }
     #[inline]
     pub fn task(&self) -> &KernelTask {
         &self.0
     }
 }
+
+impl Abort for KernelTaskRepr {
+    /// 内核任务可能正在其它硬件线程上运行，不能直接删除。
+    /// 唤醒任务，[`Joinable`](join_handle::Joinable)下一次被轮询时完成，由执行器通过`delete_task`删除
+    fn abort(self: Arc<Self>) {
+        unsafe { self.do_wake() }
+    }
+}
@@ -41,6 +41,17 @@ impl Process {
         Some(process)
     }
 
+    /// 在当前硬件线程上加载这个进程，激活它的地址空间映射
+    ///
+    /// 其它硬件线程启动的时候，用它加载启动硬件线程创建的内核进程
+    pub fn load_on_hart(self: &Arc<Self>) {
+        self.inner.lock().memory_set.activate();
+        unsafe {
+            KernelHartInfo::load_address_space_id(self.address_space_id());
+            KernelHartInfo::load_process(self.clone());
+        };
+    }
+
     /// 创建一个用户进程
     ///
     /// 暂时和创建内核进程无太大区别，后续会思考这部分设计
@@ -1,7 +1,7 @@
 use super::timer;
 use crate::{
     plic,
-    syscall::{syscall as do_syscall, SyscallResult, WAKE_NUM},
+    syscall::{syscall as do_syscall, SyscallResult},
 };
 use core::fmt;
 use riscv::register::{
@@ -242,11 +242,34 @@ pub extern "C" fn rust_supervisor_timer(trap_frame: &mut TrapFrame) -> *mut Trap
     trap_frame
 }
 
-/// S态软件中断
+/// S态软件中断，其它硬件线程通过处理器间中断发送
+#[naked]
+#[link_section = ".text"]
+pub unsafe extern "C" fn supervisor_software() {
+    asm!(
+        define_load_store!(),
+        save_non_switch!(),
+        "mv     a0, sp",
+        "call   {supervisor_software}",
+        restore_non_switch!(),
+        "sret",
+        REGBYTES = const core::mem::size_of::<usize>(),
+        supervisor_software = sym rust_supervisor_software,
+        options(noreturn)
+    )
+}
+
+/// S态软件中断处理函数
 ///
-/// unimplemented
-pub fn supervisor_software() {
-    panic!("Supervisor software: {:08x}", sepc::read());
+/// 处理器间中断只用来把硬件线程从`wfi`中叫醒，清除挂起位之后回到执行器重新查找任务
+pub extern "C" fn rust_supervisor_software(trap_frame: &mut TrapFrame) -> *mut TrapFrame {
+    clear_software_pending();
+    trap_frame
+}
+
+/// 清除S态软件中断的挂起位，用户态被软件中断打断时也要调用
+pub fn clear_software_pending() {
+    unsafe { asm!("csrci sip, {}", const 1 << 1, options(nomem, nostack)) };
 }
 
 /// S态外部中断
@@ -272,14 +295,9 @@ pub unsafe extern "C" fn rust_supervisor_external(trap_frame: &mut TrapFrame) ->
     let irq = plic::plic_claim();
     if irq == 1 {
         // virtio 外部中断
-        // 获得数据传输完成的块号
-        let _intr_ret = crate::virtio::VIRTIO_BLOCK
-            .handle_interrupt()
-            .expect("virtio handle interrupt error!");
-        // println!("[kernel] virtio intr: {}", _intr_ret);
-        // 唤醒相应的块设备读写任务
-        crate::virtio::VIRTIO_BLOCK.0.wake_ops.notify(WAKE_NUM);
-        WAKE_NUM = 1;
+        // 一次中断可能完成了多个请求，唤醒所有请求已经完成的块设备读写任务
+        let _completed = crate::virtio::VIRTIO_BLOCK.handle_interrupt();
+        // println!("[kernel] virtio intr: {}", _completed);
         // 通知 PLIC 外部中断已经处理完
         crate::plic::plic_complete(irq);
         trap_frame
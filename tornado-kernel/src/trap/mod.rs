@@ -25,7 +25,7 @@ mod handler;
 mod switch;
 pub mod timer;
 
-pub use handler::{trap_vector, TrapFrame};
+pub use handler::{clear_software_pending, trap_vector, TrapFrame};
 pub use switch::*;
 
 /// 初始化中断相关的子模块
@@ -38,6 +38,7 @@ pub fn init() {
     unsafe {
         riscv::register::sstatus::set_sie();
         riscv::register::sie::set_sext();
+        riscv::register::sie::set_ssoft();
     }
 
     // println!("[kernel] mod interrupt initialized");
@@ -7,7 +7,7 @@
 //! 地址计算方法请参考`src/memory/config.rs`函数。
 
 use crate::{
-    hart::KernelHartInfo,
+    hart::{read_tp, KernelHartInfo},
     memory::{swap_contex_va, SWAP_FRAME_VA},
 };
 /// 内核态和用户态切换时需要保存的上下文
@@ -26,6 +26,10 @@ pub struct SwapContext {
     pub epc: usize, // 34
     /// 内核 tp 寄存器的值
     pub kernel_tp: usize, // 35
+    /// 用户执行器记录当前任务的变量地址，为0时内核不抢占这个地址空间
+    pub current_task_ptr: usize, // 36
+    /// 连续运行这么多次时钟中断之后尝试抢占这个地址空间，用户允许抢占时指定
+    pub preempt_ticks: usize, // 37
 }
 
 impl SwapContext {
@@ -46,6 +50,8 @@ impl SwapContext {
             user_trap_handler,
             epc: user_entry,
             kernel_tp,
+            current_task_ptr: 0,
+            preempt_ticks: 0,
             x: [0; 31],
         };
         swap_context.set_sp(user_stack);
@@ -200,7 +206,7 @@ pub unsafe extern "C" fn supervisor_to_user() -> ! {
 ///
 /// 让这个函数接收一个[`SwapContext`]结构的引用和用户的页表还有地址空间编号
 #[no_mangle]
-pub fn switch_to_user(context: &SwapContext, user_satp: usize, user_asid: usize) -> ! {
+pub fn switch_to_user(context: &mut SwapContext, user_satp: usize, user_asid: usize) -> ! {
     use riscv::register::{
         sstatus::{self, SPP},
         stvec::{self, TrapMode},
@@ -228,6 +234,12 @@ pub fn switch_to_user(context: &SwapContext, user_satp: usize, user_asid: usize)
         sstatus::set_spp(SPP::User);
     }
 
+    // 地址空间可能换到别的硬件线程上运行，陷入内核时要回到当前硬件线程的上下文和内核栈
+    context.kernel_tp = read_tp();
+    if let Some(stack_top) = KernelHartInfo::trap_stack() {
+        context.kernel_stack = stack_top;
+    }
+
     // 将 SwapContext.epc 写到 sepc 寄存器
     // 这个是用户程序入口
     riscv::register::sepc::write(context.epc);
@@ -1,8 +1,11 @@
 use crate::sbi::set_timer;
+use core::{ops::Add, time::Duration};
 use riscv::register::{sie, time};
 
 const MSEC_PER_SEC: usize = 1000;
 
+const NSEC_PER_SEC: u128 = 1_000_000_000;
+
 #[cfg(feature = "qemu")]
 const CLOCK_FREQ: usize = 12500000;
 
@@ -32,8 +35,45 @@ pub fn tick() {
     unsafe {
         TICKS = TICKS.wrapping_add(1);
     }
+    crate::async_rt::wake_expired_timers();
 }
 
 pub fn get_time_ms() -> usize {
     time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
-}
\ No newline at end of file
+}
+
+/// 时刻，即`time`寄存器的读数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(usize);
+
+impl Instant {
+    /// 当前时刻
+    pub fn now() -> Instant {
+        Instant(time::read())
+    }
+
+    /// 启动之后第`ms`毫秒的时刻，和[`get_time_ms`]使用同一个时钟
+    pub fn from_millis(ms: usize) -> Instant {
+        Instant(ms.saturating_mul(CLOCK_FREQ / MSEC_PER_SEC))
+    }
+
+    /// 从`earlier`到这个时刻经过的时间，`earlier`更晚时返回零
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        let ticks = self.0.saturating_sub(earlier.0) as u128;
+        Duration::from_nanos((ticks * NSEC_PER_SEC / CLOCK_FREQ as u128) as u64)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+    /// 超出`time`寄存器的范围时取最大值
+    fn add(self, rhs: Duration) -> Instant {
+        let ticks = rhs.as_nanos() * CLOCK_FREQ as u128 / NSEC_PER_SEC;
+        let ticks = if ticks > usize::MAX as u128 {
+            usize::MAX
+        } else {
+            ticks as usize
+        };
+        Instant(self.0.saturating_add(ticks))
+    }
+}
@@ -0,0 +1,75 @@
+//! 把用户程序的参数和环境变量放到用户栈的顶部
+//!
+//! 参数块从低地址到高地址依次是：
+//! * 参数个数`argc`，然后是`argc`个(指针, 长度)对
+//! * 环境变量个数`envc`，然后是`envc`个(指针, 长度)对，每个环境变量的格式是`KEY=VALUE`
+//! * 所有字符串的内容，不以零结尾
+//!
+//! 每一项都是`usize`。参数块的地址通过`a0`寄存器传给用户程序，用户栈从参数块的下面开始
+use crate::memory::{Mapping, VirtualAddress, VirtualPageNumber, KERNEL_MAP_OFFSET, PAGE_SIZE};
+use alloc::{string::String, vec::Vec};
+use core::mem::size_of;
+
+/// 参数块的最大长度
+pub const ARGS_MAX: usize = 4 * PAGE_SIZE;
+
+/// 参数块能否放在用户栈上，长度不能超过[`ARGS_MAX`]
+pub fn args_fit(args: &[String], envs: &[String]) -> bool {
+    let (header_len, strings_len) = block_len(args, envs);
+    header_len + strings_len <= ARGS_MAX
+}
+
+/// 参数块的头部和字符串部分的长度
+fn block_len(args: &[String], envs: &[String]) -> (usize, usize) {
+    let header_len = (2 + 2 * (args.len() + envs.len())) * size_of::<usize>();
+    let strings_len = args.iter().chain(envs).map(|s| s.len()).sum();
+    (header_len, strings_len)
+}
+
+/// 在`stack_top`下面写入参数块，返回参数块的地址，按16字节对齐
+///
+/// 用户栈必须已经映射，参数块的长度应当先用[`args_fit`]检查
+pub fn push_args(mapping: &Mapping, stack_top: usize, args: &[String], envs: &[String]) -> usize {
+    let (header_len, strings_len) = block_len(args, envs);
+    assert!(header_len + strings_len <= ARGS_MAX, "args block too long");
+    let base = (stack_top - header_len - strings_len) & !0xf;
+    let mut header = Vec::with_capacity(header_len / size_of::<usize>());
+    let mut string_ptr = base + header_len;
+    for list in [args, envs].iter() {
+        header.push(list.len());
+        for s in list.iter() {
+            header.push(string_ptr);
+            header.push(s.len());
+            string_ptr += s.len();
+        }
+    }
+    let mut bytes = Vec::with_capacity(header_len + strings_len);
+    for word in header {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    for s in args.iter().chain(envs) {
+        bytes.extend_from_slice(s.as_bytes());
+    }
+    copy_to_mapping(mapping, base, &bytes);
+    base
+}
+
+/// 通过页表把数据复制到还没有激活的地址空间，可以跨越页的边界
+fn copy_to_mapping(mapping: &Mapping, va: usize, src: &[u8]) {
+    let mut copied = 0;
+    while copied < src.len() {
+        let va = va + copied;
+        let offset = va % PAGE_SIZE;
+        let len = (PAGE_SIZE - offset).min(src.len() - copied);
+        let ppn = mapping
+            .translate(VirtualPageNumber::floor(VirtualAddress(va)))
+            .expect("user stack mapped")
+            .page_number();
+        let dst = ppn
+            .start_address()
+            .0
+            .wrapping_add(KERNEL_MAP_OFFSET + offset) as *mut u8;
+        unsafe { dst.copy_from_nonoverlapping(src[copied..].as_ptr(), len) };
+        copied += len;
+    }
+}
@@ -0,0 +1,165 @@
+//! 解析用户程序的ELF文件
+//!
+//! 只支持小端序、RISC-V架构的ELF64可执行文件，只关心入口地址和需要加载的段
+use crate::{
+    memory::{Flags, VirtualAddress},
+    SHAREDPAYLOAD_BASE,
+};
+use alloc::vec::Vec;
+use core::{convert::TryInto, ops::Range};
+
+/// ELF文件头的长度
+const HEADER_SIZE: usize = 64;
+/// 程序头的长度
+const PROGRAM_HEADER_SIZE: usize = 56;
+/// 可执行文件
+const ET_EXEC: u16 = 2;
+/// RISC-V架构
+const EM_RISCV: u16 = 0xf3;
+/// 需要加载到内存的段
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+const PF_R: u32 = 1 << 2;
+/// 用户程序的段必须在这个地址之下，Sv39的高半部分放着交换页和各个地址空间的[`SwapContext`](crate::trap::SwapContext)
+const USER_VA_END: usize = 1 << 38;
+/// 共享调度器映射的长度，和[`MemorySet`](crate::memory::MemorySet)中映射的长度相同
+const SHAREDPAYLOAD_SIZE: usize = 0x40_0000;
+
+/// 解析ELF文件时发生的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// 文件不完整，头部或者段超出了文件的长度
+    Truncated,
+    /// 文件不以ELF魔数开头
+    BadMagic,
+    /// 不是小端序的ELF64文件
+    UnsupportedClass,
+    /// 不是RISC-V的可执行文件
+    UnsupportedType,
+    /// 段的大小不合法，或者文件中没有需要加载的段
+    BadSegment,
+    /// 段和内核保留给每个地址空间的映射重叠
+    ReservedRange,
+}
+
+/// 需要加载到内存的一个段
+#[derive(Debug)]
+pub struct LoadSegment<'a> {
+    /// 段所在的虚拟地址范围，长度是它在内存中的大小
+    pub range: Range<VirtualAddress>,
+    /// 段在文件中的内容，比`range`短的部分应当填零，比如`.bss`
+    pub data: &'a [u8],
+    /// 段的权限，不包括用户位
+    pub flags: Flags,
+}
+
+/// 解析后的ELF文件
+#[derive(Debug)]
+pub struct ElfFile<'a> {
+    entry: usize,
+    segments: Vec<LoadSegment<'a>>,
+}
+
+impl<'a> ElfFile<'a> {
+    /// 解析文件头和程序头，检查所有需要加载的段
+    pub fn parse(data: &'a [u8]) -> Result<ElfFile<'a>, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[0..4] != *b"\x7fELF" {
+            return Err(ElfError::BadMagic);
+        }
+        // 64位，小端序
+        if data[4] != 2 || data[5] != 1 {
+            return Err(ElfError::UnsupportedClass);
+        }
+        if read_u16(data, 16) != ET_EXEC || read_u16(data, 18) != EM_RISCV {
+            return Err(ElfError::UnsupportedType);
+        }
+        let entry = read_u64(data, 24) as usize;
+        let ph_offset = read_u64(data, 32) as usize;
+        let ph_size = read_u16(data, 54) as usize;
+        let ph_count = read_u16(data, 56) as usize;
+        if ph_size < PROGRAM_HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        let mut segments = Vec::new();
+        for i in 0..ph_count {
+            let ph = match ph_offset.checked_add(i * ph_size) {
+                Some(ph) if ph <= data.len() - PROGRAM_HEADER_SIZE => ph,
+                _ => return Err(ElfError::Truncated),
+            };
+            if read_u32(data, ph) != PT_LOAD {
+                continue;
+            }
+            let p_flags = read_u32(data, ph + 4);
+            let offset = read_u64(data, ph + 8) as usize;
+            let vaddr = read_u64(data, ph + 16) as usize;
+            let file_size = read_u64(data, ph + 32) as usize;
+            let mem_size = read_u64(data, ph + 40) as usize;
+            if mem_size == 0 {
+                continue;
+            }
+            let end = match vaddr.checked_add(mem_size) {
+                Some(end) if file_size <= mem_size => end,
+                _ => return Err(ElfError::BadSegment),
+            };
+            if is_reserved(vaddr..end) {
+                return Err(ElfError::ReservedRange);
+            }
+            let data = offset
+                .checked_add(file_size)
+                .and_then(|end| data.get(offset..end))
+                .ok_or(ElfError::Truncated)?;
+            let mut flags = Flags::empty();
+            flags.set(Flags::READABLE, p_flags & PF_R != 0);
+            flags.set(Flags::WRITABLE, p_flags & PF_W != 0);
+            flags.set(Flags::EXECUTABLE, p_flags & PF_X != 0);
+            segments.push(LoadSegment {
+                range: VirtualAddress(vaddr)..VirtualAddress(end),
+                data,
+                flags,
+            });
+        }
+        if segments.is_empty() {
+            return Err(ElfError::BadSegment);
+        }
+        Ok(ElfFile { entry, segments })
+    }
+
+    /// 程序的入口地址
+    pub fn entry(&self) -> usize {
+        self.entry
+    }
+
+    /// 所有需要加载的段，按程序头的顺序排列
+    pub fn segments(&self) -> &[LoadSegment<'a>] {
+        &self.segments
+    }
+
+    /// 包含所有段的最小的虚拟地址范围
+    pub fn memory_range(&self) -> Range<VirtualAddress> {
+        let start = self.segments.iter().map(|s| s.range.start).min().unwrap();
+        let end = self.segments.iter().map(|s| s.range.end).max().unwrap();
+        start..end
+    }
+}
+
+/// 地址范围是否和共享调度器重叠，或者超出了用户程序能用的范围
+fn is_reserved(range: Range<usize>) -> bool {
+    let payload = SHAREDPAYLOAD_BASE..SHAREDPAYLOAD_BASE + SHAREDPAYLOAD_SIZE;
+    range.end > USER_VA_END || (range.start < payload.end && payload.start < range.end)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
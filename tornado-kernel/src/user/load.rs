@@ -1,50 +1,239 @@
 //! 从文件系统中加载用户程序到内存
 
-use super::space::USER_SPACE;
-use crate::{fs::FS, hart::KernelHartInfo, memory::MemorySet};
-use alloc::string::String;
+use super::{
+    elf::{ElfError, ElfFile},
+    process::forget_children,
+    space::USER_SPACE,
+};
+use crate::{
+    async_rt::{self, SharedPayload},
+    fs::FS,
+    hart::KernelHartInfo,
+    memory::{AddressSpaceId, Flags, MemorySet, PhysicalAddress, VirtualPageNumber, PAGE_SIZE},
+    task, SHAREDPAYLOAD_BASE,
+};
+use alloc::{collections::BTreeMap, string::String};
 #[allow(unused)]
-use core::{intrinsics::volatile_copy_memory, ptr::copy};
+use core::{
+    intrinsics::{volatile_copy_memory, volatile_set_memory},
+    ptr::{copy, write_bytes},
+};
+use lazy_static::lazy_static;
+use spin::Mutex;
 
-/// 从文件系统中加载一个用户程序到内存，并返回包含映射关系的[`MemorySet`]结构
+/// 有内核任务正在读写用户内存的地址空间
+struct PendingIo {
+    /// 还没有完成的块设备读写个数
+    count: usize,
+    /// 程序是否已经卸载
+    unloaded: bool,
+    /// 程序卸载之后保留的地址空间映射，读写都完成之后再释放
+    mm_set: Option<MemorySet>,
+}
+
+lazy_static! {
+    static ref PENDING_IO: Mutex<BTreeMap<usize, PendingIo>> = Mutex::new(BTreeMap::new());
+}
+
+/// 加载用户程序时发生的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    /// 文件系统中没有这个文件
+    NotFound,
+    /// 文件不是可以运行的ELF文件
+    Elf(ElfError),
+    /// 分配给用户程序的物理空间不够
+    NoSpace,
+    /// 参数和环境变量太长，用户栈上放不下
+    ArgsTooLong,
+}
+
+/// 从文件系统中加载一个用户程序到内存，返回包含映射关系的[`MemorySet`]结构和程序的入口地址
+///
+/// 用户程序是ELF可执行文件，每个需要加载的段按它的权限映射。
+/// 所有段被复制到一段连续的物理内存中，大小由文件中段的范围决定
 ///
 /// note: 调用这个函数之前文件系统必须已经初始化
 ///
 /// # Example:
 ///
 /// ```
+/// # let asid = KernelHartInfo::alloc_address_space_id().unwrap();
 /// async {
-///     let mm_set = load_user("alloc-test.bin").await;    
+///     let (mm_set, entry) = load_user("alloc-test", asid).await.expect("load user");
 /// }
 /// ```
-pub async fn load_user<S: Into<String>>(user: S) -> MemorySet {
-    // 获取一个新的地址空间编号
-    let asid = KernelHartInfo::alloc_address_space_id().expect("alloc address space id");
+pub async fn load_user<S: Into<String>>(
+    user: S,
+    asid: AddressSpaceId,
+) -> Result<(MemorySet, usize), LoadError> {
     let binary = {
         let fs = FS.lock().await;
         let fs = unsafe { fs.assume_init_ref() };
-        fs.load_binary(user).await
+        fs.load_binary(user).await.ok_or(LoadError::NotFound)?
     };
-    #[cfg(feature = "qemu")]
-    let pages = 300;
-    #[cfg(feature = "k210")]
-    let pages = 100;
+    let elf = ElfFile::parse(&binary).map_err(LoadError::Elf)?;
+    let range = elf.memory_range();
+    let vpn_start = VirtualPageNumber::floor(range.start);
+    let pages = VirtualPageNumber::ceil(range.end) - vpn_start;
     let base = {
         let mut s = USER_SPACE.lock().await;
-        s.alloc(pages, asid)
-            .expect("alloc physical space for user binary")
+        s.alloc(pages, asid).ok_or(LoadError::NoSpace)?
     };
     let base = base.start_address();
-    println!("[kernel] asid {:?} user binary base: {:x?}", asid, base);
-    let base_va = base.virtual_address_linear();
-    let dst = base_va.0 as *const () as *mut u8;
-    let src = binary.as_ptr();
-    // 加载用户二进制程序到内存
+    println!(
+        "[kernel] asid {:?} user binary base: {:x?}, {} pages",
+        asid, base, pages
+    );
+    let ans = load_segments(&elf, base, pages, asid);
+    if ans.is_err() {
+        // 映射失败，已经分配的物理空间马上还回去
+        USER_SPACE.lock().await.dealloc(asid);
+    }
+    ans.map(|mm_set| (mm_set, elf.entry()))
+}
+
+/// 把程序的所有段复制到从`base`开始的`pages`页物理内存中，然后创建映射
+fn load_segments(
+    elf: &ElfFile,
+    base: PhysicalAddress,
+    pages: usize,
+    asid: AddressSpaceId,
+) -> Result<MemorySet, LoadError> {
+    let vpn_start = VirtualPageNumber::floor(elf.memory_range().start);
+    // 先把整块物理内存填零，段中文件里没有的部分，比如`.bss`，就是零
+    let base_va = base.virtual_address_linear().0 as *mut u8;
     unsafe {
         #[cfg(feature = "qemu")]
-        copy(src, dst, binary.len());
+        write_bytes(base_va, 0, pages * PAGE_SIZE);
         #[cfg(feature = "k210")]
-        volatile_copy_memory(dst, src, binary.len());
+        volatile_set_memory(base_va, 0, pages * PAGE_SIZE);
     }
-    MemorySet::new_bin(base.0, pages, asid).expect("create user memory set")
+    let mut mm_set = MemorySet::new_user(asid).ok_or(LoadError::NoSpace)?;
+    for segment in elf.segments() {
+        // 段的起始地址相对程序第一页的偏移，物理内存中保持相同的偏移
+        let offset = segment.range.start.0 - vpn_start.start_address().0;
+        let dst = unsafe { base_va.add(offset) };
+        let src = segment.data.as_ptr();
+        // 加载段的内容到内存
+        unsafe {
+            #[cfg(feature = "qemu")]
+            copy(src, dst, segment.data.len());
+            #[cfg(feature = "k210")]
+            volatile_copy_memory(dst, src, segment.data.len());
+        }
+        // 页表需要的页帧分配不出来
+        mm_set
+            .map_program_segment(
+                segment.range.clone(),
+                base + offset,
+                segment.flags | Flags::USER,
+            )
+            .ok_or(LoadError::NoSpace)?;
+    }
+    Ok(mm_set)
+}
+
+/// 用户程序的一次块设备读写，读写的内核任务持有它，完成或者被丢弃时结束
+///
+/// 读写期间内核通过用户的页表访问缓冲区，程序在这期间卸载的话，
+/// 页表、物理页帧和地址空间编号都要等所有读写结束之后才能释放
+pub struct UserIo {
+    asid: usize,
+}
+
+impl UserIo {
+    /// 开始地址空间`asid`的一次读写
+    pub fn begin(asid: AddressSpaceId) -> UserIo {
+        let asid = asid.into_inner();
+        let mut pending = PENDING_IO.lock();
+        let io = pending.entry(asid).or_insert(PendingIo {
+            count: 0,
+            unloaded: false,
+            mm_set: None,
+        });
+        io.count += 1;
+        UserIo { asid }
+    }
+
+    /// 发起读写的程序是否还没有卸载，卸载之后不应该再唤醒它的任务
+    pub fn is_live(&self) -> bool {
+        PENDING_IO
+            .lock()
+            .get(&self.asid)
+            .map_or(false, |io| !io.unloaded)
+    }
+}
+
+impl Drop for UserIo {
+    fn drop(&mut self) {
+        let released = {
+            let mut pending = PENDING_IO.lock();
+            let io = pending.get_mut(&self.asid).expect("get pending io");
+            io.count -= 1;
+            if io.count != 0 {
+                return;
+            }
+            pending.remove(&self.asid).expect("remove pending io")
+        };
+        if released.unloaded {
+            // 最后一个读写结束，释放卸载时保留下来的地址空间
+            drop(released.mm_set);
+            release_later(unsafe { AddressSpaceId::from_raw(self.asid) });
+        }
+    }
+}
+
+/// 卸载一个用户程序，在用户程序退出或者出错的时候调用
+///
+/// 先删除这个地址空间在共享调度器中的所有任务，执行器就不会再切换到这个地址空间，
+/// 再取消它登记的定时器和等待、释放它没有等待的子进程，避免地址空间编号被重新分配之后唤醒别的程序的任务。
+/// 然后卸载地址空间映射，释放页表和用户栈占用的物理页帧。
+/// 程序本身占用的物理空间由异步锁保护，交给一个内核任务释放，释放之后再回收地址空间编号。
+/// 还有块设备读写没有完成时，这些都推迟到最后一个读写结束，见[`UserIo`]
+pub fn unload_user(asid: AddressSpaceId) {
+    let shared_payload =
+        unsafe { SharedPayload::load(SHAREDPAYLOAD_BASE) }.expect("load shared payload");
+    let removed = unsafe { shared_payload.remove_address_space(asid) };
+    let timers = async_rt::cancel_timers(asid);
+    forget_children(asid.into_inner());
+    let mm_set = unsafe { KernelHartInfo::unload_user_mm_set(asid.into_inner()) };
+    println!(
+        "[kernel] asid {:?} unloaded, {} tasks removed, {} timers cancelled",
+        asid, removed, timers
+    );
+    if let Some(io) = PENDING_IO.lock().get_mut(&asid.into_inner()) {
+        // 还有读写在使用这个地址空间，由最后一个结束的读写释放
+        io.unloaded = true;
+        io.mm_set = mm_set;
+        return;
+    }
+    drop(mm_set);
+    release_later(asid);
+}
+
+/// 把释放地址空间`asid`的内核任务交给共享调度器
+fn release_later(asid: AddressSpaceId) {
+    let shared_payload =
+        unsafe { SharedPayload::load(SHAREDPAYLOAD_BASE) }.expect("load shared payload");
+    let process = KernelHartInfo::current_process().expect("get kernel process");
+    let kernel_asid = process.address_space_id();
+    let task = task::new_kernel(
+        release_user_space(asid),
+        process,
+        shared_payload.shared_scheduler,
+        shared_payload.shared_set_task_state,
+    );
+    let hart_id = KernelHartInfo::hart_id();
+    if unsafe { shared_payload.add_kernel_task(hart_id, kernel_asid, task) }.is_err() {
+        // 共享调度器放不下新任务，这个地址空间的物理空间和编号都不会被回收
+        println!("[kernel] cannot release user space of asid {:?}", asid);
+    }
+}
+
+/// 释放用户程序占用的物理空间，然后回收地址空间编号
+async fn release_user_space(asid: AddressSpaceId) {
+    let pages = USER_SPACE.lock().await.dealloc(asid);
+    KernelHartInfo::free_address_space_id(asid);
+    println!("[kernel] asid {:?} released {} pages", asid, pages);
 }
@@ -4,12 +4,22 @@
 //! 跳板页原理可参考[xv6-book](https://pdos.csail.mit.edu/6.828/2019/xv6/book-riscv-rev0.pdf)中的`Traps and device drivers`章节
 //!
 //! 本模块负责以下几个部分：
-//! * 从文件系统中加载用户程序到内存
+//! * 从文件系统中加载ELF格式的用户程序到内存，用户程序退出时卸载
+//! * 把参数和环境变量放到用户栈上，传给用户程序
+//! * 记录由用户程序启动的进程，父进程可以等待它们退出
 //! * 将每个用户的上下文放到[`KernelHartInfo`]结构中进行管理，具体请看`src/hart.rs`
 //! * 内核态切换到用户态的具体实现
 //!
+mod args;
+mod elf;
 mod load;
+mod process;
 mod space;
 mod trap;
 
+pub use args::ARGS_MAX;
+pub use load::{unload_user, UserIo};
+pub use process::{
+    cancel_process, exit_process, spawn_process, take_loaded_process, wait_process, WaitStatus,
+};
 pub use trap::{enter_user, prepare_user};
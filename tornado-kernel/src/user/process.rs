@@ -0,0 +1,205 @@
+//! 由用户程序启动的进程
+//!
+//! 进程编号和地址空间编号分开分配：地址空间编号在进程退出时就被回收，
+//! 进程编号一直保留到父进程取走退出码为止。只有通过[`spawn_process`]启动的程序会被记录，
+//! 内核启动时准备的用户程序没有进程编号。
+//! 只有父进程可以等待子进程；父进程退出之后，没有被等待的子进程退出时直接释放进程编号
+use super::trap::prepare_user_in;
+use crate::{
+    async_rt::{ext_intr_off, ext_intr_on, SharedPayload, TaskState},
+    hart::KernelHartInfo,
+    memory::AddressSpaceId,
+    SHAREDPAYLOAD_BASE,
+};
+use alloc::{collections::BTreeMap, collections::VecDeque, string::String, vec::Vec};
+use core::{
+    future::Future,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+/// 进程的状态
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// 正在加载或者运行，记录它的地址空间编号
+    Running(usize),
+    /// 已经退出，还没有被等待
+    Exited(i32),
+}
+
+#[derive(Debug)]
+struct Process {
+    state: State,
+    /// 父进程的地址空间编号，父进程已经退出时为None
+    parent: Option<usize>,
+    /// 父进程中等待这个进程退出的用户任务
+    waiter: Option<usize>,
+}
+
+lazy_static! {
+    static ref PROCESSES: Mutex<BTreeMap<usize, Process>> = Mutex::new(BTreeMap::new());
+    /// 加载完成、等待第一次进入的地址空间
+    static ref LOADED: Mutex<VecDeque<usize>> = Mutex::new(VecDeque::new());
+}
+
+static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
+
+/// 等待进程时得到的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStatus {
+    /// 进程已经退出，得到它的退出码，进程编号随之释放
+    Exited(i32),
+    /// 进程还在运行，退出时会唤醒等待的任务
+    Running,
+    /// 没有这个进程，它不是调用者的子进程，或者它的退出码已经被取走
+    NotFound,
+    /// 父进程里已经有别的任务在等待这个进程
+    Busy,
+}
+
+/// 地址空间为`parent`的程序启动用户程序`path`，分配地址空间和进程编号，返回进程编号和需要运行的加载任务
+///
+/// `args`不包括程序的名字，加载时`path`会作为第一个参数。
+/// 加载任务应当交给内核执行器运行，没能交给执行器时调用[`cancel_process`]
+pub fn spawn_process(
+    parent: usize,
+    path: String,
+    args: Vec<String>,
+    envs: Vec<String>,
+    kernel_stack_top: usize,
+) -> Option<(usize, impl Future<Output = ()> + Send + Sync + 'static)> {
+    let asid = KernelHartInfo::alloc_address_space_id()?;
+    let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+    PROCESSES.lock().insert(
+        pid,
+        Process {
+            state: State::Running(asid.into_inner()),
+            parent: Some(parent),
+            waiter: None,
+        },
+    );
+    let mut argv = Vec::with_capacity(args.len() + 1);
+    argv.push(path);
+    argv.extend(args);
+    Some((pid, load_process(pid, asid, argv, envs, kernel_stack_top)))
+}
+
+/// 撤销还没有开始加载的进程
+pub fn cancel_process(pid: usize) {
+    if let Some(Process {
+        state: State::Running(asid),
+        ..
+    }) = PROCESSES.lock().remove(&pid)
+    {
+        KernelHartInfo::free_address_space_id(unsafe { AddressSpaceId::from_raw(asid) });
+    }
+}
+
+async fn load_process(
+    pid: usize,
+    asid: AddressSpaceId,
+    argv: Vec<String>,
+    envs: Vec<String>,
+    kernel_stack_top: usize,
+) {
+    let path = &argv[0];
+    match prepare_user_in(path.clone(), &argv, &envs, asid, kernel_stack_top).await {
+        Ok(()) => LOADED.lock().push_back(asid.into_inner()),
+        Err(err) => {
+            println!("[kernel] cannot load process {} '{}': {:?}", pid, path, err);
+            KernelHartInfo::free_address_space_id(asid);
+            let waiter = exit_with(&mut PROCESSES.lock(), pid, -1);
+            if let Some(waiter) = waiter {
+                wake_waiter(waiter);
+            }
+        }
+    }
+}
+
+/// 取出一个加载完成、还没有运行过的地址空间
+///
+/// 新加载的程序在共享调度器里还没有任务，需要内核主动进入一次，让它的执行器开始运行
+pub fn take_loaded_process() -> Option<usize> {
+    LOADED.lock().pop_front()
+}
+
+/// 地址空间为`asid`的程序以`exit_code`退出，唤醒等待它的用户任务
+pub fn exit_process(asid: usize, exit_code: i32) {
+    let waiter = {
+        let mut processes = PROCESSES.lock();
+        let pid = processes
+            .iter()
+            .find(|(_, p)| matches!(p.state, State::Running(a) if a == asid))
+            .map(|(&pid, _)| pid);
+        pid.and_then(|pid| exit_with(&mut processes, pid, exit_code))
+    };
+    if let Some(waiter) = waiter {
+        wake_waiter(waiter);
+    }
+}
+
+/// 进程`pid`以`exit_code`退出，返回需要唤醒的任务
+///
+/// 父进程已经退出、没有人会取走退出码时，直接释放进程编号
+fn exit_with(
+    processes: &mut BTreeMap<usize, Process>,
+    pid: usize,
+    exit_code: i32,
+) -> Option<usize> {
+    let process = processes.get_mut(&pid)?;
+    if process.parent.is_none() {
+        processes.remove(&pid);
+        return None;
+    }
+    process.state = State::Exited(exit_code);
+    process.waiter.take()
+}
+
+/// 地址空间为`asid`的任务等待进程`pid`退出
+///
+/// 只有父进程可以等待。进程还在运行时登记`wake_task_repr`，进程退出时内核唤醒这个任务；
+/// 同一个任务可以重复登记，已经有别的任务在等待时返回[`WaitStatus::Busy`]
+pub fn wait_process(pid: usize, asid: usize, wake_task_repr: usize) -> WaitStatus {
+    let mut processes = PROCESSES.lock();
+    let process = match processes.get_mut(&pid) {
+        Some(process) if process.parent == Some(asid) => process,
+        _ => return WaitStatus::NotFound,
+    };
+    match (process.state, process.waiter) {
+        (State::Exited(code), _) => {
+            processes.remove(&pid);
+            WaitStatus::Exited(code)
+        }
+        (State::Running(_), Some(waiter)) if waiter != wake_task_repr => WaitStatus::Busy,
+        (State::Running(_), _) => {
+            process.waiter = Some(wake_task_repr);
+            WaitStatus::Running
+        }
+    }
+}
+
+/// 地址空间为`asid`的程序退出，处理它启动的子进程，在卸载这个地址空间时调用
+///
+/// 已经退出、没有被等待的子进程直接释放；还在运行的子进程不再有父进程，
+/// 它们登记的等待随之撤销，地址空间编号被重新分配之后，子进程退出时不会唤醒新程序里的任务
+pub fn forget_children(asid: usize) {
+    PROCESSES.lock().retain(|_, process| {
+        if process.parent != Some(asid) {
+            return true;
+        }
+        process.parent = None;
+        process.waiter = None;
+        matches!(process.state, State::Running(_))
+    });
+}
+
+/// 在共享调度器里把等待的用户任务设置为就绪
+fn wake_waiter(wake_task_repr: usize) {
+    unsafe {
+        let shared_payload = SharedPayload::load(SHAREDPAYLOAD_BASE).expect("load shared payload");
+        ext_intr_off();
+        shared_payload.set_task_state(wake_task_repr, TaskState::Ready);
+        ext_intr_on();
+    }
+}
@@ -1,6 +1,6 @@
 //! 管理分配给用户程序的内存空间
 use crate::memory::{AddressSpaceId, PhysicalAddress, PhysicalPageNumber, PAGE_SIZE};
-use alloc::boxed::Box;
+use alloc::{vec, vec::Vec};
 use async_mutex::AsyncMutex;
 use lazy_static::lazy_static;
 
@@ -19,39 +19,28 @@ lazy_static! {
 ///
 /// 常量泛型参数N: 最大N页内存
 /// 常量泛型参数B: 用户空间起始地址
+///
+/// 加载程序时所有段复制到一段连续的物理内存中，所以每次分配的页必须相邻。
+/// 程序退出之后释放的页留下空洞，分配时从低地址开始找第一段足够长的空闲页
 pub struct UserSpaceManager<const N: usize, const B: usize> {
-    /// 已用链表
-    used: ListNode<AddressSpaceId>, // 头结点是哑结点
-    /// 空闲链表
-    free: ListNode<AddressSpaceId>, // 头结点是哑结点
-    /// 长度
+    /// 每一页属于哪个地址空间，空闲的页为0
+    owners: Vec<usize>,
+    /// 已经分配的页数
     len: usize,
 }
 
 impl<const N: usize, const B: usize> UserSpaceManager<N, B> {
     /// 创建一个空的[`UserSpaceManager`]
     pub fn new() -> Self {
-        let used = ListNode {
-            id: 0,
-            val: unsafe { AddressSpaceId::from_raw(0) },
-            next: None,
-        };
-        let mut free = used.clone();
-        for i in 0..N {
-            let prev = free.next.take();
-            let node = ListNode {
-                id: N - 1 - i,
-                val: unsafe { AddressSpaceId::from_raw(0) },
-                next: prev,
-            };
-            free.next = Some(Box::new(node));
+        Self {
+            owners: vec![0; N],
+            len: 0,
         }
-        Self { used, free, len: 0 }
     }
 
     /// 分配一个空间，需要物理页的数量为 `pages`
     ///
-    /// 分配成功返回起始物理页号
+    /// 分配成功返回起始物理页号，没有足够长的连续空闲页时返回None
     ///
     /// # Example:
     ///
@@ -64,63 +53,48 @@ impl<const N: usize, const B: usize> UserSpaceManager<N, B> {
     ///
     /// println!("alloc ppn {:?}", ppn);
     /// ```
-    pub fn alloc(&mut self, pages: usize, _asid: AddressSpaceId) -> Option<PhysicalPageNumber> {
+    pub fn alloc(&mut self, pages: usize, asid: AddressSpaceId) -> Option<PhysicalPageNumber> {
         assert!(PAGE_SIZE % 2 == 0);
-        if pages > N - self.len {
-            None
-        } else {
-            let base = self.free.next.as_ref().unwrap().id * PAGE_SIZE + B;
-            let base = PhysicalPageNumber::floor(PhysicalAddress(base));
-            // 更新链表
-            for _ in 0..pages {
-                let mut node = self.free.next.take().unwrap();
-                self.free.next = node.next.take();
-                let prev = self.used.next.take();
-                node.next = prev;
-                self.used.next = Some(node);
-            }
-            self.len += pages;
-            Some(base)
+        if pages == 0 || pages > N - self.len {
+            return None;
         }
+        let start = self.find_free(pages)?;
+        for owner in &mut self.owners[start..start + pages] {
+            // 记下这一页属于哪个地址空间，释放的时候按地址空间查找
+            *owner = asid.into_inner();
+        }
+        self.len += pages;
+        let base = start * PAGE_SIZE + B;
+        Some(PhysicalPageNumber::floor(PhysicalAddress(base)))
     }
 
-    /// 释放一个地址空间占用的物理内存
-    ///
-    /// note: 还没测试
-    #[allow(unused)]
-    pub fn dealloc(&mut self, asid: AddressSpaceId) -> Option<(PhysicalPageNumber, usize)> {
-        let mut prev = &mut self.used;
-        loop {
-            if prev.next.is_none() {
-                break;
+    /// 找到第一段至少`pages`页的连续空闲页，返回它的第一页
+    fn find_free(&self, pages: usize) -> Option<usize> {
+        let mut run = 0;
+        for (index, &owner) in self.owners.iter().enumerate() {
+            if owner != 0 {
+                run = 0;
+                continue;
             }
-            if prev.next.as_ref().unwrap().val == asid {
-                let mut num = 0;
-                let base = prev.next.as_ref().unwrap().id * PAGE_SIZE + B;
-                let base = PhysicalPageNumber::floor(PhysicalAddress(base));
-                // 更新链表
-                while prev.next.as_ref().is_some() && prev.next.as_ref().unwrap().val == asid {
-                    let mut node = prev.next.take().unwrap();
-                    prev.next = node.next.take();
-                    let temp = self.free.next.take();
-                    node.next = temp;
-                    self.free.next = Some(node);
-                    num += 1;
-                }
-                self.len -= num;
-                return Some((base, num));
-            } else {
-                prev = prev.next.as_mut().unwrap();
+            run += 1;
+            if run == pages {
+                return Some(index + 1 - pages);
             }
         }
         None
     }
-}
 
-/// 链表结点
-#[derive(PartialEq, Eq, Clone, Debug)]
-pub struct ListNode<T> {
-    pub id: usize,
-    pub val: T,
-    pub next: Option<Box<ListNode<T>>>,
+    /// 释放一个地址空间占用的所有物理内存，返回释放的页数
+    ///
+    /// 同一个地址空间可能分配过多次，每次分配的页不一定相邻，这里全部释放
+    pub fn dealloc(&mut self, asid: AddressSpaceId) -> usize {
+        let asid = asid.into_inner();
+        let mut num = 0;
+        for owner in self.owners.iter_mut().filter(|owner| **owner == asid) {
+            *owner = 0;
+            num += 1;
+        }
+        self.len -= num;
+        num
+    }
 }
@@ -1,13 +1,18 @@
-use super::load::load_user;
+use super::{
+    args::{args_fit, push_args},
+    load::{load_user, LoadError},
+    space::USER_SPACE,
+};
 use crate::{
     hart::{self, KernelHartInfo},
     memory::{
-        swap_contex_va, Flags, VirtualAddress, VirtualPageNumber, KERNEL_MAP_OFFSET, STACK_SIZE,
+        swap_contex_va, AddressSpaceId, Flags, VirtualAddress, VirtualPageNumber,
+        KERNEL_MAP_OFFSET, STACK_SIZE,
     },
     syscall::{get_swap_cx, user_trap_handler},
     trap,
 };
-use alloc::string::String;
+use alloc::{string::String, vec};
 use riscv::register::satp;
 
 /// 准备用户地址空间映射
@@ -23,13 +28,35 @@ use riscv::register::satp;
 /// let kernel_stack = process.alloc_task().unwrap();
 ///
 /// async {
-///     prepare_user("alloc-test.bin", kernel_stack.end.0).await;
+///     prepare_user("alloc-test", kernel_stack.end.0).await;
 /// }
 /// ```
 pub async fn prepare_user<S: Into<String>>(user: S, kernel_stack_top: usize) {
     let user: String = user.into();
+    // 获取一个新的地址空间编号
+    let asid = KernelHartInfo::alloc_address_space_id().expect("alloc address space id");
+    // 唯一的参数是程序的名字，没有环境变量
+    let args = vec![user.clone()];
+    prepare_user_in(user, &args, &[], asid, kernel_stack_top)
+        .await
+        .expect("load user program")
+}
+
+/// 在地址空间`asid`中准备用户程序，加载失败时返回错误，这时候地址空间编号还没有被使用
+///
+/// `args`是完整的参数列表，第一个参数通常是程序的名字；`envs`中每一项的格式是`KEY=VALUE`
+pub(super) async fn prepare_user_in<S: Into<String>>(
+    user: S,
+    args: &[String],
+    envs: &[String],
+    asid: AddressSpaceId,
+    kernel_stack_top: usize,
+) -> Result<(), LoadError> {
+    if !args_fit(args, envs) {
+        return Err(LoadError::ArgsTooLong);
+    }
     // 创建一个用户态映射
-    let mut user_memory = load_user(&user).await;
+    let (mut user_memory, user_entry) = load_user(user, asid).await?;
     // 获取用户地址空间编号
     let user_asid = user_memory.address_space_id.into_inner();
     // 存放用户特权级切换上下文的虚拟地址
@@ -54,11 +81,20 @@ pub async fn prepare_user<S: Into<String>>(user: S, kernel_stack_top: usize) {
     // 获取用户的`satp`寄存器
     let _user_satp = user_memory.mapping.get_satp(user_memory.address_space_id);
     // 用户态栈
-    let user_stack_handle = user_memory
+    let user_stack_handle = match user_memory
         .alloc_page_range(STACK_SIZE, Flags::READABLE | Flags::WRITABLE | Flags::USER)
-        .expect("alloc user stack");
+    {
+        Some(range) => range,
+        None => {
+            // 程序已经占用的物理空间随地址空间编号一起还回去
+            USER_SPACE.lock().await.dealloc(asid);
+            return Err(LoadError::NoSpace);
+        }
+    };
 
-    let user_stack_top = user_stack_handle.end.0;
+    // 参数块放在用户栈的顶部，用户栈从它的下面开始
+    let args_block = push_args(&user_memory.mapping, user_stack_handle.end.0, args, envs);
+    let user_stack_top = args_block;
     // 将用户地址空间映射注册到 [`KernelHartInfo`]
     assert!(
         KernelHartInfo::load_user_mm_set(user_memory),
@@ -71,7 +107,7 @@ pub async fn prepare_user<S: Into<String>>(user: S, kernel_stack_top: usize) {
     // 目前通过tp寄存器把地址空间编号传给用户，后面可能会修改
     *swap_cx = trap::SwapContext::new_to_user(
         kernel_satp,
-        0,
+        user_entry,
         tp,
         kernel_stack_top,
         user_stack_top,
@@ -80,12 +116,20 @@ pub async fn prepare_user<S: Into<String>>(user: S, kernel_stack_top: usize) {
     // 在这里把共享调度器中`raw_table`的地址通过`gp`寄存器传给用户
     swap_cx.set_gp(crate::SHAREDPAYLOAD_BASE);
     swap_cx.set_tp(user_asid);
+    // 通过`a0`寄存器把参数块的地址传给用户
+    swap_cx.x[9] = args_block;
+    Ok(())
 }
 
 /// 进入地址空间为`asid`的用户态空间
 ///
 /// 通常用于第一次从内核态进入用户态
 pub fn enter_user(asid: usize) -> ! {
+    assert!(
+        KernelHartInfo::try_enter_user(asid),
+        "address space {} is running on another hart",
+        asid
+    );
     let satp = KernelHartInfo::user_satp(asid).expect("get satp with asid");
     let swap_context = unsafe { get_swap_cx(&satp, asid) };
     trap::switch_to_user(swap_context, satp.inner(), asid)
@@ -66,32 +66,20 @@ impl VirtIOAsyncBlock {
     }
     /// 处理virtio外部中断，通常在外部中断处理函数里面使用
     ///
+    /// 唤醒所有请求已经完成的块设备读写任务，返回完成的请求数
+    ///
     /// # Example:
     ///
     /// ```
     /// unsafe extern "C" fn supervisor_external() {
     ///     let irq = plic::plic_claim();
     ///     if irq == 1 {
-    ///         let ret = VIRTIO_BLOCK.handle_interrupt().unwrap();
-    ///         println!("virtio intr return: {}", ret);        
+    ///         let completed = VIRTIO_BLOCK.handle_interrupt();
+    ///         println!("virtio intr completed: {}", completed);
     ///     }
     /// }
     /// ```
-    pub unsafe fn handle_interrupt(&self) -> Option<u64> {
-        let ret = self
-            .0
-            .handle_interrupt()
-            .expect("handle virtio interrupt error!");
-        match ret {
-            InterruptRet::Read(sector) => {
-                return Some(sector);
-            }
-            InterruptRet::Write(sector) => {
-                return Some(sector);
-            }
-            _other => {
-                return None;
-            }
-        }
+    pub unsafe fn handle_interrupt(&self) -> usize {
+        self.0.handle_interrupt()
     }
 }
@@ -8,13 +8,18 @@ extern crate alloc;
 #[macro_use]
 extern crate tornado_user;
 
-use tornado_user::{do_yield, execute_async_analysis, read_timer, spawn};
+use tornado_user::{do_yield, env, execute_async_analysis, read_timer, spawn};
 async fn analysis_task(_n: usize) {}
 
 // 异步main函数，由entry调用execute_async_main
 #[no_mangle]
 fn main() -> i32 {
-    for i in 0..100 {
+    // 第一个参数是切换的次数，没有参数时切换100次
+    let rounds = env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(100);
+    for i in 0..rounds {
         spawn(analysis_task(i));
         do_yield(2);
     }
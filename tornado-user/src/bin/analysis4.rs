@@ -0,0 +1,86 @@
+//! 共享调度器唤醒延迟性能测试程序
+//!
+//! 调度器中先放入若干个睡眠的任务，然后反复唤醒最后加入的任务，观察唤醒耗时随任务数量的变化
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(llvm_asm)]
+
+extern crate alloc;
+#[macro_use]
+extern crate tornado_user;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+use tornado_user::{execute_async, read_timer, reset_timer, scheduler_stats, spawn};
+
+const TASK_COUNTS: [usize; 5] = [10, 50, 100, 200, 350];
+const WAKE_ROUNDS: usize = 10000;
+
+// 异步main函数，由entry调用execute_async_main
+#[no_mangle]
+fn main() -> i32 {
+    for &count in TASK_COUNTS.iter() {
+        let wakers = Arc::new(Mutex::new(Vec::with_capacity(count)));
+        for _ in 0..count {
+            spawn(Parked::new(wakers.clone()));
+        }
+        // 最后加入的任务在所有睡眠任务都登记完唤醒器之后才运行
+        spawn(wake_last(wakers, count));
+        execute_async();
+    }
+    let stats = scheduler_stats();
+    println!(
+        "[analysis] peeks: {}, no wake task: {}, lock spins: {}, peak queue length: {}",
+        stats.peeks, stats.no_wake_task, stats.lock_spins, stats.peak_queue_len
+    );
+    0
+}
+
+async fn wake_last(wakers: Arc<Mutex<Vec<Waker>>>, count: usize) {
+    let wakers = wakers.lock();
+    assert_eq!(wakers.len(), count);
+    let last = wakers.last().unwrap();
+    reset_timer();
+    for _ in 0..WAKE_ROUNDS {
+        last.wake_by_ref();
+    }
+    let time = read_timer();
+    println!(
+        "[analysis] tasks: {}, {} wakes timer: {}",
+        count, WAKE_ROUNDS, time
+    );
+    // 唤醒所有睡眠的任务，让它们结束
+    for waker in wakers.iter() {
+        waker.wake_by_ref();
+    }
+}
+
+/// 第一次被轮询时登记唤醒器并睡眠，第二次被轮询时结束
+struct Parked {
+    wakers: Option<Arc<Mutex<Vec<Waker>>>>,
+}
+
+impl Parked {
+    fn new(wakers: Arc<Mutex<Vec<Waker>>>) -> Self {
+        Self {
+            wakers: Some(wakers),
+        }
+    }
+}
+
+impl Future for Parked {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.wakers.take() {
+            Some(wakers) => {
+                wakers.lock().push(cx.waker().clone());
+                Poll::Pending
+            }
+            None => Poll::Ready(()),
+        }
+    }
+}
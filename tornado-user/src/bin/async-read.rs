@@ -11,7 +11,10 @@ extern crate tornado_user;
 use tornado_user::{execute_async_main, io::read_block};
 async fn async_main() -> i32 {
     let mut buf = [0; 512];
-    read_block(0, &mut buf).await;
+    if let Err(err) = read_block(0, &mut buf).await {
+        println!("[user] async read block failed: {:?}", err);
+        return -1;
+    }
     println!("[user] async read block ret: {:x?}", &buf[0..10]);
     0
 }
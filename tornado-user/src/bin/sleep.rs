@@ -0,0 +1,39 @@
+//! 用户态睡眠和定时器演示程序
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(llvm_asm)]
+
+extern crate alloc;
+#[macro_use]
+extern crate tornado_user;
+
+use tornado_user::{
+    env, execute_async_main,
+    rt::time::{interval_ms, sleep_ms, Instant},
+    spawn,
+};
+async fn async_main() -> i32 {
+    spawn(async {
+        let mut interval = interval_ms(100);
+        for i in 0..5 {
+            let tick = interval.tick().await;
+            println!("[user] interval tick {} at {} ms", i, tick.as_millis());
+        }
+    });
+    // 第一个参数是睡眠的毫秒数
+    let ms = env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(300);
+    let start = Instant::now();
+    sleep_ms(ms).await;
+    println!("[user] slept for {:?}", start.elapsed());
+    0
+}
+
+// 异步main函数，由entry调用execute_async_main
+#[no_mangle]
+fn main() -> i32 {
+    execute_async_main(async_main())
+}
@@ -0,0 +1,38 @@
+//! 用户程序启动其它程序的演示程序
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(llvm_asm)]
+
+extern crate alloc;
+#[macro_use]
+extern crate tornado_user;
+
+use tornado_user::{execute_async_main, process};
+
+async fn async_main() -> i32 {
+    let children = [("user_task", &[][..]), ("sleep", &["500"][..])];
+    let mut pids = [None; 2];
+    for (pid, (path, args)) in pids.iter_mut().zip(children.iter()) {
+        match process::spawn(path, args) {
+            Ok(child) => {
+                println!("[user] spawned '{}' as process {}", path, child.as_usize());
+                *pid = Some(child);
+            }
+            Err(err) => println!("[user] cannot spawn '{}': {:?}", path, err),
+        }
+    }
+    for pid in pids.iter().flatten() {
+        match process::wait(*pid).await {
+            Ok(code) => println!("[user] process {} exited with {}", pid.as_usize(), code),
+            Err(err) => println!("[user] wait process {}: {:?}", pid.as_usize(), err),
+        }
+    }
+    0
+}
+
+// 异步main函数，由entry调用execute_async_main
+#[no_mangle]
+fn main() -> i32 {
+    execute_async_main(async_main())
+}
@@ -11,13 +11,9 @@ use core::pin::Pin;
 use core::task::{Context, Poll};
 
 async fn async_main() -> i32 {
-    // todo: 唤醒逻辑
-    tornado_user::spawn(async {
-        let ans = FibonacciFuture::new(5).await;
-        println!("[user] Fibonacci[5] = {}", ans);
-    });
-    let ans = FibonacciFuture::new(6).await;
-    println!("[user] Fibonacci[6] = {}", ans);
+    // 两个计算交替进行，都完成之后一起返回
+    let (a, b) = join!(FibonacciFuture::new(5), FibonacciFuture::new(6));
+    println!("[user] Fibonacci[5] = {}, Fibonacci[6] = {}", a, b);
     0
 }
 
@@ -0,0 +1,102 @@
+//! 参数和环境变量
+//!
+//! 内核在用户栈的顶部放一个参数块，通过`a0`寄存器把它的地址传给[`_start`](crate::_start)。
+//! 参数块从低地址到高地址依次是`argc`、`argc`个(指针, 长度)对、`envc`、`envc`个(指针, 长度)对，
+//! 然后是字符串的内容。用户栈从参数块的下面开始，参数块在程序运行期间一直有效
+use core::{slice, str};
+
+static mut ARGS_BLOCK: usize = 0;
+
+/// 记录参数块的地址，在`_start`里调用
+pub(crate) unsafe fn init(args_block: usize) {
+    ARGS_BLOCK = args_block;
+}
+
+/// 参数块中从`offset`开始的一组字符串，没有参数块时为空
+fn strings(offset: usize) -> (&'static [[usize; 2]], usize) {
+    let block = unsafe { ARGS_BLOCK };
+    if block == 0 {
+        return (&[], 0);
+    }
+    // note(unsafe): 参数块由内核写入，之后不会被修改
+    unsafe {
+        let count = *((block as *const usize).add(offset));
+        let pairs = slice::from_raw_parts((block as *const usize).add(offset + 1).cast(), count);
+        (pairs, offset + 1 + 2 * count)
+    }
+}
+
+fn to_str(&[ptr, len]: &[usize; 2]) -> &'static str {
+    // note(unsafe): 内核写入的字符串都是合法的UTF-8
+    unsafe { str::from_utf8_unchecked(slice::from_raw_parts(ptr as *const u8, len)) }
+}
+
+/// 程序的参数，第一个参数通常是程序的名字
+///
+/// # Example:
+///
+/// ```
+/// let rounds = env::args()
+///     .nth(1)
+///     .and_then(|n| n.parse().ok())
+///     .unwrap_or(100);
+/// ```
+pub fn args() -> Args {
+    Args {
+        inner: strings(0).0.iter(),
+    }
+}
+
+/// 程序的环境变量，得到(名字, 值)对
+pub fn vars() -> Vars {
+    let (_args, env_offset) = strings(0);
+    Vars {
+        inner: strings(env_offset).0.iter(),
+    }
+}
+
+/// 取得环境变量`key`的值，没有这个环境变量时返回None
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().find(|(k, _)| *k == key).map(|(_, v)| v)
+}
+
+/// [`args`]返回的迭代器
+#[derive(Debug, Clone)]
+pub struct Args {
+    inner: slice::Iter<'static, [usize; 2]>,
+}
+
+impl Iterator for Args {
+    type Item = &'static str;
+    fn next(&mut self) -> Option<&'static str> {
+        self.inner.next().map(to_str)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Args {}
+
+/// [`vars`]返回的迭代器
+#[derive(Debug, Clone)]
+pub struct Vars {
+    inner: slice::Iter<'static, [usize; 2]>,
+}
+
+impl Iterator for Vars {
+    type Item = (&'static str, &'static str);
+    fn next(&mut self) -> Option<Self::Item> {
+        // 没有等号的项当作值为空的环境变量
+        self.inner.next().map(|pair| {
+            let entry = to_str(pair);
+            match entry.find('=') {
+                Some(i) => (&entry[..i], &entry[i + 1..]),
+                None => (entry, ""),
+            }
+        })
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,178 @@
+//! 按完成顺序取出结果的future集合
+use crate::stream::Stream;
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    future::Future,
+    iter::FromIterator,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex;
+
+/// 被唤醒的future的编号，以及等待这个集合的任务
+struct ReadyQueue {
+    ready: Mutex<VecDeque<usize>>,
+    parent: Mutex<Option<Waker>>,
+}
+
+/// 集合中每个future的唤醒器
+///
+/// 唤醒时把自己的编号放进就绪队列，再唤醒等待集合的任务。
+/// 这样集合被唤醒之后只需要轮询就绪的future
+struct SlotWaker {
+    index: usize,
+    queue: Arc<ReadyQueue>,
+    /// 编号已经在就绪队列中，避免重复放入
+    queued: AtomicBool,
+}
+
+impl woke::Woke for SlotWaker {
+    fn wake_by_ref(this: &Arc<Self>) {
+        if !this.queued.swap(true, Ordering::AcqRel) {
+            this.queue.ready.lock().push_back(this.index);
+        }
+        if let Some(waker) = this.queue.parent.lock().as_ref() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+struct Slot<F> {
+    future: Pin<Box<F>>,
+    waker: Arc<SlotWaker>,
+}
+
+/// 一组future，作为[`Stream`]按完成的先后顺序产生它们的结果
+///
+/// 每个future有自己的唤醒器，只有被唤醒的future才会被再次轮询。
+/// 集合为空时流结束，之后可以继续放入新的future
+///
+/// # Example:
+///
+/// ```
+/// let mut set: FuturesUnordered<_> = (0..4).map(|i| async move { i * 2 }).collect();
+/// while let Some(n) = set.next().await {
+///     println!("[user] {}", n);
+/// }
+/// ```
+pub struct FuturesUnordered<F> {
+    slots: Vec<Option<Slot<F>>>,
+    free: Vec<usize>,
+    queue: Arc<ReadyQueue>,
+    len: usize,
+}
+
+impl<F: Future> FuturesUnordered<F> {
+    /// 创建空的集合
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            queue: Arc::new(ReadyQueue {
+                ready: Mutex::new(VecDeque::new()),
+                parent: Mutex::new(None),
+            }),
+            len: 0,
+        }
+    }
+
+    /// 放入一个future，它会在下次轮询集合时被轮询
+    pub fn push(&mut self, future: F) {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+        };
+        let waker = Arc::new(SlotWaker {
+            index,
+            queue: self.queue.clone(),
+            queued: AtomicBool::new(true),
+        });
+        self.queue.ready.lock().push_back(index);
+        self.slots[index] = Some(Slot {
+            future: Box::pin(future),
+            waker,
+        });
+        self.len += 1;
+        if let Some(waker) = self.queue.parent.lock().as_ref() {
+            waker.wake_by_ref();
+        }
+    }
+
+    /// 集合中还没有完成的future的数量
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// 集合是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<F: Future> Default for FuturesUnordered<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: Future> FromIterator<F> for FuturesUnordered<F> {
+    fn from_iter<I: IntoIterator<Item = F>>(iter: I) -> Self {
+        let mut set = Self::new();
+        for future in iter {
+            set.push(future);
+        }
+        set
+    }
+}
+
+impl<F: Future> Stream for FuturesUnordered<F> {
+    type Item = F::Output;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<F::Output>> {
+        if self.len == 0 {
+            return Poll::Ready(None);
+        }
+        {
+            let mut parent = self.queue.parent.lock();
+            match parent.as_ref() {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                _ => *parent = Some(cx.waker().clone()),
+            }
+        }
+        // 一直被唤醒的future可能让这次轮询不返回，轮询的次数超过集合大小就让出
+        let budget = self.len;
+        let mut polled = 0;
+        loop {
+            let index = match self.queue.ready.lock().pop_front() {
+                Some(index) => index,
+                None => return Poll::Pending,
+            };
+            let this = &mut *self;
+            // 已经完成的future留下的编号，跳过
+            let slot = match this.slots[index].as_mut() {
+                Some(slot) => slot,
+                None => continue,
+            };
+            slot.waker.queued.store(false, Ordering::Release);
+            let waker = woke::waker_ref(&slot.waker);
+            let mut context = Context::from_waker(&*waker);
+            if let Poll::Ready(output) = slot.future.as_mut().poll(&mut context) {
+                this.slots[index] = None;
+                this.free.push(index);
+                this.len -= 1;
+                return Poll::Ready(Some(output));
+            }
+            polled += 1;
+            if polled >= budget {
+                // 还有就绪的future时唤醒自己，稍后接着轮询
+                if !this.queue.ready.lock().is_empty() {
+                    cx.waker().wake_by_ref();
+                }
+                return Poll::Pending;
+            }
+        }
+    }
+}
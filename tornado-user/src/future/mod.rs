@@ -1,3 +1,364 @@
 //! [`Future`]相关
 //!
-//! todo!()
+//! 组合多个future的工具：同时等待所有future的[`join!`](crate::join)和[`join_all`]，
+//! 等待最先完成的一个的[`select!`](crate::select)，以及按完成顺序取出结果的[`FuturesUnordered`]。
+//!
+//! 这些工具把执行器传进来的唤醒器原样交给里面的future，或者包装之后再唤醒它，
+//! 最终唤醒的都是共享调度器里的任务，见[`UserTaskRepr`](crate::task::UserTaskRepr)
+mod futures_unordered;
+
+pub use futures_unordered::FuturesUnordered;
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// 由闭包实现的future，每次轮询都调用这个闭包
+///
+/// 闭包里保存的状态随着这个future一起固定，`join!`和`select!`依赖这一点
+pub fn poll_fn<T, F>(f: F) -> PollFn<F>
+where
+    F: FnMut(&mut Context<'_>) -> Poll<T>,
+{
+    PollFn { f }
+}
+
+/// [`poll_fn`]返回的future
+pub struct PollFn<F> {
+    f: F,
+}
+
+impl<T, F> Future for PollFn<F>
+where
+    F: FnMut(&mut Context<'_>) -> Poll<T>,
+{
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        // note(unsafe): 闭包不会被移出`PollFn`
+        let f = unsafe { &mut self.get_unchecked_mut().f };
+        f(cx)
+    }
+}
+
+/// 保存future或者它的结果，结果可以在之后取出
+///
+/// `join!`和[`join_all`]用它记住已经完成的future的结果
+pub enum MaybeDone<F: Future> {
+    /// 还没有完成
+    Future(F),
+    /// 已经完成，结果还没有取出
+    Done(F::Output),
+    /// 结果已经取出
+    Gone,
+}
+
+impl<F: Future> MaybeDone<F> {
+    /// 包装一个还没有完成的future
+    pub fn new(future: F) -> Self {
+        MaybeDone::Future(future)
+    }
+
+    /// 取出完成的结果，没有完成或者已经取出时返回None
+    pub fn take_output(self: Pin<&mut Self>) -> Option<F::Output> {
+        // note(unsafe): 只有在不是`Future`的时候才移动里面的值
+        let this = unsafe { self.get_unchecked_mut() };
+        match this {
+            MaybeDone::Done(_) => match mem::replace(this, MaybeDone::Gone) {
+                MaybeDone::Done(output) => Some(output),
+                _ => unreachable!(),
+            },
+            _ => None,
+        }
+    }
+}
+
+impl<F: Future> Future for MaybeDone<F> {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // note(unsafe): `Future`里面的future不会被移动，完成之后直接覆盖
+        let this = unsafe { self.get_unchecked_mut() };
+        let output = match this {
+            MaybeDone::Future(future) => match unsafe { Pin::new_unchecked(future) }.poll(cx) {
+                Poll::Ready(output) => output,
+                Poll::Pending => return Poll::Pending,
+            },
+            MaybeDone::Done(_) => return Poll::Ready(()),
+            MaybeDone::Gone => panic!("poll MaybeDone after taking output"),
+        };
+        *this = MaybeDone::Done(output);
+        Poll::Ready(())
+    }
+}
+
+/// 同时等待多个future，全部完成之后按顺序返回它们的结果
+///
+/// 只能在异步函数里使用。每次被唤醒都轮询所有还没有完成的future
+///
+/// # Example:
+///
+/// ```
+/// let (a, b) = join!(async { 1 }, async { "two" });
+/// assert_eq!((a, b), (1, "two"));
+/// ```
+#[macro_export]
+macro_rules! join {
+    // 每个分支前面放上它在元组中的位置之前的`_`，用来从元组里解构出这个分支
+    (@ { ( $($count:tt)* ) $( ( $($skip:tt)* ) $e:expr, )* }) => {{
+        let mut futures = ( $( $crate::future::MaybeDone::new($e), )* );
+        $crate::future::poll_fn(move |cx| {
+            let mut pending = false;
+            $(
+                let ( $($skip,)* future, .. ) = &mut futures;
+                // note(unsafe): `futures`在`poll_fn`里随它一起固定
+                let future = unsafe { core::pin::Pin::new_unchecked(future) };
+                if core::future::Future::poll(future, cx).is_pending() {
+                    pending = true;
+                }
+            )*
+            if pending {
+                return core::task::Poll::Pending;
+            }
+            core::task::Poll::Ready(( $({
+                let ( $($skip,)* future, .. ) = &mut futures;
+                let future = unsafe { core::pin::Pin::new_unchecked(future) };
+                future.take_output().expect("join! output taken twice")
+            }, )* ))
+        }).await
+    }};
+    (@ { ( $($s:tt)* ) $($t:tt)* } $e:expr, $($r:tt)* ) => {
+        $crate::join!(@{ ($($s)* _) $($t)* ($($s)*) $e, } $($r)*)
+    };
+    ( $($e:expr),+ $(,)? ) => {
+        $crate::join!(@{ () } $($e,)*)
+    };
+}
+
+/// 等待多个future中最先完成的一个，运行它对应的分支，其余的future被丢弃
+///
+/// 每个分支写成`模式 = future => 表达式`，所有分支的表达式类型相同。
+/// 各个分支按顺序轮询，同时完成时排在前面的分支优先。
+/// 分支的表达式在轮询的时候求值，里面不能使用`.await`，需要时把结果带出`select!`再等待。
+/// 不想丢弃的future可以传入它的可变引用
+///
+/// # Example:
+///
+/// ```
+/// let ans = select! {
+///     n = async { 1 } => n,
+///     _ = sleep_ms(100) => 0,
+/// };
+/// ```
+#[macro_export]
+macro_rules! select {
+    (@ { ( $($count:tt)* ) $( ( $($skip:tt)* ) $p:pat = $e:expr => $h:expr, )* }) => {{
+        let mut futures = ( $( $e, )* );
+        $crate::future::poll_fn(move |cx| {
+            $(
+                let ( $($skip,)* future, .. ) = &mut futures;
+                // note(unsafe): `futures`在`poll_fn`里随它一起固定
+                let future = unsafe { core::pin::Pin::new_unchecked(future) };
+                if let core::task::Poll::Ready(output) = core::future::Future::poll(future, cx) {
+                    let $p = output;
+                    return core::task::Poll::Ready($h);
+                }
+            )*
+            core::task::Poll::Pending
+        }).await
+    }};
+    (@ { ( $($s:tt)* ) $($t:tt)* } $p:pat = $e:expr => $h:expr, $($r:tt)* ) => {
+        $crate::select!(@{ ($($s)* _) $($t)* ($($s)*) $p = $e => $h, } $($r)*)
+    };
+    ( $( $p:pat = $e:expr => $h:expr ),+ $(,)? ) => {
+        $crate::select!(@{ () } $( $p = $e => $h, )*)
+    };
+}
+
+/// 同时等待迭代器中的所有future，全部完成之后按顺序返回它们的结果
+///
+/// future的数量不固定时使用，数量固定时用[`join!`](crate::join)
+pub fn join_all<I>(iter: I) -> JoinAll<I::Item>
+where
+    I: IntoIterator,
+    I::Item: Future,
+{
+    let futures: Vec<_> = iter.into_iter().map(MaybeDone::new).collect();
+    JoinAll {
+        futures: futures.into_boxed_slice().into(),
+    }
+}
+
+/// [`join_all`]返回的future
+pub struct JoinAll<F: Future> {
+    futures: Pin<Box<[MaybeDone<F>]>>,
+}
+
+impl<F: Future> Future for JoinAll<F> {
+    type Output = Vec<F::Output>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut pending = false;
+        for future in iter_pin_mut(self.futures.as_mut()) {
+            if future.poll(cx).is_pending() {
+                pending = true;
+            }
+        }
+        if pending {
+            return Poll::Pending;
+        }
+        let outputs = iter_pin_mut(self.futures.as_mut())
+            .map(|future| future.take_output().expect("join_all output taken twice"))
+            .collect();
+        Poll::Ready(outputs)
+    }
+}
+
+/// 逐个得到固定切片中元素的固定引用
+fn iter_pin_mut<T>(slice: Pin<&mut [T]>) -> impl Iterator<Item = Pin<&mut T>> {
+    // note(unsafe): 切片的元素不会被移动
+    unsafe { slice.get_unchecked_mut() }
+        .iter_mut()
+        .map(|item| unsafe { Pin::new_unchecked(item) })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{join_all, FuturesUnordered};
+    use crate::stream::Stream;
+    use alloc::{sync::Arc, vec, vec::Vec};
+    use core::{
+        future::Future,
+        pin::Pin,
+        sync::atomic::{AtomicUsize, Ordering},
+        task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    };
+    use spin::Mutex;
+
+    /// 唤醒时把`count`加一的唤醒器
+    pub(crate) fn counting_waker(count: &'static AtomicUsize) -> Waker {
+        fn clone(data: *const ()) -> RawWaker {
+            RawWaker::new(data, &VTABLE)
+        }
+        fn wake(data: *const ()) {
+            unsafe { &*(data as *const AtomicUsize) }.fetch_add(1, Ordering::Relaxed);
+        }
+        fn drop(_data: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+        unsafe { Waker::from_raw(RawWaker::new(count as *const _ as *const (), &VTABLE)) }
+    }
+
+    /// 记录被轮询的future的编号
+    pub(crate) type PollLog = Arc<Mutex<Vec<usize>>>;
+
+    /// 手动打开的门，打开之前等待它的future一直返回`Pending`
+    #[derive(Clone, Default)]
+    pub(crate) struct Gate(Arc<Mutex<(bool, Option<Waker>)>>);
+
+    impl Gate {
+        /// 打开门，唤醒最后一次轮询时登记的唤醒器
+        pub(crate) fn open(&self) {
+            let waker = {
+                let mut state = self.0.lock();
+                state.0 = true;
+                state.1.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+
+        /// 等待门打开的future，每次被轮询时把`id`记到`log`里，完成时得到`output`
+        pub(crate) fn wait<T: Unpin>(&self, id: usize, log: &PollLog, output: T) -> GateWait<T> {
+            GateWait {
+                gate: self.clone(),
+                id,
+                log: log.clone(),
+                output: Some(output),
+            }
+        }
+    }
+
+    /// [`Gate::wait`]返回的future
+    pub(crate) struct GateWait<T> {
+        gate: Gate,
+        id: usize,
+        log: PollLog,
+        output: Option<T>,
+    }
+
+    impl<T: Unpin> Future for GateWait<T> {
+        type Output = T;
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+            self.log.lock().push(self.id);
+            let mut state = self.gate.0.lock();
+            if state.0 {
+                drop(state);
+                return Poll::Ready(self.output.take().expect("poll GateWait after completion"));
+            }
+            state.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn join_all_keeps_input_order() {
+        static WAKES: AtomicUsize = AtomicUsize::new(0);
+        let log = PollLog::default();
+        let gates = [Gate::default(), Gate::default(), Gate::default()];
+        let mut all = join_all(vec![
+            gates[0].wait(0, &log, 'a'),
+            gates[1].wait(1, &log, 'b'),
+            gates[2].wait(2, &log, 'c'),
+        ]);
+        let waker = counting_waker(&WAKES);
+        let mut cx = Context::from_waker(&waker);
+        assert_eq!(Pin::new(&mut all).poll(&mut cx), Poll::Pending);
+        assert_eq!(*log.lock(), [0, 1, 2]);
+        // 每个future都登记了任务的唤醒器
+        gates[1].open();
+        assert_eq!(WAKES.load(Ordering::Relaxed), 1);
+        assert_eq!(Pin::new(&mut all).poll(&mut cx), Poll::Pending);
+        gates[2].open();
+        gates[0].open();
+        assert_eq!(WAKES.load(Ordering::Relaxed), 3);
+        // 已经完成的future不再轮询，结果按传入的顺序排列
+        log.lock().clear();
+        assert_eq!(
+            Pin::new(&mut all).poll(&mut cx),
+            Poll::Ready(vec!['a', 'b', 'c'])
+        );
+        assert_eq!(*log.lock(), [0, 2]);
+    }
+
+    #[test]
+    fn futures_unordered_polls_only_woken() {
+        static WAKES: AtomicUsize = AtomicUsize::new(0);
+        let log = PollLog::default();
+        let gates = [Gate::default(), Gate::default(), Gate::default()];
+        let mut set: FuturesUnordered<_> = (0..3).map(|i| gates[i].wait(i, &log, i)).collect();
+        let waker = counting_waker(&WAKES);
+        let mut cx = Context::from_waker(&waker);
+        assert_eq!(Pin::new(&mut set).poll_next(&mut cx), Poll::Pending);
+        assert_eq!(*log.lock(), [0, 1, 2]);
+        // 结果按完成的先后顺序产生，只有被唤醒的future会被再次轮询
+        gates[2].open();
+        gates[0].open();
+        assert_eq!(WAKES.load(Ordering::Relaxed), 2);
+        log.lock().clear();
+        assert_eq!(Pin::new(&mut set).poll_next(&mut cx), Poll::Ready(Some(2)));
+        assert_eq!(Pin::new(&mut set).poll_next(&mut cx), Poll::Ready(Some(0)));
+        assert_eq!(Pin::new(&mut set).poll_next(&mut cx), Poll::Pending);
+        assert_eq!(*log.lock(), [2, 0]);
+        assert_eq!(set.len(), 1);
+        gates[1].open();
+        assert_eq!(WAKES.load(Ordering::Relaxed), 3);
+        assert_eq!(Pin::new(&mut set).poll_next(&mut cx), Poll::Ready(Some(1)));
+        assert_eq!(Pin::new(&mut set).poll_next(&mut cx), Poll::Ready(None));
+        // 流结束之后还可以放入新的future
+        set.push(gates[0].wait(3, &log, 3));
+        assert_eq!(WAKES.load(Ordering::Relaxed), 4);
+        assert_eq!(Pin::new(&mut set).poll_next(&mut cx), Poll::Ready(Some(3)));
+    }
+}
@@ -2,6 +2,7 @@
 
 use super::syscall::sys_enroll_read;
 use crate::syscall::sys_enroll_write;
+use crate::task::{shared::current_task_repr, PendingIo};
 use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll};
@@ -27,12 +28,35 @@ impl Future for PollTwice {
     }
 }
 
-pub fn read_block(block_id: usize, buf: &mut [u8]) -> PollTwice {
-    let _sys_ret = sys_enroll_read(block_id, buf);
-    PollTwice::new()
+/// 块设备读写时发生的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoError {
+    /// 共享调度器放不下内核的读写任务，可以稍后重试
+    Busy,
 }
 
-pub fn write_block(block_id: usize, buf: &[u8]) -> PollTwice {
-    let _sys_ret = sys_enroll_write(block_id, buf);
-    PollTwice::new()
+/// 读一个块，读完成之后内核会唤醒当前任务
+///
+/// note: 只能在执行器运行的任务中调用
+pub async fn read_block(block_id: usize, buf: &mut [u8]) -> Result<(), IoError> {
+    let sys_ret = sys_enroll_read(block_id, buf, current_task_repr());
+    if sys_ret.code != 0 {
+        return Err(IoError::Busy);
+    }
+    let _pending = PendingIo::new();
+    PollTwice::new().await;
+    Ok(())
+}
+
+/// 写一个块，写完成之后内核会唤醒当前任务
+///
+/// note: 只能在执行器运行的任务中调用
+pub async fn write_block(block_id: usize, buf: &[u8]) -> Result<(), IoError> {
+    let sys_ret = sys_enroll_write(block_id, buf, current_task_repr());
+    if sys_ret.code != 0 {
+        return Err(IoError::Busy);
+    }
+    let _pending = PendingIo::new();
+    PollTwice::new().await;
+    Ok(())
 }
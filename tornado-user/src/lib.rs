@@ -11,12 +11,14 @@ extern crate alloc;
 
 #[macro_use]
 pub mod console;
+pub mod env;
 pub mod fs;
 pub mod future;
 pub mod io;
 pub mod net;
 pub mod option;
 pub mod path;
+pub mod process;
 pub mod result;
 pub mod rt;
 pub mod stream;
@@ -28,6 +30,7 @@ pub mod unit;
 pub mod vec;
 pub use console::{stdin, Stdin};
 
+use alloc::sync::Arc;
 use buddy_system_allocator::LockedHeap;
 use core::future::Future;
 
@@ -63,9 +66,11 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
     panic!("user alloc error: {:?}", layout)
 }
 
+/// 用户程序的入口，内核从ELF文件头得到它的地址
+///
+/// `.bss`段已经由内核在加载程序时填零，`args_block`是内核放在用户栈顶部的参数块，见[`env`]
 #[no_mangle]
-#[link_section = ".text.entry"]
-pub extern "C" fn _start() -> ! {
+pub extern "C" fn _start(args_block: usize) -> ! {
     let mut address_space_id: usize;
     let mut shared_payload_base: usize;
     unsafe {
@@ -75,19 +80,19 @@ pub extern "C" fn _start() -> ! {
         // 从 tp 寄存器里面取出该用户态的地址空间编号
         asm!("mv {}, tp", out(reg) address_space_id, options(nomem, nostack));
         ADDRESS_SPACE_ID = address_space_id;
-    }
-    extern "C" {
-        fn sbss();
-        fn ebss();
+        env::init(args_block);
     }
     unsafe {
-        r0::zero_bss(
-            &mut sbss as *mut _ as *mut u32,
-            &mut ebss as *mut _ as *mut u32,
-        );
         HEAP.lock()
             .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
     }
+    if let Err(err) = unsafe { task::shared::SharedPayload::load(SHARED_PAYLOAD_BASE) } {
+        println!("[User] cannot load shared payload: {:?}", err);
+        exit(-1);
+        unreachable!()
+    }
+    task::shared::refresh_hart_id();
+    task::shared::enable_preempt(0);
     let exit_code = main();
     exit(exit_code);
     unreachable!()
@@ -104,57 +109,152 @@ fn main() -> i32 {
 /// 运行一个异步的main函数，在用户的entry函数里调用
 /// 应该作为标准库的一部分，这里使用一个库函数来模拟有标准库的情况
 pub fn execute_async_main(main: impl Future<Output = i32> + Send + Sync + 'static) -> i32 {
-    let hart_id = 0; // todo!
     let shared_payload = unsafe { task::shared::SharedPayload::new(SHARED_PAYLOAD_BASE) };
-    let address_space_id = unsafe { task::shared::AddressSpaceId::from_raw(ADDRESS_SPACE_ID) };
-    static mut EXIT_CODE: i32 = 0;
-    let main_task = task::new_user(
-        async move {
-            unsafe { EXIT_CODE = main.await };
-        },
-        shared_payload.shared_scheduler,
-        shared_payload.shared_set_task_state,
-    );
-    unsafe {
-        shared_payload.add_task(hart_id, address_space_id, main_task.task_repr());
+    let (main_task, main_handle) = task::joinable(main, new_task);
+    if let Err(err) = add_new_task(
+        main_task,
+        task::shared::ANY_HART,
+        task::shared::DEFAULT_PRIORITY,
+    ) {
+        panic!("cannot spawn main task: {:?}", err)
     }
     task::shared::run_until_ready(
-        || unsafe { shared_payload.peek_task(task::shared::user_should_switch) },
+        || unsafe {
+            shared_payload.peek_task(task::shared::hart_id(), task::shared::user_should_switch)
+        },
         |task_repr| unsafe { shared_payload.delete_task(task_repr) },
         |task_repr, new_state| unsafe { shared_payload.set_task_state(task_repr, new_state) },
     );
-    unsafe { EXIT_CODE }
+    main_handle
+        .take_output()
+        .expect("main task not finished after executor exits")
 }
 
-/// 生成一个新的任务
-pub fn spawn(future: impl Future<Output = ()> + Send + Sync + 'static) {
+/// 打包一个新的用户任务，还没有交给共享调度器
+fn new_task(future: impl Future<Output = ()> + Send + Sync + 'static) -> Arc<task::UserTaskRepr> {
     let shared_payload = unsafe { task::shared::SharedPayload::new(SHARED_PAYLOAD_BASE) };
-    let asid = unsafe { task::shared::AddressSpaceId::from_raw(ADDRESS_SPACE_ID) };
-    let task = task::new_user(
+    task::new_user(
         future,
         shared_payload.shared_scheduler,
         shared_payload.shared_set_task_state,
-    );
-    unsafe {
-        shared_payload.add_task(0 /* todo */, asid, task.task_repr());
+    )
+}
+
+/// 把任务交给共享调度器，共享调度器放不下时任务被丢弃，返回错误
+fn add_new_task(
+    task: Arc<task::UserTaskRepr>,
+    hart_id: usize,
+    priority: u8,
+) -> Result<usize, task::SpawnError> {
+    let shared_payload = unsafe { task::shared::SharedPayload::new(SHARED_PAYLOAD_BASE) };
+    let asid = unsafe { task::shared::AddressSpaceId::from_raw(ADDRESS_SPACE_ID) };
+    unsafe { shared_payload.add_user_task(hart_id, asid, task, priority) }
+        .map_err(|_task| task::SpawnError::SchedulerFull)
+}
+
+/// 生成一个新的任务，返回任务的句柄
+///
+/// 等待句柄得到任务的结果，丢弃句柄不影响任务运行。
+/// 共享调度器的堆内存耗尽、放不下新任务时会panic；
+/// 需要自己处理这种情况时使用[`try_spawn`]，或者在异步任务里用[`spawn_wait`]等待
+pub fn spawn<F>(future: F) -> task::JoinHandle<F::Output>
+where
+    F: Future + Send + Sync + 'static,
+    F::Output: Send + 'static,
+{
+    match try_spawn(future) {
+        Ok(handle) => handle,
+        Err(err) => panic!("cannot spawn task: {:?}", err),
     }
 }
 
+/// 尝试生成一个新的任务，共享调度器放不下时返回错误，任务被丢弃
+pub fn try_spawn<F>(future: F) -> Result<task::JoinHandle<F::Output>, task::SpawnError>
+where
+    F: Future + Send + Sync + 'static,
+    F::Output: Send + 'static,
+{
+    let (task, handle) = task::joinable(future, new_task);
+    add_new_task(task, task::shared::ANY_HART, task::shared::DEFAULT_PRIORITY)
+        .map(|_task_repr| handle)
+}
+
+/// 生成一个新的任务，共享调度器放不下时让出当前任务，等有空间了再放进去，完成时得到任务的句柄
+///
+/// note: 只能在执行器运行的任务中等待
+pub fn spawn_wait<F>(future: F) -> task::SpawnWait<F::Output>
+where
+    F: Future + Send + Sync + 'static,
+    F::Output: Send + 'static,
+{
+    let (task, handle) = task::joinable(future, new_task);
+    task::SpawnWait::new(task, handle)
+}
+
+/// 生成一个绑定到硬件线程`hart_id`的新任务，返回任务的句柄
+///
+/// 绑定的任务只会在这个硬件线程上运行，不会被其它硬件线程偷走
+pub fn spawn_pinned<F>(future: F, hart_id: usize) -> task::JoinHandle<F::Output>
+where
+    F: Future + Send + Sync + 'static,
+    F::Output: Send + 'static,
+{
+    let (task, handle) = task::joinable(future, new_task);
+    if let Err(err) = add_new_task(task, hart_id, task::shared::DEFAULT_PRIORITY) {
+        panic!("cannot spawn task on hart {}: {:?}", hart_id, err)
+    }
+    handle
+}
+
+/// 生成一个指定优先级的新任务，返回任务的句柄
+///
+/// 数值越小优先级越高，默认优先级为4，0到3级适合留给对延迟敏感的任务
+pub fn spawn_with_priority<F>(future: F, priority: u8) -> task::JoinHandle<F::Output>
+where
+    F: Future + Send + Sync + 'static,
+    F::Output: Send + 'static,
+{
+    let (task, handle) = task::joinable(future, new_task);
+    if let Err(err) = add_new_task(task, task::shared::ANY_HART, priority) {
+        panic!("cannot spawn task: {:?}", err)
+    }
+    handle
+}
+
 /// 运行异步任务
 pub fn execute_async() {
     let shared_payload = unsafe { task::shared::SharedPayload::new(SHARED_PAYLOAD_BASE) };
     task::shared::run_until_ready(
-        || unsafe { shared_payload.peek_task(task::shared::user_should_switch) },
+        || unsafe {
+            shared_payload.peek_task(task::shared::hart_id(), task::shared::user_should_switch)
+        },
         |task_repr| unsafe { shared_payload.delete_task(task_repr) },
         |task_repr, new_state| unsafe { shared_payload.set_task_state(task_repr, new_state) },
     );
 }
 
+/// 读取共享调度器的统计信息
+///
+/// 直接调用共享调度器，不需要陷入内核；需要经过内核读取时使用[`syscall::sys_scheduler_stats`]
+pub fn scheduler_stats() -> task::shared::SchedulerStats {
+    let shared_payload = unsafe { task::shared::SharedPayload::new(SHARED_PAYLOAD_BASE) };
+    unsafe { shared_payload.stats() }
+}
+
+/// 设置内核抢占当前地址空间之前，任务可以连续运行的时钟中断次数，为0时恢复内核的默认值
+///
+/// 计算密集的程序可以调大，减少地址空间切换；对延迟敏感的程序可以调小
+pub fn set_preempt_ticks(ticks: usize) {
+    task::shared::enable_preempt(ticks);
+}
+
 // 性能测试使用
 pub fn execute_async_analysis() {
     let shared_payload = unsafe { task::shared::SharedPayload::new(SHARED_PAYLOAD_BASE) };
     task::shared::run_until_ready_analysis(
-        || unsafe { shared_payload.peek_task(task::shared::user_should_switch) },
+        || unsafe {
+            shared_payload.peek_task(task::shared::hart_id(), task::shared::user_should_switch)
+        },
         |task_repr| unsafe { shared_payload.delete_task(task_repr) },
     );
 }
@@ -0,0 +1,108 @@
+//! 进程相关
+//!
+//! 启动的程序运行在自己的地址空间里，和当前程序共用共享调度器。
+//! 等待进程退出的方式和睡眠一样：向内核登记当前任务，进程退出时内核在共享调度器里把任务设置为就绪
+use crate::env;
+use crate::syscall::{sys_spawn, sys_wait};
+use crate::task::shared::current_task_repr;
+use alloc::{format, string::String, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// 进程编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pid(usize);
+
+impl Pid {
+    /// 编号的数值
+    pub fn as_usize(&self) -> usize {
+        self.0
+    }
+}
+
+/// 启动进程时发生的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    /// 共享调度器已满，内核放不下加载程序的任务
+    SchedulerFull,
+    /// 没有空闲的地址空间
+    NoAddressSpace,
+    /// 路径、参数或者环境变量不在当前程序能访问的内存中
+    BadAddress,
+    /// 路径、参数和环境变量太长
+    ArgsTooLong,
+}
+
+/// 等待进程时发生的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// 没有这个进程，它不是当前程序的子进程，或者它的退出码已经被取走
+    NotFound,
+    /// 当前程序里已经有别的任务在等待这个进程
+    Busy,
+}
+
+/// 启动文件系统中的用户程序`path`，参数为`args`，不包括程序的名字
+///
+/// 新进程继承当前程序的环境变量。程序在内核里异步加载，这个函数返回时它可能还没有开始运行；
+/// 找不到程序或者加载失败时，进程以-1退出
+///
+/// # Example:
+///
+/// ```
+/// let pid = process::spawn("analysis1", &["100"]).expect("spawn process");
+/// let exit_code = process::wait(pid).await.expect("wait process");
+/// ```
+pub fn spawn(path: &str, args: &[&str]) -> Result<Pid, SpawnError> {
+    spawn_with_env(path, args, &[])
+}
+
+/// 和[`spawn`]相同，另外为新进程设置环境变量`envs`，同名的环境变量覆盖继承来的值
+pub fn spawn_with_env(path: &str, args: &[&str], envs: &[(&str, &str)]) -> Result<Pid, SpawnError> {
+    let mut vars: Vec<String> = env::vars()
+        .filter(|(k, _)| envs.iter().all(|(key, _)| key != k))
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect();
+    vars.extend(envs.iter().map(|(k, v)| format!("{}={}", k, v)));
+    let vars: Vec<&str> = vars.iter().map(|s| s.as_str()).collect();
+    let ans = sys_spawn(path, args, &vars);
+    match ans.code {
+        0 => Ok(Pid(ans.extra)),
+        1 => Err(SpawnError::SchedulerFull),
+        2 => Err(SpawnError::NoAddressSpace),
+        3 => Err(SpawnError::BadAddress),
+        _ => Err(SpawnError::ArgsTooLong),
+    }
+}
+
+/// 等待进程`pid`退出，得到它的退出码
+///
+/// 只能等待当前程序启动的进程，每个进程的退出码只能取走一次，同一时间只能有一个任务等待
+///
+/// note: 只能在执行器运行的任务中等待
+pub fn wait(pid: Pid) -> Wait {
+    Wait { pid }
+}
+
+/// [`wait`]返回的future
+#[derive(Debug)]
+pub struct Wait {
+    pid: Pid,
+}
+
+impl Future for Wait {
+    type Output = Result<i32, WaitError>;
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // 进程还在运行时内核登记当前任务，每次被唤醒都重新询问，同一个任务重复登记没有影响
+        let ans = sys_wait(self.pid.0, current_task_repr());
+        match ans.code {
+            0 => Poll::Ready(Ok(ans.extra as i32)),
+            1 => Poll::Pending,
+            3 => Poll::Ready(Err(WaitError::Busy)),
+            _ => Poll::Ready(Err(WaitError::NotFound)),
+        }
+    }
+}
@@ -1,3 +1,4 @@
 //! 运行时相关
 //!
-//! todo!()
+//! 目前提供睡眠和定时器，见[`time`]
+pub mod time;
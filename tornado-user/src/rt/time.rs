@@ -0,0 +1,159 @@
+//! 时间相关
+//!
+//! 时间取自内核的时钟，单位为毫秒。睡眠的任务通过系统调用把自己登记到内核的定时器队列，
+//! 时间到了之后内核在时钟中断里把任务设置为就绪，和块设备读写的唤醒方式一样
+use crate::syscall::{sys_enroll_timer, sys_get_time};
+use crate::task::shared::current_task_repr;
+use core::{
+    future::Future,
+    ops::{Add, Sub},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// 时刻，即内核时钟的毫秒读数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(usize);
+
+impl Instant {
+    /// 当前时刻
+    pub fn now() -> Instant {
+        Instant(sys_get_time().code)
+    }
+
+    /// 时钟的毫秒读数
+    pub fn as_millis(&self) -> usize {
+        self.0
+    }
+
+    /// 从`earlier`到这个时刻经过的时间，`earlier`更晚时返回零
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_millis(self.0.saturating_sub(earlier.0) as u64)
+    }
+
+    /// 从这个时刻到现在经过的时间
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+    /// 不足一毫秒的部分向上取整，超出时钟的范围时取最大值
+    fn add(self, rhs: Duration) -> Instant {
+        let ms = (rhs.as_nanos() + 999_999) / 1_000_000;
+        let ms = if ms > usize::MAX as u128 {
+            usize::MAX
+        } else {
+            ms as usize
+        };
+        Instant(self.0.saturating_add(ms))
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// 睡眠`ms`毫秒
+///
+/// note: 只能在执行器运行的任务中等待
+pub fn sleep_ms(ms: usize) -> Sleep {
+    sleep(Duration::from_millis(ms as u64))
+}
+
+/// 睡眠`duration`这么长的时间
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// 睡眠到`deadline`时刻
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        enrolled: false,
+    }
+}
+
+/// [`sleep_ms`]、[`sleep`]和[`sleep_until`]返回的future
+///
+/// 第一次轮询时向内核登记定时器，之后被提前唤醒只检查时间，不重复登记。
+/// 还没到期就被丢弃时，内核仍会在到期时唤醒一次当前任务
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Instant,
+    /// 是否已经在内核登记了定时器
+    enrolled: bool,
+}
+
+impl Sleep {
+    /// 睡眠结束的时刻
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        if !self.enrolled {
+            sys_enroll_timer(self.deadline.0, current_task_repr());
+            self.enrolled = true;
+        }
+        Poll::Pending
+    }
+}
+
+/// 每隔`period_ms`毫秒完成一次的定时器
+pub fn interval_ms(period_ms: usize) -> Interval {
+    interval(Duration::from_millis(period_ms as u64))
+}
+
+/// 每隔`period`完成一次的定时器，第一次在`period`之后完成
+pub fn interval(period: Duration) -> Interval {
+    assert!(
+        period > Duration::from_millis(0),
+        "interval period must be non-zero"
+    );
+    Interval {
+        sleep: sleep(period),
+        period,
+    }
+}
+
+/// [`interval_ms`]和[`interval`]返回的定时器
+#[derive(Debug)]
+pub struct Interval {
+    sleep: Sleep,
+    period: Duration,
+}
+
+impl Interval {
+    /// 等到下一个节拍，返回这个节拍预定的时刻
+    ///
+    /// 节拍按预定的时刻排列，不会因为任务运行得慢而累积误差；
+    /// 错过的节拍不补，下一个节拍从当前时刻重新计算
+    pub async fn tick(&mut self) -> Instant {
+        (&mut self.sleep).await;
+        let deadline = self.sleep.deadline;
+        let mut next = deadline + self.period;
+        let now = Instant::now();
+        if next <= now {
+            next = now + self.period;
+        }
+        self.sleep = sleep_until(next);
+        deadline
+    }
+
+    /// 定时器的周期
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
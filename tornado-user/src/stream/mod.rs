@@ -1 +1,249 @@
-//! todo!()
+//! 异步的值序列
+//!
+//! [`Stream`]之于[`Future`]，就像[`Iterator`]之于单个值
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// 异步产生一系列值
+pub trait Stream {
+    /// 产生的值的类型
+    type Item;
+
+    /// 尝试取出下一个值
+    ///
+    /// 还没有值时返回`Pending`并在有值时唤醒任务，流结束时返回`Ready(None)`
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>>;
+
+    /// 等待下一个值，流结束时得到None
+    fn next(&mut self) -> Next<'_, Self>
+    where
+        Self: Unpin,
+    {
+        Next { stream: self }
+    }
+
+    /// 把每个值交给`f`，产生它的返回值
+    fn map<T, F>(self, f: F) -> Map<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(Self::Item) -> T,
+    {
+        Map { stream: self, f }
+    }
+
+    /// 只产生满足`predicate`的值
+    fn filter<P>(self, predicate: P) -> Filter<Self, P>
+    where
+        Self: Sized,
+        P: FnMut(&Self::Item) -> bool,
+    {
+        Filter {
+            stream: self,
+            predicate,
+        }
+    }
+
+    /// 依次对每个值运行`f`返回的future，上一个完成之后才取下一个值，流结束时完成
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// stream.for_each(|n| async move { println!("[user] {}", n) }).await;
+    /// ```
+    fn for_each<Fut, F>(self, f: F) -> ForEach<Self, Fut, F>
+    where
+        Self: Sized,
+        F: FnMut(Self::Item) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        ForEach {
+            stream: self,
+            f,
+            future: None,
+        }
+    }
+}
+
+impl<S: Stream + Unpin + ?Sized> Stream for &mut S {
+    type Item = S::Item;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        Pin::new(&mut **self).poll_next(cx)
+    }
+}
+
+/// [`Stream::next`]返回的future
+pub struct Next<'a, S: ?Sized> {
+    stream: &'a mut S,
+}
+
+impl<S: Stream + Unpin + ?Sized> Future for Next<'_, S> {
+    type Output = Option<S::Item>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
+
+/// [`Stream::map`]返回的流
+pub struct Map<S, F> {
+    stream: S,
+    f: F,
+}
+
+impl<S, F, T> Stream for Map<S, F>
+where
+    S: Stream,
+    F: FnMut(S::Item) -> T,
+{
+    type Item = T;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        // note(unsafe): `stream`不会被移出`Map`
+        let this = unsafe { self.get_unchecked_mut() };
+        let stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+        stream.poll_next(cx).map(|item| item.map(&mut this.f))
+    }
+}
+
+/// [`Stream::filter`]返回的流
+pub struct Filter<S, P> {
+    stream: S,
+    predicate: P,
+}
+
+impl<S, P> Stream for Filter<S, P>
+where
+    S: Stream,
+    P: FnMut(&S::Item) -> bool,
+{
+    type Item = S::Item;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        // note(unsafe): `stream`不会被移出`Filter`
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+        loop {
+            match stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) if !(this.predicate)(&item) => continue,
+                other => return other,
+            }
+        }
+    }
+}
+
+/// [`Stream::for_each`]返回的future
+pub struct ForEach<S, Fut, F> {
+    stream: S,
+    f: F,
+    /// 正在运行的、处理上一个值的future
+    future: Option<Fut>,
+}
+
+impl<S, Fut, F> Future for ForEach<S, Fut, F>
+where
+    S: Stream,
+    F: FnMut(S::Item) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // note(unsafe): `stream`和`future`不会被移动，`future`完成之后才被覆盖
+        let this = unsafe { self.get_unchecked_mut() };
+        loop {
+            if let Some(future) = this.future.as_mut() {
+                match unsafe { Pin::new_unchecked(future) }.poll(cx) {
+                    Poll::Ready(()) => this.future = None,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            let stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+            match stream.poll_next(cx) {
+                Poll::Ready(Some(item)) => this.future = Some((this.f)(item)),
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stream;
+    use crate::future::tests::{counting_waker, Gate, PollLog};
+    use alloc::{vec, vec::Vec};
+    use core::{
+        future::Future,
+        pin::Pin,
+        sync::atomic::{AtomicUsize, Ordering},
+        task::{Context, Poll},
+    };
+
+    /// 依次产生向量里的值，记录被取走的个数
+    struct Iter {
+        items: vec::IntoIter<usize>,
+        taken: &'static AtomicUsize,
+    }
+
+    impl Stream for Iter {
+        type Item = usize;
+        fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<usize>> {
+            let item = self.items.next();
+            if item.is_some() {
+                self.taken.fetch_add(1, Ordering::Relaxed);
+            }
+            Poll::Ready(item)
+        }
+    }
+
+    fn iter(items: Vec<usize>, taken: &'static AtomicUsize) -> Iter {
+        Iter {
+            items: items.into_iter(),
+            taken,
+        }
+    }
+
+    #[test]
+    fn map_filter_next() {
+        static TAKEN: AtomicUsize = AtomicUsize::new(0);
+        static WAKES: AtomicUsize = AtomicUsize::new(0);
+        let mut stream = iter(vec![1, 2, 3, 4, 5, 6], &TAKEN)
+            .filter(|n| n % 2 == 0)
+            .map(|n| n * 10);
+        let waker = counting_waker(&WAKES);
+        let mut cx = Context::from_waker(&waker);
+        let mut next = |stream: &mut _| Pin::new(&mut Stream::next(stream)).poll(&mut cx);
+        assert_eq!(next(&mut stream), Poll::Ready(Some(20)));
+        assert_eq!(TAKEN.load(Ordering::Relaxed), 2);
+        assert_eq!(next(&mut stream), Poll::Ready(Some(40)));
+        assert_eq!(next(&mut stream), Poll::Ready(Some(60)));
+        assert_eq!(next(&mut stream), Poll::Ready(None));
+        assert_eq!(TAKEN.load(Ordering::Relaxed), 6);
+    }
+
+    #[test]
+    fn for_each_waits_for_each_future() {
+        static TAKEN: AtomicUsize = AtomicUsize::new(0);
+        static WAKES: AtomicUsize = AtomicUsize::new(0);
+        let log = PollLog::default();
+        let gates = [Gate::default(), Gate::default(), Gate::default()];
+        let mut for_each = iter(vec![0, 1, 2], &TAKEN).for_each(|i| gates[i].wait(i, &log, ()));
+        let waker = counting_waker(&WAKES);
+        let mut cx = Context::from_waker(&waker);
+        let mut for_each = unsafe { Pin::new_unchecked(&mut for_each) };
+        assert_eq!(for_each.as_mut().poll(&mut cx), Poll::Pending);
+        // 上一个值的future完成之前不取下一个值
+        assert_eq!(TAKEN.load(Ordering::Relaxed), 1);
+        assert_eq!(for_each.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(TAKEN.load(Ordering::Relaxed), 1);
+        assert_eq!(*log.lock(), [0, 0]);
+        gates[0].open();
+        assert_eq!(WAKES.load(Ordering::Relaxed), 1);
+        assert_eq!(for_each.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(TAKEN.load(Ordering::Relaxed), 2);
+        gates[1].open();
+        gates[2].open();
+        assert_eq!(for_each.as_mut().poll(&mut cx), Poll::Ready(()));
+        assert_eq!(TAKEN.load(Ordering::Relaxed), 3);
+        assert_eq!(*log.lock(), [0, 0, 0, 1, 1, 2]);
+    }
+}
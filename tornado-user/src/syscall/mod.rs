@@ -1,4 +1,6 @@
 #![allow(unused)]
+use crate::task::shared::SchedulerStats;
+use alloc::vec::Vec;
 
 const MODULE_PROCESS: usize = 0x114514;
 const MODULE_TEST_INTERFACE: usize = 0x233666;
@@ -6,6 +8,8 @@ const MODULE_TASK: usize = 0x7777777;
 
 const FUNC_PROCESS_EXIT: usize = 0x1919810;
 const FUNC_PROCESS_PANIC: usize = 0x11451419;
+const FUNC_PROCESS_SPAWN: usize = 0x1919811;
+const FUNC_PROCESS_WAIT: usize = 0x1919812;
 
 const FUNC_TEST_WRITE: usize = 0x666233;
 const FUNC_TEST_WRITE_ONE: usize = 0x444555;
@@ -16,8 +20,14 @@ const FUNC_TEST_READ_TIMER: usize = 0x22;
 
 const FUNC_SWITCH_TASK: usize = 0x666666;
 const FUNC_IO_TASK: usize = 0x55555;
+const FUNC_TIMER_TASK: usize = 0x56666;
+const FUNC_GET_TIME: usize = 0x56667;
+const FUNC_ENABLE_PREEMPT: usize = 0x57777;
 
-const FUNC_CHECK: usize = 0x4444;
+const FUNC_WAIT_INTERRUPT: usize = 0x4455;
+const FUNC_HART_ID: usize = 0x8888;
+const FUNC_NOTIFY_IDLE: usize = 0x8889;
+const FUNC_SCHEDULER_STATS: usize = 0x9999;
 
 const BLOCK_SIZE: usize = 512;
 pub struct SyscallResult {
@@ -181,6 +191,41 @@ pub fn sys_panic(file_name: Option<&str>, line: u32, col: u32, msg: Option<&str>
     )
 }
 
+/// 启动文件系统中的用户程序`path`，参数为`argv`，环境变量为`envs`
+///
+/// `argv`不包括程序的名字，`envs`中每一项的格式是`KEY=VALUE`。
+/// 成功时`code`为0，新进程的编号在`extra`中；共享调度器已满时`code`为1，没有空闲的地址空间时为2，
+/// 字符串不在用户能访问的内存中时为3，字符串太长时为4。程序由内核异步加载，加载失败的进程以-1退出
+pub fn sys_spawn(path: &str, argv: &[&str], envs: &[&str]) -> SyscallResult {
+    let pairs = |strs: &[&str]| -> Vec<[usize; 2]> {
+        strs.iter()
+            .map(|s| [s.as_ptr() as usize, s.len()])
+            .collect()
+    };
+    let (argv, envs) = (pairs(argv), pairs(envs));
+    syscall_6(
+        MODULE_PROCESS,
+        FUNC_PROCESS_SPAWN,
+        [
+            path.as_ptr() as usize,
+            path.len(),
+            argv.as_ptr() as usize,
+            argv.len(),
+            envs.as_ptr() as usize,
+            envs.len(),
+        ],
+    )
+}
+
+/// 取得进程`pid`的退出码
+///
+/// 进程已经退出时`code`为0，退出码在`extra`中；还在运行时`code`为1，
+/// 进程退出后内核将唤醒`wake_task_repr`表示的任务；没有这个子进程时`code`为2；
+/// 已经有别的任务在等待这个进程时`code`为3
+pub fn sys_wait(pid: usize, wake_task_repr: usize) -> SyscallResult {
+    syscall_2(MODULE_PROCESS, FUNC_PROCESS_WAIT, [pid, wake_task_repr])
+}
+
 pub fn sys_yield(next_asid: usize) -> SyscallResult {
     syscall_1(MODULE_TASK, FUNC_SWITCH_TASK, next_asid)
 }
@@ -220,30 +265,78 @@ pub fn sys_read_timer() -> SyscallResult {
 }
 
 /// 往内核注册一个块设备读任务
-pub fn sys_enroll_read(block_id: usize, buf: &mut [u8]) -> SyscallResult {
+///
+/// 读任务完成后，内核将唤醒`wake_task_repr`表示的任务
+pub fn sys_enroll_read(block_id: usize, buf: &mut [u8], wake_task_repr: usize) -> SyscallResult {
     assert!(buf.len() == BLOCK_SIZE);
     // 第一个参数 0 表示读块设备
-    syscall_3(
+    syscall_4(
         MODULE_TASK,
         FUNC_IO_TASK,
-        [0, block_id, buf.as_ptr() as usize],
+        [0, block_id, buf.as_ptr() as usize, wake_task_repr],
     )
 }
 
 /// 往内核注册一个块设备写任务
-pub fn sys_enroll_write(block_id: usize, buf: &[u8]) -> SyscallResult {
+///
+/// 写任务完成后，内核将唤醒`wake_task_repr`表示的任务
+pub fn sys_enroll_write(block_id: usize, buf: &[u8], wake_task_repr: usize) -> SyscallResult {
     assert!(buf.len() == BLOCK_SIZE);
     // 第一个参数 1 表示写块设备
-    syscall_3(
+    syscall_4(
         MODULE_TASK,
         FUNC_IO_TASK,
-        [1, block_id, buf.as_ptr() as usize],
+        [1, block_id, buf.as_ptr() as usize, wake_task_repr],
     )
 }
 
-/// 进行内核检查
+/// 往内核的定时器队列登记一个定时器
+///
+/// 时间到达`deadline_ms`之后，内核将唤醒`wake_task_repr`表示的任务
+pub fn sys_enroll_timer(deadline_ms: usize, wake_task_repr: usize) -> SyscallResult {
+    syscall_2(MODULE_TASK, FUNC_TIMER_TASK, [deadline_ms, wake_task_repr])
+}
+
+/// 读取内核启动以来的毫秒数，和[`sys_enroll_timer`]使用同一个时钟
+pub fn sys_get_time() -> SyscallResult {
+    syscall_0(MODULE_TASK, FUNC_GET_TIME)
+}
+
+/// 允许内核在时钟中断时抢占当前地址空间
+///
+/// `current_task_ptr`是执行器记录当前任务的变量地址，内核抢占时从这里读出被打断的任务。
+/// 连续运行`ticks`次时钟中断之后内核才尝试抢占，为0时使用内核的默认值
+pub fn sys_enable_preempt(current_task_ptr: usize, ticks: usize) -> SyscallResult {
+    syscall_2(MODULE_TASK, FUNC_ENABLE_PREEMPT, [current_task_ptr, ticks])
+}
+
+/// 没有可以运行的任务时让内核等待中断
 ///
-/// 用于异步运行时中轮询一定次数都没发现可执行的任务时
-pub fn sys_kernel_check() -> SyscallResult {
-    syscall_0(MODULE_TASK, FUNC_CHECK)
+/// 内核先检查一次共享调度器，有就绪的任务或者需要让出时马上返回
+pub fn sys_wait_interrupt() -> SyscallResult {
+    syscall_0(MODULE_TASK, FUNC_WAIT_INTERRUPT)
+}
+
+/// 得到当前的硬件线程编号，结果在`extra`中
+pub fn sys_hart_id() -> SyscallResult {
+    syscall_0(MODULE_TASK, FUNC_HART_ID)
+}
+
+/// 让内核叫醒正在等待中断的其它硬件线程，在共享调度器里设置任务就绪之后使用
+pub fn sys_notify_idle_harts() -> SyscallResult {
+    syscall_0(MODULE_TASK, FUNC_NOTIFY_IDLE)
+}
+
+/// 通过内核读取共享调度器的统计信息，统计信息的字节数在`extra`中
+///
+/// `stats`不在可写的用户内存中时`code`为1
+pub fn sys_scheduler_stats(stats: &mut SchedulerStats) -> SyscallResult {
+    syscall_2(
+        MODULE_TASK,
+        FUNC_SCHEDULER_STATS,
+        [
+            stats as *mut _ as usize,
+            core::mem::size_of::<SchedulerStats>(),
+        ],
+    )
 }
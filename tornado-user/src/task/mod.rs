@@ -1,13 +1,27 @@
 use alloc::sync::Arc;
 use core::future::Future;
+use core::mem::ManuallyDrop;
+use core::pin::Pin;
 use core::ptr::NonNull;
-use shared::TaskState;
+use core::sync::atomic::Ordering;
+use core::task::{Context, Poll};
+use join_handle::Abort;
+use shared::{AddressSpaceId, TaskState};
 use user_task::UserTask;
 
 pub mod channel;
 pub mod shared;
+mod task_local;
 pub mod user_task;
 
+pub(crate) use join_handle::joinable;
+pub use join_handle::JoinError;
+pub use task_local::{AccessError, LocalKey};
+pub use user_task::UserTaskId as TaskId;
+
+/// 用户任务的句柄，见[`spawn`](crate::spawn)
+pub type JoinHandle<T> = join_handle::JoinHandle<T, UserTaskRepr>;
+
 /// 共享调度器返回的结果
 #[derive(Debug)]
 pub enum TaskResult {
@@ -21,6 +35,13 @@ pub enum TaskResult {
     Finished,
 }
 
+/// 生成任务时发生的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    /// 共享调度器的堆内存耗尽，放不下新任务
+    SchedulerFull,
+}
+
 // 创建一个新的用户任务，打包它的环境
 pub fn new_user(
     future: impl Future<Output = ()> + 'static + Send + Sync,
@@ -48,10 +69,14 @@ impl UserTaskRepr {
     pub unsafe fn task_repr(self: Arc<Self>) -> usize {
         Arc::into_raw(self) as usize
     }
+    /// 唤醒这个任务
+    ///
+    /// 其它硬件线程可能正在等待中断，任务就绪之后让内核叫醒它们，它们可以把这个任务偷过去运行
     pub unsafe fn do_wake(self: &Arc<Self>) {
         let shared_scheduler = NonNull::new(self.1 as *mut ()).unwrap();
         let task_repr = Arc::as_ptr(self) as usize;
-        (self.2)(shared_scheduler, task_repr, TaskState::Ready)
+        (self.2)(shared_scheduler, task_repr, TaskState::Ready);
+        crate::syscall::sys_notify_idle_harts();
     }
     #[inline]
     pub fn task(&self) -> &UserTask {
@@ -59,8 +84,127 @@ impl UserTaskRepr {
     }
 }
 
+impl Abort for UserTaskRepr {
+    /// 一个地址空间同一时刻只有一个执行器在运行，被取消的任务不在轮询中，可以直接从共享调度器删除；
+    /// 任务取消自己时唤醒它，等这次轮询返回之后再完成，由执行器删除。
+    /// 内核还在读写future里的缓冲区时不删除，等读写完成、任务被唤醒之后再完成
+    fn abort(self: Arc<Self>) {
+        if self.task().pending_io.load(Ordering::Acquire) != 0 {
+            return;
+        }
+        let task_repr = Arc::as_ptr(&self) as usize;
+        let shared_payload = unsafe { shared::SharedPayload::new(crate::SHARED_PAYLOAD_BASE) };
+        if task_repr == shared::current_task_repr() {
+            unsafe { self.do_wake() };
+        } else if unsafe { shared_payload.delete_task(task_repr) } {
+            // 共享调度器持有的引用随任务一起删除
+            drop(unsafe { Arc::from_raw(task_repr as *const UserTaskRepr) });
+        }
+    }
+}
+
 impl woke::Woke for UserTaskRepr {
     fn wake_by_ref(task: &Arc<Self>) {
         unsafe { task.do_wake() }
     }
 }
+
+/// 正在运行的任务的编号和它所在的地址空间
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurrentTask {
+    id: TaskId,
+    asid: AddressSpaceId,
+}
+
+impl CurrentTask {
+    /// 任务的编号，在这个地址空间里唯一
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// 任务所在的地址空间
+    pub fn asid(&self) -> AddressSpaceId {
+        self.asid
+    }
+}
+
+/// 得到正在运行的任务的信息
+///
+/// 不在任务中运行时panic，比如在执行器开始之前
+pub fn current() -> CurrentTask {
+    try_current().expect("task::current called outside of a task")
+}
+
+/// 得到正在运行的任务的信息，不在任务中运行时返回None
+pub fn try_current() -> Option<CurrentTask> {
+    current_task().map(|task| CurrentTask {
+        id: task.task().id,
+        asid: unsafe { AddressSpaceId::from_raw(crate::ADDRESS_SPACE_ID) },
+    })
+}
+
+/// 得到执行器正在轮询的任务
+fn current_task() -> Option<Arc<UserTaskRepr>> {
+    let task_repr = shared::current_task_repr();
+    if task_repr == 0 {
+        return None;
+    }
+    // note(unsafe): 轮询期间执行器持有任务的引用，这里只是再增加一个
+    let task = ManuallyDrop::new(unsafe { Arc::from_raw(task_repr as *const UserTaskRepr) });
+    Some(Arc::clone(&task))
+}
+
+/// 内核正在读写当前任务的缓冲区，存在期间取消当前任务不会丢弃它的future
+///
+/// 在登记读写之后创建，读写完成、任务被唤醒之后丢弃
+pub(crate) struct PendingIo(Arc<UserTaskRepr>);
+
+impl PendingIo {
+    /// note: 只能在执行器运行的任务中调用
+    pub(crate) fn new() -> Self {
+        let task = current_task().expect("block io outside of a task");
+        task.task().pending_io.fetch_add(1, Ordering::AcqRel);
+        Self(task)
+    }
+}
+
+impl Drop for PendingIo {
+    fn drop(&mut self) {
+        self.0.task().pending_io.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// 等待共享调度器接收新任务，完成时得到任务的句柄
+///
+/// 每次被轮询时尝试把任务放进共享调度器；放不下就唤醒自己并返回`Pending`，
+/// 让执行器先运行其它任务，等它们结束、释放出空间后再重试
+pub struct SpawnWait<T> {
+    task: Option<(Arc<UserTaskRepr>, JoinHandle<T>)>,
+}
+
+impl<T> SpawnWait<T> {
+    pub(crate) fn new(task: Arc<UserTaskRepr>, handle: JoinHandle<T>) -> Self {
+        Self {
+            task: Some((task, handle)),
+        }
+    }
+}
+
+impl<T> Future for SpawnWait<T> {
+    type Output = JoinHandle<T>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let (task, handle) = self.task.take().expect("poll SpawnWait after completion");
+        let shared_payload = unsafe { shared::SharedPayload::new(crate::SHARED_PAYLOAD_BASE) };
+        let asid = unsafe { shared::AddressSpaceId::from_raw(crate::ADDRESS_SPACE_ID) };
+        match unsafe {
+            shared_payload.add_user_task(shared::ANY_HART, asid, task, shared::DEFAULT_PRIORITY)
+        } {
+            Ok(_task_repr) => Poll::Ready(handle),
+            Err(task) => {
+                self.task = Some((task, handle));
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
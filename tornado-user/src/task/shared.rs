@@ -1,5 +1,7 @@
 use crate::do_yield;
-use crate::sys_kernel_check;
+use crate::sys_enable_preempt;
+use crate::sys_hart_id;
+use crate::sys_wait_interrupt;
 use crate::task::UserTaskRepr;
 use crate::ADDRESS_SPACE_ID;
 //！ 尝试在用户态给共享调度器添加任务
@@ -8,8 +10,11 @@ use alloc::sync::Arc;
 use core::ptr::NonNull;
 use core::{
     mem,
+    sync::atomic::{AtomicUsize, Ordering},
     task::{Context, Poll},
 };
+pub use shared_abi::{ANY_HART, DEFAULT_PRIORITY, STATS_MAX_ASIDS};
+use shared_abi::{SHARED_ABI_VERSION, SHARED_PAYLOAD_MAGIC};
 use woke::waker_ref;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -23,6 +28,41 @@ impl AddressSpaceId {
     }
 }
 
+/// 执行器当前正在运行的任务的表示，没有任务运行时为0
+static CURRENT_TASK_REPR: AtomicUsize = AtomicUsize::new(0);
+
+/// 得到执行器当前正在运行的任务的表示
+///
+/// 用于需要内核在事件完成后唤醒当前任务的系统调用
+pub(crate) fn current_task_repr() -> usize {
+    CURRENT_TASK_REPR.load(Ordering::Relaxed)
+}
+
+/// 允许内核在时钟中断时抢占正在运行的任务
+///
+/// 被抢占的任务在共享调度器里重新设置为就绪，轮到它的时候切换回这个地址空间继续运行。
+/// 连续运行`ticks`次时钟中断之后才会被抢占，为0时使用内核的默认值
+pub(crate) fn enable_preempt(ticks: usize) {
+    sys_enable_preempt(&CURRENT_TASK_REPR as *const _ as usize, ticks);
+}
+
+/// 执行器所在的硬件线程编号
+static HART_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// 得到执行器所在的硬件线程编号
+pub(crate) fn hart_id() -> usize {
+    HART_ID.load(Ordering::Relaxed)
+}
+
+/// 向内核询问当前的硬件线程编号
+///
+/// 时钟中断抢占、让出和等待中断之后，地址空间都可能换到别的硬件线程上运行，
+/// 所以保存的编号只用来选择先看哪个就绪队列。编号过期时只是先看了别的硬件线程的队列，
+/// 任务仍然能通过偷任务找到，不影响正确性。执行器在启动、让出和等待中断之后重新询问
+pub(crate) fn refresh_hart_id() {
+    HART_ID.store(sys_hart_id().extra, Ordering::Relaxed);
+}
+
 pub extern "C" fn user_should_switch(asid: AddressSpaceId) -> bool {
     asid.0 != unsafe { ADDRESS_SPACE_ID as u16 }
 }
@@ -32,23 +72,21 @@ pub fn run_until_ready(
     delete_task: impl Fn(usize) -> bool,
     set_task_state: impl Fn(usize, TaskState),
 ) {
-    let mut threshold = 0;
     loop {
-        if threshold > 50 {
-            sys_kernel_check();
-            threshold = 0;
-        }
         let task = peek_task();
         // println!(">>> user executor: next task = {:x?}", task);
         match task {
             TaskResult::Task(task_repr) => {
                 // 在相同的地址空间里面
+                // 先设置为睡眠状态，这样任务在运行中唤醒自己时，不会被执行器覆盖掉
+                set_task_state(task_repr, TaskState::Sleeping);
                 let task: Arc<UserTaskRepr> = unsafe { Arc::from_raw(task_repr as *mut _) };
                 let waker = waker_ref(&task);
                 let mut context = Context::from_waker(&*waker);
+                CURRENT_TASK_REPR.store(task_repr, Ordering::Relaxed);
                 let ret = task.task().future.lock().as_mut().poll(&mut context);
+                CURRENT_TASK_REPR.store(0, Ordering::Relaxed);
                 if let Poll::Pending = ret {
-                    set_task_state(task_repr, TaskState::Sleeping);
                     mem::forget(task); // 不要释放task的内存，它将继续保存在内存中被使用
                 } else {
                     delete_task(task_repr);
@@ -58,8 +96,13 @@ pub fn run_until_ready(
                 // // 不释放这个任务的内存，执行切换地址空间的系统调用
                 // mem::forget(task);
                 do_yield(next_asid);
+                refresh_hart_id();
+            }
+            TaskResult::NoWakeTask => {
+                // 用户态不能执行`wfi`，让内核等待中断，醒来之后重新查找任务
+                sys_wait_interrupt();
+                refresh_hart_id();
             }
-            TaskResult::NoWakeTask => threshold += 1,
             TaskResult::Finished => {
                 break;
             }
@@ -85,6 +128,7 @@ pub fn run_until_ready_analysis(
                 // // 不释放这个任务的内存，执行切换地址空间的系统调用
                 // mem::forget(task);
                 do_yield(next_asid);
+                refresh_hart_id();
             }
             TaskResult::NoWakeTask => unreachable!(),
             TaskResult::Finished => {
@@ -102,46 +146,126 @@ pub enum TaskState {
     Sleeping = 1,
 }
 
+/// 共享调度器的统计信息，布局和共享调度器中的定义一致
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct SchedulerStats {
+    /// 就绪的任务数
+    pub ready: usize,
+    /// 睡眠的任务数
+    pub sleeping: usize,
+    /// `asids`中有效的项数
+    pub asid_count: usize,
+    /// 各个地址空间的任务数，按地址空间编号排列
+    pub asids: [AsidStats; STATS_MAX_ASIDS],
+    /// 查找下一个任务的次数
+    pub peeks: usize,
+    /// 返回`NoWakeTask`的次数
+    pub no_wake_task: usize,
+    /// 返回`ShouldYield`的次数
+    pub should_yield: usize,
+    /// 没有抢到共享调度器的锁、自旋等待的次数
+    pub lock_spins: usize,
+    /// 单个就绪队列中条目数量的峰值
+    pub peak_queue_len: usize,
+    /// 按地址空间分时间片时，地址空间切换的次数
+    pub asid_switches: usize,
+}
+
+/// 一个地址空间的任务数
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct AsidStats {
+    pub address_space_id: usize,
+    pub ready: usize,
+    pub sleeping: usize,
+}
+
 /// 共享载荷
 #[repr(C)]
 pub struct SharedPayload {
     pub(crate) shared_scheduler: NonNull<()>,
-    shared_add_task: unsafe extern "C" fn(NonNull<()>, usize, AddressSpaceId, usize) -> bool,
-    shared_peek_task:
-        unsafe extern "C" fn(NonNull<()>, extern "C" fn(AddressSpaceId) -> bool) -> TaskResult,
+    shared_add_task: unsafe extern "C" fn(NonNull<()>, usize, AddressSpaceId, usize, u8) -> bool,
+    shared_peek_task: unsafe extern "C" fn(
+        NonNull<()>,
+        usize,
+        extern "C" fn(AddressSpaceId) -> bool,
+    ) -> TaskResult,
     shared_delete_task: unsafe extern "C" fn(NonNull<()>, usize) -> bool,
     pub(crate) shared_set_task_state: unsafe extern "C" fn(NonNull<()>, usize, TaskState),
+    shared_set_task_priority: unsafe extern "C" fn(NonNull<()>, usize, u8),
+    shared_scheduler_stats: unsafe extern "C" fn(NonNull<()>, *mut SchedulerStats),
 }
 
-type SharedPayloadAsUsize = [usize; 7]; // 编译时基地址，（已清空）初始化函数，共享调度器地址，添加函数，弹出函数
-type SharedPayloadRaw = (
-    usize, // 编译时基地址，转换后类型占位，不使用
-    usize, // 初始化函数已清空，不适用
-    NonNull<()>,
-    unsafe extern "C" fn(NonNull<()>, usize, AddressSpaceId, usize) -> bool, // 添加任务
-    unsafe extern "C" fn(NonNull<()>, extern "C" fn(AddressSpaceId) -> bool) -> TaskResult, // 弹出任务
-    unsafe extern "C" fn(NonNull<()>, usize) -> bool, // 删除任务
-    unsafe extern "C" fn(NonNull<()>, usize, TaskState), // 改变任务的状态
-);
+/// 共享调度器中的虚函数表，布局和共享调度器中的`SharedRawTable`一致
+///
+/// 地址都是共享调度器编译时的地址，加载时需要加上偏移量
+#[repr(C)]
+struct SharedPayloadRaw {
+    magic: usize,
+    version: usize,
+    size: usize,
+    compiled_base: usize,
+    init: usize, // 初始化函数只能由内核运行，用户不使用
+    shared_scheduler: usize,
+    add_task: usize,
+    peek_task: usize,
+    delete_task: usize,
+    set_task_state: usize,
+    set_task_priority: usize,
+    scheduler_stats: usize,
+    remove_address_space: usize, // 只有内核在地址空间退出时使用
+    has_ready_task: usize,       // 只有内核在时钟中断抢占时使用
+    requeue_task: usize,         // 只有内核执行器切换地址空间失败时使用
+}
+
+/// 加载共享调度器时发生的错误
+#[derive(Debug)]
+pub enum PayloadError {
+    /// 基地址上的魔数不对
+    BadMagic(usize),
+    /// 共享调度器的版本号和用户运行时不一致
+    VersionMismatch { expected: usize, found: usize },
+    /// 虚函数表的大小和用户运行时不一致
+    SizeMismatch { expected: usize, found: usize },
+}
 
 impl SharedPayload {
-    pub unsafe fn new(base: usize) -> Self {
-        let mut payload_usize = *(base as *const SharedPayloadAsUsize);
-        let compiled_offset = payload_usize[0];
-        for (i, idx) in payload_usize.iter_mut().enumerate() {
-            if i == 0 || i == 1 {
-                continue;
-            }
-            *idx = idx.wrapping_sub(compiled_offset).wrapping_add(base);
+    /// 根据基地址加载共享调度器，虚函数表的魔数、版本号或大小不一致时返回错误
+    pub unsafe fn load(base: usize) -> Result<Self, PayloadError> {
+        let raw = &*(base as *const SharedPayloadRaw);
+        if raw.magic != SHARED_PAYLOAD_MAGIC {
+            return Err(PayloadError::BadMagic(raw.magic));
+        }
+        if raw.version != SHARED_ABI_VERSION {
+            return Err(PayloadError::VersionMismatch {
+                expected: SHARED_ABI_VERSION,
+                found: raw.version,
+            });
         }
-        let raw_table: SharedPayloadRaw = mem::transmute(payload_usize);
-        Self {
-            shared_scheduler: raw_table.2,
-            shared_add_task: raw_table.3,
-            shared_peek_task: raw_table.4,
-            shared_delete_task: raw_table.5,
-            shared_set_task_state: raw_table.6,
+        if raw.size != mem::size_of::<SharedPayloadRaw>() {
+            return Err(PayloadError::SizeMismatch {
+                expected: mem::size_of::<SharedPayloadRaw>(),
+                found: raw.size,
+            });
         }
+        let relocate = |addr: usize| addr.wrapping_sub(raw.compiled_base).wrapping_add(base);
+        Ok(Self {
+            shared_scheduler: NonNull::new_unchecked(relocate(raw.shared_scheduler) as *mut ()),
+            shared_add_task: mem::transmute(relocate(raw.add_task)),
+            shared_peek_task: mem::transmute(relocate(raw.peek_task)),
+            shared_delete_task: mem::transmute(relocate(raw.delete_task)),
+            shared_set_task_state: mem::transmute(relocate(raw.set_task_state)),
+            shared_set_task_priority: mem::transmute(relocate(raw.set_task_priority)),
+            shared_scheduler_stats: mem::transmute(relocate(raw.scheduler_stats)),
+        })
+    }
+
+    /// 根据基地址加载共享调度器
+    ///
+    /// 启动时已经用[`SharedPayload::load`]检查过虚函数表，这里加载失败说明内存被破坏了
+    pub unsafe fn new(base: usize) -> Self {
+        Self::load(base).expect("load shared payload")
     }
 
     pub unsafe fn add_task(
@@ -149,17 +273,43 @@ impl SharedPayload {
         hart_id: usize,
         address_space_id: AddressSpaceId,
         task_repr: usize,
+        priority: u8,
     ) -> bool {
         let f = self.shared_add_task;
-        f(self.shared_scheduler, hart_id, address_space_id, task_repr)
+        f(
+            self.shared_scheduler,
+            hart_id,
+            address_space_id,
+            task_repr,
+            priority,
+        )
+    }
+
+    /// 把用户任务交给共享调度器，共享调度器放不下时收回任务，原样返回
+    ///
+    /// 优先级和任务一起交给共享调度器，任务不会先以默认优先级排队
+    pub unsafe fn add_user_task(
+        &self,
+        hart_id: usize,
+        address_space_id: AddressSpaceId,
+        task: Arc<UserTaskRepr>,
+        priority: u8,
+    ) -> Result<usize, Arc<UserTaskRepr>> {
+        let task_repr = task.task_repr();
+        if self.add_task(hart_id, address_space_id, task_repr, priority) {
+            Ok(task_repr)
+        } else {
+            Err(Arc::from_raw(task_repr as *const UserTaskRepr))
+        }
     }
 
     pub unsafe fn peek_task(
         &self,
+        hart_id: usize,
         should_yield: extern "C" fn(AddressSpaceId) -> bool,
     ) -> TaskResult {
         let f = self.shared_peek_task;
-        f(self.shared_scheduler, should_yield)
+        f(self.shared_scheduler, hart_id, should_yield)
     }
 
     pub unsafe fn delete_task(&self, task_repr: usize) -> bool {
@@ -171,4 +321,19 @@ impl SharedPayload {
         let f = self.shared_set_task_state;
         f(self.shared_scheduler, task_repr, new_state)
     }
+
+    /// 修改已经在共享调度器中的任务的优先级，数值越小优先级越高
+    ///
+    /// 就绪的任务会移到新优先级队列的尾部。新任务应当在添加时直接指定优先级，见[`SharedPayload::add_user_task`]
+    pub unsafe fn set_task_priority(&self, task_repr: usize, priority: u8) {
+        let f = self.shared_set_task_priority;
+        f(self.shared_scheduler, task_repr, priority)
+    }
+
+    pub unsafe fn stats(&self) -> SchedulerStats {
+        let f = self.shared_scheduler_stats;
+        let mut stats = SchedulerStats::default();
+        f(self.shared_scheduler, &mut stats);
+        stats
+    }
 }
@@ -0,0 +1,92 @@
+//! 任务局部存储
+//!
+//! 每个任务在[`UserTask`](super::user_task::UserTask)里保存自己的一组值，
+//! 用[`LocalKey`]的地址区分不同的键。值在任务第一次访问时初始化，随任务一起释放
+use super::current_task;
+use alloc::sync::Arc;
+use core::any::Any;
+
+/// 任务局部变量的键，由[`task_local!`](crate::task_local)声明
+pub struct LocalKey<T: 'static> {
+    #[doc(hidden)]
+    pub init: fn() -> T,
+}
+
+/// 在任务之外访问任务局部变量时发生的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessError;
+
+impl<T: Send + Sync + 'static> LocalKey<T> {
+    /// 用当前任务中的值调用`f`，当前任务还没有访问过这个变量时先初始化它
+    ///
+    /// 不在任务中运行时panic
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        self.try_with(f)
+            .expect("cannot access a task-local value outside of a task")
+    }
+
+    /// 和[`with`](Self::with)相同，不在任务中运行时返回错误
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        let task = current_task().ok_or(AccessError)?;
+        let key = self as *const _ as usize;
+        let value = task.0.locals.lock().get(&key).cloned();
+        let value = match value {
+            Some(value) => value,
+            None => {
+                // 初始化的时候不持有锁，初始化函数可以访问别的任务局部变量
+                let value: Arc<dyn Any + Send + Sync> = Arc::new((self.init)());
+                task.0.locals.lock().insert(key, value.clone());
+                value
+            }
+        };
+        let value = value
+            .downcast::<T>()
+            .unwrap_or_else(|_| unreachable!("task-local value of wrong type"));
+        // 调用`f`时也不持有锁，`f`里可以替换这个变量，已经取出的值不受影响
+        Ok(f(&*value))
+    }
+
+    /// 替换当前任务中的值
+    ///
+    /// 不在任务中运行时panic
+    pub fn set(&'static self, value: T) {
+        let task = current_task().expect("cannot access a task-local value outside of a task");
+        let key = self as *const _ as usize;
+        task.0.locals.lock().insert(key, Arc::new(value));
+    }
+}
+
+/// 声明任务局部变量，每个任务都有自己的一份
+///
+/// 语法和`thread_local!`相同，初始化表达式在任务第一次访问变量时求值。
+/// 变量的类型需要满足`Send + Sync + 'static`
+///
+/// # Example:
+///
+/// ```
+/// task_local! {
+///     static REQUEST_ID: usize = 0;
+/// }
+///
+/// REQUEST_ID.set(42);
+/// REQUEST_ID.with(|id| println!("[user] request {}", id));
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $crate::task_local!($(#[$attr])* $vis static $name: $t = $init);
+        $crate::task_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $(#[$attr])*
+        $vis static $name: $crate::task::LocalKey<$t> = $crate::task::LocalKey {
+            init: {
+                fn __init() -> $t {
+                    $init
+                }
+                __init
+            },
+        };
+    };
+}
@@ -1,4 +1,5 @@
-use alloc::boxed::Box;
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
+use core::any::Any;
 use core::fmt;
 use core::future::Future;
 use core::pin::Pin;
@@ -13,6 +14,10 @@ pub struct UserTask {
     pub inner: Mutex<UserTaskInner>,
     /// 任务的 future
     pub future: Mutex<Pin<Box<dyn Future<Output = ()> + 'static + Send + Sync>>>, // 用UnsafeCell代替Mutex会好一点
+    /// 任务局部变量，键是变量的地址
+    pub(crate) locals: Mutex<BTreeMap<usize, Arc<dyn Any + Send + Sync>>>,
+    /// 内核还在读写缓冲区的块设备请求数，不为0时不能丢弃future
+    pub(crate) pending_io: AtomicUsize,
 }
 
 /// 任务信息的可变部分
@@ -54,6 +59,8 @@ impl UserTask {
                 finished: false,
             }),
             future: Mutex::new(Box::pin(future)),
+            locals: Mutex::new(BTreeMap::new()),
+            pending_io: AtomicUsize::new(0),
         }
     }
 }
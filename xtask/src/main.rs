@@ -9,7 +9,7 @@ const DEFAULT_TARGET: &'static str = "riscv64imac-unknown-none-elf";
 const DD: &'static str = "dd";
 const KERNEL_OFFSET: u64 = 0x2_0000;
 const SCHEDULER_OFFSET: u64 = 0x40_0000;
-const USER_APPS: [&'static str; 11] = [
+const USER_APPS: [&'static str; 14] = [
     "user_task",
     "alloc-test",
     "yield-task0",
@@ -20,9 +20,18 @@ const USER_APPS: [&'static str; 11] = [
     "analysis1",
     "analysis2",
     "analysis3",
+    "analysis4",
     "swap-speed",
+    "sleep",
+    "spawn",
 ];
 const PASSWORD: &'static str = "xxx";
+/// qemu 默认启动的硬件线程数量
+const QEMU_HARTS: u32 = 1;
+/// 内核支持的最大硬件线程数量
+const MAX_HARTS: u32 = shared_abi::MAX_HARTS as u32;
+/// 共享调度器的调度算法，和 tornado-kernel/src/async_rt/shared.rs 中 SchedPolicy::from_name 一致
+const SCHED_POLICIES: [&'static str; 5] = ["priority", "fifo", "asid-rr", "lottery", "asid-fair"];
 
 type Result<T = ()> = core::result::Result<T, XTaskError>;
 
@@ -37,6 +46,12 @@ struct Xtask<'x, S: AsRef<OsStr>> {
     objdump: S,
     objcopy: S,
     size: S,
+    /// 内核是否运行演示任务
+    demo: bool,
+    /// 共享调度器的调度算法，编译内核时选择
+    policy: Option<String>,
+    /// 按地址空间分时间片时的时间片长度
+    quantum: Option<u32>,
 }
 
 #[derive(Debug)]
@@ -53,8 +68,9 @@ enum XTaskError {
     BuildUserAppError,
     KernelObjcopyError,
     SharedSchedulerObjcopyError,
-    UserAppObjcopyError,
     QemuExecuteError,
+    InvalidHarts,
+    InvalidPolicy,
     K210ExecuteError,
     QemuDebugError,
     NoPort,
@@ -74,11 +90,18 @@ fn main() -> Result {
             (@arg platform: +required "Select execute platform")
             (@arg db: --db "Build database binary")
             (@arg release: --release "Build artifacts in release mode, with optimizations")
+            (@arg demo: --demo "Run kernel demo tasks on startup")
+            (@arg policy: --policy +takes_value "Shared scheduler policy: priority, fifo, asid-rr, lottery or asid-fair")
+            (@arg quantum: --quantum +takes_value "Time slice of the asid-fair policy, default if not given")
         )
         (@subcommand qemu =>
             (about: "Execute qemu")
             // (@arg user: +required "Select user binary to execute")
             (@arg release: --release "Build artifacts in release mode, with optimizations")
+            (@arg demo: --demo "Run kernel demo tasks on startup")
+            (@arg smp: --smp +takes_value "Number of harts, 1 by default, at most the kernel supports")
+            (@arg policy: --policy +takes_value "Shared scheduler policy: priority, fifo, asid-rr, lottery or asid-fair")
+            (@arg quantum: --quantum +takes_value "Time slice of the asid-fair policy, default if not given")
         )
         (@subcommand k210 =>
             (about: "Execute k210")
@@ -117,6 +140,10 @@ fn main() -> Result {
         if matches.is_present("release") {
             xtask.set_release();
         }
+        if matches.is_present("demo") {
+            xtask.set_demo();
+        }
+        xtask.set_policy(matches.value_of("policy"), matches.value_of("quantum"))?;
         let platform = matches.args.get("platform").unwrap();
         let platform = platform.vals[0].to_str().unwrap();
         xtask.build_kernel(platform)?;
@@ -125,23 +152,38 @@ fn main() -> Result {
         xtask.shared_scheduler_binary()?;
         if matches.is_present("db") {
             xtask.build_all_user_app_and_db()?;
-            xtask.all_user_app_binary()?;
         } else {
             xtask.build_all_user_app()?;
-            xtask.all_user_app_binary()?;
         }
     } else if let Some(matches) = matches.subcommand_matches("qemu") {
         // let app = matches.args.get("user").unwrap();
+        let harts = match matches.value_of("smp") {
+            Some(harts) => match harts.parse() {
+                Ok(harts) if harts >= 1 && harts <= MAX_HARTS => harts,
+                _ => {
+                    eprintln!(
+                        "invalid number of harts '{}', expected 1 to {}",
+                        harts, MAX_HARTS
+                    );
+                    return Err(XTaskError::InvalidHarts);
+                }
+            },
+            None => QEMU_HARTS,
+        };
         if matches.is_present("release") {
             xtask.set_release();
         }
+        if matches.is_present("demo") {
+            xtask.set_demo();
+        }
+        xtask.set_policy(matches.value_of("policy"), matches.value_of("quantum"))?;
         xtask.build_kernel("qemu")?;
         xtask.build_shared_scheduler("qemu")?;
         // xtask.build_user_app(app.vals[0].to_str().unwrap())?;
         xtask.kernel_binary()?;
         xtask.shared_scheduler_binary()?;
         // xtask.user_app_binary(app.vals[0].to_str().unwrap())?;
-        xtask.execute_qemu(1)?;
+        xtask.execute_qemu(harts)?;
     } else if let Some(_matches) = matches.subcommand_matches("k210") {
         xtask.set_release();
         xtask.build_kernel("k210")?;
@@ -187,11 +229,8 @@ fn main() -> Result {
         }
         if matches.is_present("db") {
             xtask.build_all_user_app_and_db()?;
-            xtask.all_user_app_binary()?;
-            xtask.user_app_binary_db()?;
         } else {
             xtask.build_all_user_app()?;
-            xtask.all_user_app_binary()?;
         }
         if matches.is_present("sdcard") {
             xtask.mkfs_fat_sdcard()?;
@@ -232,6 +271,9 @@ impl<'x> Xtask<'x, String> {
             objcopy,
             objdump,
             size,
+            demo: false,
+            policy: None,
+            quantum: None,
         }
     }
     #[allow(unused)]
@@ -256,6 +298,9 @@ impl<'x> Xtask<'x, String> {
             objcopy,
             objdump,
             size,
+            demo: false,
+            policy: None,
+            quantum: None,
         }
     }
     fn available_toolchain() -> Vec<String> {
@@ -334,6 +379,32 @@ impl<'x, S: AsRef<OsStr>> Xtask<'x, S> {
     fn set_release(&mut self) {
         self.mode = CompileMode::Release;
     }
+    fn set_demo(&mut self) {
+        self.demo = true;
+    }
+    /// 设置共享调度器的调度算法和时间片长度，编译内核时传给内核
+    fn set_policy(&mut self, policy: Option<&str>, quantum: Option<&str>) -> Result {
+        if let Some(policy) = policy {
+            if !SCHED_POLICIES.contains(&policy) {
+                eprintln!(
+                    "unknown scheduler policy '{}', expected one of {:?}",
+                    policy, SCHED_POLICIES
+                );
+                return Err(XTaskError::InvalidPolicy);
+            }
+            self.policy = Some(policy.to_string());
+        }
+        if let Some(quantum) = quantum {
+            match quantum.parse() {
+                Ok(quantum) => self.quantum = Some(quantum),
+                Err(_) => {
+                    eprintln!("invalid time slice '{}'", quantum);
+                    return Err(XTaskError::InvalidPolicy);
+                }
+            }
+        }
+        Ok(())
+    }
     fn target_dir(&self) -> PathBuf {
         let mut p = self.root.join("target").join(self.target);
         p = match self.mode {
@@ -347,12 +418,25 @@ impl<'x, S: AsRef<OsStr>> Xtask<'x, S> {
         let mut cargo = Command::new(&self.cargo);
         cargo.current_dir(self.root.join("tornado-kernel"));
         cargo.arg("build");
-        cargo.args(&["--features", platform.as_ref().to_str().unwrap()]);
+        let mut features = platform.as_ref().to_str().unwrap().to_string();
+        if self.demo {
+            features.push_str(",demo");
+        }
+        cargo.args(&["--features", &features]);
         if matches!(self.mode, CompileMode::Release) {
             cargo.arg("--release");
         }
         cargo.args(&["--target", self.target]);
         cargo.env("PLATFORM", platform);
+        // 没有指定时不传，内核使用默认的调度算法
+        match &self.policy {
+            Some(policy) => cargo.env("SCHED_POLICY", policy),
+            None => cargo.env_remove("SCHED_POLICY"),
+        };
+        match self.quantum {
+            Some(quantum) => cargo.env("SCHED_QUANTUM", quantum.to_string()),
+            None => cargo.env_remove("SCHED_QUANTUM"),
+        };
         if let Ok(status) = cargo.status() {
             if status.success() {
                 Ok(())
@@ -485,39 +569,6 @@ impl<'x, S: AsRef<OsStr>> Xtask<'x, S> {
             Err(XTaskError::CommandNotFound)
         }
     }
-    /// 生成用户程序二进制文件
-    fn user_app_binary<APP: AsRef<str>>(&self, app: APP) -> Result {
-        // objcopy := "rust-objcopy --binary-architecture=riscv64"
-        // @{{objcopy}} {{build-path}}/{{app}} --strip-all -O binary {{build-path}}/{{app}}.bin
-        let mut objcopy = Command::new(&self.objcopy);
-        objcopy
-            .current_dir(self.target_dir())
-            .arg(app.as_ref())
-            .args(&["--binary-architecture=riscv64", "--strip-all"])
-            .args(&["-O", "binary", format!("{}.bin", app.as_ref()).as_str()]);
-        if let Ok(status) = objcopy.status() {
-            if status.success() {
-                Ok(())
-            } else {
-                Err(XTaskError::UserAppObjcopyError)
-            }
-        } else {
-            Err(XTaskError::CommandNotFound)
-        }
-    }
-    /// 生成所有用户程序的二进制文件
-    fn all_user_app_binary(&self) -> Result {
-        for app in USER_APPS.iter() {
-            self.user_app_binary(*app)?;
-        }
-        Ok(())
-    }
-
-    /// 生成所有用户程序的二进制文件，但是没有数据库测例
-    fn user_app_binary_db(&self) -> Result {
-        self.user_app_binary("database")?;
-        Ok(())
-    }
     /// 运行 qemu
     // fn execute_qemu<APP: AsRef<str>>(&self, _app: APP, threads: u32) -> Result {
     fn execute_qemu(&self, threads: u32) -> Result {
@@ -774,9 +825,9 @@ impl<'x, S: AsRef<OsStr>> Xtask<'x, S> {
         let mut sudo = Command::new("sudo");
         sudo.args(&["-S", "mount", "fs.img", "/mnt"]);
         s(sudo)?;
+        // 用户程序直接以ELF文件的形式放进文件系统，由内核解析
         for app in USER_APPS.iter() {
             let mut sudo = Command::new("sudo");
-            let app = format!("{}.bin", *app);
             sudo.current_dir(self.target_dir())
                 .args(&["-S", "cp"])
                 .arg(app)
@@ -787,7 +838,7 @@ impl<'x, S: AsRef<OsStr>> Xtask<'x, S> {
             let mut sudo = Command::new("sudo");
             sudo.current_dir(self.target_dir())
                 .args(&["-S", "cp"])
-                .arg("database.bin")
+                .arg("database")
                 .arg("/mnt");
             s(sudo)?;
         }
@@ -816,9 +867,9 @@ impl<'x, S: AsRef<OsStr>> Xtask<'x, S> {
                 Ok(())
             }
         };
+        // 用户程序直接以ELF文件的形式放进文件系统，由内核解析
         for app in USER_APPS.iter() {
             let mut sudo = Command::new("sudo");
-            let app = format!("{}.bin", *app);
             sudo.current_dir(self.target_dir())
                 .args(&["-S", "cp"])
                 .arg(app)